@@ -0,0 +1,238 @@
+use std::{net::SocketAddr, sync::Arc, time::SystemTime};
+
+use tokio::sync::{
+    mpsc::{self, error::TrySendError},
+    Notify,
+};
+use tracing::warn;
+
+use crate::package::Message;
+
+/// net io 线程发往游戏线程的事件
+pub enum NetEvent {
+    Connected {
+        session_id: u64,
+        addr: SocketAddr,
+        connected_at: SystemTime,
+        outbound: Outbound,
+    },
+    Message {
+        session_id: u64,
+        message: Message,
+    },
+    Disconnected {
+        session_id: u64,
+    },
+}
+
+/// 入站队列满时的处理方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum InboundPolicy {
+    /// 等待队列空出位置，期间不再读取该连接的数据
+    #[default]
+    Block,
+    /// 断开发送方连接
+    Disconnect,
+}
+
+/// 出站队列中的指令
+#[derive(Debug)]
+pub enum Outgoing {
+    Message(Message),
+    /// 发送完之前的消息后断开
+    Close,
+}
+
+/// 出站队列满时的处理方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// 断开连接
+    #[default]
+    Disconnect,
+    /// 队列用掉一半后丢弃低优先级消息，把剩下的空间留给普通消息，
+    /// 普通消息仍然在队列满时断开
+    DropLow,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Priority {
+    #[default]
+    Normal,
+    /// 可以丢弃的消息，例如位置同步
+    Low,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendError {
+    /// 出站队列已满
+    Full,
+    /// 低优先级消息被丢弃
+    Dropped,
+    /// 连接已关闭
+    Closed,
+}
+
+/// 游戏线程发往某个连接的出站通道
+#[derive(Debug, Clone)]
+pub struct Outbound {
+    tx: mpsc::Sender<Outgoing>,
+    kick: Arc<Notify>,
+    policy: OverflowPolicy,
+}
+
+impl Outbound {
+    /// 不会阻塞游戏线程，队列满时返回 `SendError::Full`
+    pub fn send(&self, message: Message) -> Result<(), SendError> {
+        self.send_with(message, Priority::Normal)
+    }
+
+    pub fn send_with(&self, message: Message, priority: Priority) -> Result<(), SendError> {
+        if priority == Priority::Low
+            && self.policy == OverflowPolicy::DropLow
+            && self.tx.capacity() * 2 < self.tx.max_capacity()
+        {
+            return Err(SendError::Dropped);
+        }
+        self.tx
+            .try_send(Outgoing::Message(message))
+            .map_err(|err| match err {
+                TrySendError::Full(_) => SendError::Full,
+                TrySendError::Closed(_) => SendError::Closed,
+            })
+    }
+
+    /// 发送完已在队列中的消息后断开，队列满时直接断开
+    pub fn close(&self) {
+        if let Err(TrySendError::Full(_)) = self.tx.try_send(Outgoing::Close) {
+            self.kick();
+        }
+    }
+
+    /// 通知连接断开，已在队列中的消息不再发送
+    pub fn kick(&self) {
+        self.kick.notify_one();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.tx.is_closed()
+    }
+}
+
+/// 连接自己发送消息用的出站通道，例如心跳
+///
+/// 只持有弱引用，游戏线程的 `Outbound` 全部释放后 `rx.recv()` 返回 None，
+/// 此时发送返回 `SendError::Closed`。
+#[derive(Debug, Clone)]
+pub struct LocalOutbound {
+    tx: mpsc::WeakSender<Outgoing>,
+}
+
+impl LocalOutbound {
+    pub fn send(&self, message: Message) -> Result<(), SendError> {
+        let Some(tx) = self.tx.upgrade() else {
+            return Err(SendError::Closed);
+        };
+        tx.try_send(Outgoing::Message(message))
+            .map_err(|err| match err {
+                TrySendError::Full(_) => SendError::Full,
+                TrySendError::Closed(_) => SendError::Closed,
+            })
+    }
+}
+
+/// 连接一侧持有的出站接收端
+pub struct OutboundReceiver {
+    pub rx: mpsc::Receiver<Outgoing>,
+    pub kick: Arc<Notify>,
+    pub local: LocalOutbound,
+}
+
+/// net io 线程一侧的桥
+#[derive(Debug, Clone)]
+pub struct NetBridge {
+    inbound: mpsc::Sender<NetEvent>,
+    policy: InboundPolicy,
+    outbound_capacity: usize,
+    outbound_policy: OverflowPolicy,
+}
+
+impl NetBridge {
+    pub fn session_channel(&self) -> (Outbound, OutboundReceiver) {
+        let (tx, rx) = mpsc::channel(self.outbound_capacity);
+        let kick = Arc::new(Notify::new());
+        let local = LocalOutbound { tx: tx.downgrade() };
+        let outbound = Outbound {
+            tx,
+            kick: kick.clone(),
+            policy: self.outbound_policy,
+        };
+        (outbound, OutboundReceiver { rx, kick, local })
+    }
+
+    /// 投递一个事件，返回错误时调用方应断开连接
+    pub async fn post(&self, event: NetEvent) -> crate::Result<()> {
+        match self.policy {
+            InboundPolicy::Block => {
+                if self.inbound.send(event).await.is_err() {
+                    return Err("game thread closed".into());
+                }
+            }
+            InboundPolicy::Disconnect => match self.inbound.try_send(event) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => {
+                    warn!("inbound queue full");
+                    return Err("inbound queue full".into());
+                }
+                Err(TrySendError::Closed(_)) => return Err("game thread closed".into()),
+            },
+        }
+        Ok(())
+    }
+
+    /// 断开事件总是等待投递，不受 `InboundPolicy` 影响
+    pub async fn post_disconnect(&self, session_id: u64) {
+        _ = self
+            .inbound
+            .send(NetEvent::Disconnected { session_id })
+            .await;
+    }
+}
+
+/// 游戏线程一侧的桥
+#[derive(Debug)]
+pub struct GameBridge {
+    inbound: mpsc::Receiver<NetEvent>,
+}
+
+impl GameBridge {
+    /// 每帧调用，最多处理 `max` 个事件，返回处理的数量
+    pub fn drain(&mut self, max: usize, mut f: impl FnMut(NetEvent)) -> usize {
+        let mut count = 0;
+        while count < max {
+            match self.inbound.try_recv() {
+                Ok(event) => f(event),
+                Err(_) => break,
+            }
+            count += 1;
+        }
+        count
+    }
+}
+
+pub fn channel(
+    capacity: usize,
+    policy: InboundPolicy,
+    outbound_capacity: usize,
+    outbound_policy: OverflowPolicy,
+) -> (NetBridge, GameBridge) {
+    let (tx, rx) = mpsc::channel(capacity);
+    (
+        NetBridge {
+            inbound: tx,
+            policy,
+            outbound_capacity,
+            outbound_policy,
+        },
+        GameBridge { inbound: rx },
+    )
+}
@@ -0,0 +1,240 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use toml::{Table, Value};
+use tracing::warn;
+use tracing_subscriber::EnvFilter;
+
+use crate::options::{Options, MAX_TICK_RATE};
+
+/// 环境变量前缀，`RENGINE_SERVER_PORT` 对应 `server.port`，不对应配置项的变量被忽略
+pub const ENV_PREFIX: &str = "RENGINE_";
+
+/// 启动配置
+///
+/// 按默认值、TOML 文件、环境变量、命令行参数的顺序合并，后面的覆盖前面的。
+/// 时长使用 `"60s"`、`"500ms"` 这样的写法，超时设为 `"0s"` 表示不限制。
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub net: NetConfig,
+    pub log: LogConfig,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub host: String,
+    pub port: u16,
+    /// net io 线程的 tokio 工作线程数
+    pub worker_threads: usize,
+    /// 每秒帧数
+    pub tick_rate: u32,
+    #[serde(with = "humantime_serde")]
+    pub drain_timeout: Duration,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetConfig {
+    pub max_frame_len: usize,
+    pub inbound_capacity: usize,
+    pub outbound_capacity: usize,
+    pub max_events_per_frame: usize,
+    #[serde(with = "humantime_serde")]
+    pub idle_timeout: Duration,
+    #[serde(with = "humantime_serde")]
+    pub frame_timeout: Duration,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    /// `EnvFilter` 语法，例如 `"info"` 或 `"info,re_core=debug"`
+    pub level: String,
+    pub format: LogFormat,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Full,
+    Compact,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        let options = Options::default();
+        Self {
+            host: options.bind.ip().to_string(),
+            port: 7777,
+            worker_threads: options.worker_threads,
+            tick_rate: options.tick_rate,
+            drain_timeout: options.drain_timeout,
+        }
+    }
+}
+
+impl Default for NetConfig {
+    fn default() -> Self {
+        let options = Options::default();
+        Self {
+            max_frame_len: options.max_frame_len,
+            inbound_capacity: options.inbound_capacity,
+            outbound_capacity: options.outbound_capacity,
+            max_events_per_frame: options.max_events_per_frame,
+            idle_timeout: options.idle_timeout.unwrap_or_default(),
+            frame_timeout: options.frame_timeout.unwrap_or_default(),
+        }
+    }
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+            format: LogFormat::default(),
+        }
+    }
+}
+
+impl Config {
+    /// 读取配置文件和进程的环境变量，`overrides` 是命令行参数，形如 `("server.port", "7777")`
+    pub fn load(path: Option<&Path>, overrides: &[(&str, String)]) -> crate::Result<Config> {
+        let file = match path {
+            Some(path) => Some(
+                std::fs::read_to_string(path)
+                    .map_err(|err| format!("read config {}: {}", path.display(), err))?,
+            ),
+            None => None,
+        };
+        Self::from_layers(file.as_deref(), std::env::vars(), overrides)
+    }
+
+    /// 合并各层配置并校验
+    pub fn from_layers(
+        file: Option<&str>,
+        env: impl IntoIterator<Item = (String, String)>,
+        overrides: &[(&str, String)],
+    ) -> crate::Result<Config> {
+        let mut table = match file {
+            Some(file) => file
+                .parse::<Table>()
+                .map_err(|err| format!("config file: {}", err))?,
+            None => Table::new(),
+        };
+        // 环境变量可能属于其它程序，不认识的只记录警告，值不对的仍然报错
+        let known = Table::try_from(Config::default()).map_err(|err| format!("config: {}", err))?;
+        for (name, value) in env {
+            let Some(key) = name.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            let Some((section, field)) = key.split_once('_') else {
+                warn!("ignore env {}: expect {}<SECTION>_<KEY>", name, ENV_PREFIX);
+                continue;
+            };
+            let (section, field) = (section.to_lowercase(), field.to_lowercase());
+            let is_known = known
+                .get(&section)
+                .and_then(Value::as_table)
+                .is_some_and(|fields| fields.contains_key(&field));
+            if !is_known {
+                warn!("ignore env {}: unknown key {}.{}", name, section, field);
+                continue;
+            }
+            set_value(&mut table, &section, &field, &value);
+        }
+        for (key, value) in overrides {
+            let Some((section, field)) = key.split_once('.') else {
+                return Err(format!("override {}: expect <section>.<key>", key).into());
+            };
+            set_value(&mut table, section, field, value);
+        }
+
+        let config = Config::deserialize(table).map_err(|err| format!("config: {}", err))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> crate::Result<()> {
+        self.bind()?;
+        if self.server.worker_threads == 0 {
+            return Err("server.worker_threads must be at least 1".into());
+        }
+        if !(1..=MAX_TICK_RATE).contains(&self.server.tick_rate) {
+            return Err(format!(
+                "server.tick_rate must be in 1..={}, got {}",
+                MAX_TICK_RATE, self.server.tick_rate
+            )
+            .into());
+        }
+        if self.net.max_frame_len < 4 || self.net.max_frame_len > i32::MAX as usize {
+            return Err(format!(
+                "net.max_frame_len must be in 4..={}, got {}",
+                i32::MAX,
+                self.net.max_frame_len
+            )
+            .into());
+        }
+        for (name, value) in [
+            ("net.inbound_capacity", self.net.inbound_capacity),
+            ("net.outbound_capacity", self.net.outbound_capacity),
+            ("net.max_events_per_frame", self.net.max_events_per_frame),
+        ] {
+            if value == 0 {
+                return Err(format!("{} must be at least 1", name).into());
+            }
+        }
+        EnvFilter::try_new(&self.log.level)
+            .map_err(|err| format!("log.level `{}`: {}", self.log.level, err))?;
+        Ok(())
+    }
+
+    pub fn bind(&self) -> crate::Result<SocketAddr> {
+        let ip: IpAddr = self
+            .server
+            .host
+            .parse()
+            .map_err(|err| format!("server.host `{}`: {}", self.server.host, err))?;
+        Ok(SocketAddr::new(ip, self.server.port))
+    }
+
+    /// 生成运行配置，配置文件里没有的选项保持默认值
+    pub fn to_options(&self) -> crate::Result<Options> {
+        let non_zero = |timeout: Duration| (!timeout.is_zero()).then_some(timeout);
+        Ok(Options {
+            bind: self.bind()?,
+            worker_threads: self.server.worker_threads,
+            tick_rate: self.server.tick_rate,
+            drain_timeout: self.server.drain_timeout,
+            max_frame_len: self.net.max_frame_len,
+            inbound_capacity: self.net.inbound_capacity,
+            outbound_capacity: self.net.outbound_capacity,
+            max_events_per_frame: self.net.max_events_per_frame,
+            idle_timeout: non_zero(self.net.idle_timeout),
+            frame_timeout: non_zero(self.net.frame_timeout),
+            ..Options::default()
+        })
+    }
+}
+
+/// 按 TOML 解析，失败时当作字符串，例如地址和时长
+fn set_value(table: &mut Table, section: &str, field: &str, value: &str) {
+    let value = format!("v = {}", value)
+        .parse::<Table>()
+        .ok()
+        .and_then(|mut t| t.remove("v"))
+        .unwrap_or_else(|| Value::String(value.to_string()));
+    let section = table
+        .entry(section)
+        .or_insert_with(|| Value::Table(Table::new()));
+    if let Value::Table(section) = section {
+        section.insert(field.to_string(), value);
+    }
+}
@@ -1,66 +1,257 @@
-use std::net::SocketAddr;
+use std::{future, net::SocketAddr, sync::Arc, time::Duration};
 
-use tokio::{net::TcpStream, select, sync::mpsc};
-use tracing::info;
+use tokio::{
+    select,
+    sync::{mpsc, Notify},
+    time::{sleep_until, Instant},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
 
 use crate::{
-    package::{Message, Package},
+    bridge::{LocalOutbound, NetBridge, NetEvent, OutboundReceiver, Outgoing},
+    package::{Message, Package, PackageReader, PackageWriter},
     shutdown::Shutdown,
 };
 
+/// 一次 flush 最多合并的消息数
+const MAX_BATCH: usize = 64;
+/// 合并写入的缓冲区上限
+const MAX_BATCH_BYTES: usize = 64 * 1024;
+
+/// 心跳消息，由 net io 线程直接处理，不会投递到游戏线程
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Heartbeat {
+    pub ping: i32,
+    pub pong: i32,
+    /// 超过这个时间没有收到数据时服务器主动发送 ping，`None` 时只回复客户端的 ping
+    pub interval: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConnectionConfig {
+    /// 超过这个时间没有收到任何消息时断开
+    pub idle_timeout: Option<Duration>,
+    /// 一帧开始到达后必须在这个时间内收完
+    pub frame_timeout: Option<Duration>,
+    pub heartbeat: Option<Heartbeat>,
+}
+
 pub struct Connection {
-    stream: Package,
+    id: u64,
+    stream: Option<Package>,
     addr: SocketAddr,
+    config: ConnectionConfig,
+    bridge: NetBridge,
+    outbound: Option<OutboundReceiver>,
     shutdown: Shutdown,
-    _shutdown_complete: mpsc::Sender<()>,
+    shutdown_complete: mpsc::Sender<()>,
 }
 
 impl Connection {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
-        conn: TcpStream,
+        id: u64,
+        stream: Package,
         addr: SocketAddr,
+        config: ConnectionConfig,
+        bridge: NetBridge,
+        outbound: OutboundReceiver,
         shutdown: Shutdown,
         shutdown_complete: mpsc::Sender<()>,
     ) -> Self {
         Self {
-            stream: Package::new(conn),
-            addr: addr,
-            shutdown: shutdown,
-            _shutdown_complete: shutdown_complete,
+            id,
+            stream: Some(stream),
+            addr,
+            config,
+            bridge,
+            outbound: Some(outbound),
+            shutdown,
+            shutdown_complete,
         }
     }
 
+    /// 读写分别在两个任务中进行，任意一边结束时另一边也随之结束
+    ///
+    /// 服务器关闭时只停止读取，写任务继续发送队列中的消息，
+    /// 直到游戏线程关闭会话或者超时被强制中止。
     pub async fn io_loop(&mut self) -> crate::Result<()> {
-        info!("new client {}", self.addr);
+        info!("new client {}, session {}", self.addr, self.id);
+        let (Some(stream), Some(outbound)) = (self.stream.take(), self.outbound.take()) else {
+            return Ok(());
+        };
+        let (reader, writer) = stream.into_split();
+        let closed = self.shutdown.child_token();
+        let local = outbound.local.clone();
+
+        let writer = tokio::spawn(write_loop(
+            self.id,
+            writer,
+            outbound,
+            closed.clone(),
+            self.shutdown_complete.clone(),
+        ));
+        let res = self.read_loop(reader, &local, &closed).await;
+        if !self.shutdown.is_shutdown() {
+            closed.cancel();
+        }
+        select! {
+            _ = self.bridge.post_disconnect(self.id) => {}
+            _ = self.shutdown.recv() => {}
+        }
+        match writer.await {
+            Ok(Err(err)) => warn!("session {} write error: {}", self.id, err),
+            Err(err) => warn!("session {} writer panicked: {}", self.id, err),
+            Ok(Ok(())) => {}
+        }
+        res
+    }
+
+    async fn read_loop(
+        &mut self,
+        mut reader: PackageReader,
+        local: &LocalOutbound,
+        closed: &CancellationToken,
+    ) -> crate::Result<()> {
+        let config = self.config;
+        reader.set_frame_timeout(config.frame_timeout);
+        let mut last_recv = Instant::now();
+        let mut last_ping = last_recv;
         while !self.shutdown.is_shutdown() {
-            let maybe_package = select! {
-                 res = self.stream.read_message() => res?,
-                _ = self.shutdown.recv() => {
+            let idle_deadline = config.idle_timeout.map(|timeout| last_recv + timeout);
+            let ping_deadline = config
+                .heartbeat
+                .and_then(|heartbeat| heartbeat.interval)
+                .map(|interval| last_recv.max(last_ping) + interval);
+
+            select! {
+                res = reader.read_message() => {
+                    let message = match res {
+                        Ok(Some(message)) => message,
+                        Ok(None) => return Ok(()),
+                        Err(err) => {
+                            info!("session {} read error: {}", self.id, err);
+                            return Err(err);
+                        }
+                    };
+                    last_recv = Instant::now();
+                    if let Some(heartbeat) = config.heartbeat {
+                        if message.msgcode == heartbeat.ping {
+                            _ = local.send(Message::new_no_body(heartbeat.pong));
+                            continue;
+                        }
+                        if message.msgcode == heartbeat.pong {
+                            continue;
+                        }
+                    }
+                    let event = NetEvent::Message {
+                        session_id: self.id,
+                        message,
+                    };
+                    select! {
+                        res = self.bridge.post(event) => res?,
+                        _ = self.shutdown.recv() => return Ok(()),
+                    }
+                }
+                _ = sleep_opt(idle_deadline) => {
+                    info!("session {} idle timeout", self.id);
+                    return Err("idle timeout".into());
+                }
+                _ = sleep_opt(ping_deadline) => {
+                    if let Some(heartbeat) = config.heartbeat {
+                        _ = local.send(Message::new_no_body(heartbeat.ping));
+                    }
+                    last_ping = Instant::now();
+                }
+                _ = closed.cancelled() => {
                     return Ok(());
                 }
-            };
-
-            let message = match maybe_package {
-                Some(message) => message,
-                None => {
+                _ = self.shutdown.recv() => {
                     return Ok(());
                 }
-            };
-            let body = match message.body {
-                Some(body) => body,
-                None => return Ok(()),
-            };
+            }
+        }
+
+        Ok(())
+    }
+}
+
+async fn sleep_opt(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => sleep_until(deadline).await,
+        None => future::pending().await,
+    }
+}
 
-            info!(
-                "new message, code {}, size {}, body {}",
-                message.msgcode,
-                body.len(),
-                String::from_utf8(body.to_vec())?
-            );
+async fn write_loop(
+    id: u64,
+    mut writer: PackageWriter,
+    outbound: OutboundReceiver,
+    closed: CancellationToken,
+    _shutdown_complete: mpsc::Sender<()>,
+) -> crate::Result<()> {
+    let OutboundReceiver { mut rx, kick, .. } = outbound;
+    let res = write_messages(id, &mut writer, &mut rx, &kick, &closed).await;
+    closed.cancel();
+    res
+}
 
-            self.stream.write_message(Message::new(2, body)).await?;
+async fn write_messages(
+    id: u64,
+    writer: &mut PackageWriter,
+    rx: &mut mpsc::Receiver<Outgoing>,
+    kick: &Arc<Notify>,
+    closed: &CancellationToken,
+) -> crate::Result<()> {
+    loop {
+        let outgoing = select! {
+            outgoing = rx.recv() => outgoing,
+            _ = kick.notified() => {
+                info!("session {} kicked", id);
+                return Ok(());
+            }
+            _ = closed.cancelled() => {
+                return Ok(());
+            }
+        };
+
+        let mut close = false;
+        let mut next = outgoing;
+        let mut batch = 0;
+        loop {
+            match next {
+                Some(Outgoing::Message(message)) => writer.write_message(message)?,
+                Some(Outgoing::Close) | None => {
+                    close = true;
+                    break;
+                }
+            }
+            batch += 1;
+            if batch >= MAX_BATCH || writer.buffered() >= MAX_BATCH_BYTES {
+                break;
+            }
+            next = match rx.try_recv() {
+                Ok(outgoing) => Some(outgoing),
+                Err(_) => break,
+            };
         }
 
-        Ok(())
+        if close {
+            return select! {
+                res = writer.shutdown() => res,
+                _ = closed.cancelled() => Ok(()),
+            };
+        }
+        select! {
+            res = writer.flush() => res?,
+            _ = kick.notified() => {
+                info!("session {} kicked", id);
+                return Ok(());
+            }
+            _ = closed.cancelled() => {
+                return Ok(());
+            }
+        }
     }
 }
@@ -1,43 +1,347 @@
+use std::{future::Future, pin::Pin};
+
+use re_object::{
+    game_scene::GameScene,
+    replication::{Replicator, DEFAULT_MAX_PACKET_LEN},
+};
 use tokio::{
     net::TcpListener,
     sync::{broadcast, mpsc},
-    time::{sleep, Duration},
+    time::{sleep_until, timeout, Duration, Instant},
 };
-use tracing::info;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
 
 use crate::{
+    bridge::{self, GameBridge, NetEvent},
+    connection::ConnectionConfig,
+    dispatcher::{Context, Dispatcher},
+    frame::{Frame, FrameStats, OverrunPolicy, Stage, Systems},
+    options::{Options, MAX_TICK_RATE},
+    package::{Message, PackageCodec},
+    session::{Sessions, MSG_SERVER_CLOSING},
     shutdown::Shutdown,
     tcp_server::{self, Listener},
 };
 
+pub type ShutdownFuture = Pin<Box<dyn Future<Output = ()>>>;
+pub type ShutdownHook = Box<dyn FnOnce(&mut Core) -> Option<ShutdownFuture>>;
+
 pub struct Core {
+    /// 通知 net io 线程停止接受连接和读取消息
     pub notify_shutdown: broadcast::Sender<()>,
     pub shutdown_complete_rx: mpsc::Receiver<()>,
-    pub shutdown_complete_tx: mpsc::Sender<()>,
+    /// 关闭时释放，所有持有者释放后 `shutdown_complete_rx` 返回
+    pub shutdown_complete_tx: Option<mpsc::Sender<()>>,
+    /// 排空超时后强制断开所有连接
+    pub abort: CancellationToken,
+    pub drain_timeout: Duration,
     pub quit: bool,
+    pub dispatcher: Option<Dispatcher>,
+    pub sessions: Sessions,
+    pub net: Option<GameBridge>,
+    /// 内置系统更新的场景，在 `Options::setup` 中创建
+    pub scene: Option<GameScene>,
+    pub replicator: Replicator,
+    pub max_events_per_frame: usize,
+    pub tick: Duration,
+    pub overrun: OverrunPolicy,
+    pub frame: Frame,
+    pub stats: FrameStats,
+    systems: Systems,
+    pending_systems: Systems,
+    shutdown_hooks: Vec<ShutdownHook>,
+}
+
+impl Default for Core {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Core {
-    pub async fn run(&mut self, port: i32) -> crate::Result<()> {
-        let address = format!("0.0.0.0:{}", port);
-        let listener = TcpListener::bind(&address).await?;
-        info!("listen on {}", address);
+    pub fn new() -> Self {
         let (notify_shutdown, _) = broadcast::channel(1);
+        let (shutdown_complete_tx, shutdown_complete_rx) = mpsc::channel(1);
+        Self {
+            notify_shutdown,
+            shutdown_complete_rx,
+            shutdown_complete_tx: Some(shutdown_complete_tx),
+            abort: CancellationToken::new(),
+            drain_timeout: Duration::from_secs(5),
+            quit: false,
+            dispatcher: None,
+            sessions: Sessions::new(),
+            net: None,
+            scene: None,
+            replicator: Replicator::default(),
+            max_events_per_frame: 0,
+            tick: Duration::from_millis(100),
+            overrun: OverrunPolicy::default(),
+            frame: Frame::default(),
+            stats: FrameStats::default(),
+            systems: Systems::default(),
+            pending_systems: Systems::default(),
+            shutdown_hooks: Vec::new(),
+        }
+    }
+
+    /// 注册每帧执行的系统，帧内可以调用，下一帧生效
+    pub fn add_system(&mut self, stage: Stage, system: impl FnMut(&mut Core, &Frame) + 'static) {
+        self.pending_systems.add(stage, Box::new(system));
+    }
+
+    /// 注册内置系统，只由 `run` 调用一次，重复注册会让每个系统每帧执行多次
+    ///
+    /// Update 按 `Frame::time` 触发定时器，再处理延迟的属性修改和观察者，Cleanup 释放删除的对象，
+    /// Replicate 把属性变化发给客户端。没有场景时前两个不做任何事。
+    pub(crate) fn add_builtin_systems(&mut self) {
+        self.add_system(Stage::Update, |core, frame| {
+            if let Some(scene) = core.scene.as_ref() {
+                scene.update_timers(frame.time);
+                scene.flush_changes();
+                scene.flush_observers();
+            }
+        });
+        self.add_system(Stage::Cleanup, |core, _| {
+            if let Some(scene) = core.scene.as_ref() {
+                scene.factory.borrow_mut().clear_deleted();
+            }
+        });
+        self.add_system(Stage::Replicate, |core, _| {
+            core.sessions.replicate(&mut core.replicator);
+        });
+    }
+
+    /// 注册关闭时在游戏线程上执行的回调，在网络连接全部结束后按注册顺序执行
+    pub fn on_shutdown(&mut self, f: impl FnOnce(&mut Core) + 'static) {
+        self.shutdown_hooks.push(Box::new(|core| {
+            f(core);
+            None
+        }));
+    }
+
+    /// 同 `on_shutdown`，返回的 future 执行完后才执行下一个回调，例如等待最后一次存盘
+    pub fn on_shutdown_async<F>(&mut self, f: impl FnOnce(&mut Core) -> F + 'static)
+    where
+        F: Future<Output = ()> + 'static,
+    {
+        self.shutdown_hooks
+            .push(Box::new(|core| Some(Box::pin(f(core)) as ShutdownFuture)));
+    }
+
+    /// 运行直到 `signal` 完成或者调用了 `shutdown`，然后优雅关闭
+    pub async fn run(&mut self, options: &Options, signal: impl Future) -> crate::Result<()> {
+        let listener = TcpListener::bind(options.bind).await?;
+        info!("listen on {}", listener.local_addr()?);
+        let Some(shutdown_complete_tx) = self.shutdown_complete_tx.clone() else {
+            return Err("core already shut down".into());
+        };
+        let (net_bridge, game_bridge) = bridge::channel(
+            options.inbound_capacity,
+            options.inbound_policy,
+            options.outbound_capacity,
+            options.outbound_policy,
+        );
+        self.dispatcher = Some(Dispatcher::init(options.unknown_msg));
+        self.net = Some(game_bridge);
+        self.max_events_per_frame = options.max_events_per_frame;
+        self.tick = Duration::from_secs(1) / options.tick_rate.clamp(1, MAX_TICK_RATE);
+        self.overrun = options.overrun;
+        self.drain_timeout = options.drain_timeout;
+        // 同步包加上消息码不能超过最大帧长度
+        self.replicator.set_max_packet_len(
+            DEFAULT_MAX_PACKET_LEN.min(options.max_frame_len.saturating_sub(4)),
+        );
+        self.add_system(Stage::Net, |core, _| core.process_net());
+        self.add_builtin_systems();
+        if let Some(setup) = options.setup {
+            setup(self);
+        }
 
         let server = Listener {
             listener,
-            shutdown_complete_tx: self.shutdown_complete_tx.clone(),
+            bridge: net_bridge,
+            codec: PackageCodec::new(options.max_frame_len),
+            config: ConnectionConfig {
+                idle_timeout: options.idle_timeout,
+                frame_timeout: options.frame_timeout,
+                heartbeat: options.heartbeat,
+            },
+            next_session_id: 0,
+            notify_shutdown: self.notify_shutdown.clone(),
+            abort: self.abort.clone(),
+            shutdown_complete_tx,
         };
-        let shutdown = Shutdown::new(notify_shutdown.subscribe());
-        tcp_server::run_server(server, shutdown)?;
-        self.core_loop().await;
-        drop(notify_shutdown);
+        let shutdown = Shutdown::new(self.notify_shutdown.subscribe(), self.abort.clone());
+        tcp_server::run_server(server, options.worker_threads, shutdown)?;
+        tokio::select! {
+            _ = self.core_loop() => {}
+            _ = signal => {
+                info!("shutting down");
+            }
+        }
+        self.graceful_shutdown().await;
         Ok(())
     }
 
+    /// 停止接受连接，通知所有会话服务器关闭，等出站队列发送完后执行关闭回调
+    ///
+    /// 超过 `drain_timeout` 还没有结束的连接会被强制断开。
+    pub async fn graceful_shutdown(&mut self) {
+        self.quit = true;
+        _ = self.notify_shutdown.send(());
+        // 不再处理网络事件，net io 线程的投递会立即失败
+        self.net = None;
+
+        let closing = Message::new_no_body(MSG_SERVER_CLOSING);
+        for session in self.sessions.iter() {
+            if session.send(closing.clone()) {
+                session.close();
+            }
+        }
+        let ids: Vec<u64> = self.sessions.ids().collect();
+        for id in ids {
+            self.sessions.remove(id);
+        }
+
+        drop(self.shutdown_complete_tx.take());
+        if timeout(self.drain_timeout, self.shutdown_complete_rx.recv())
+            .await
+            .is_err()
+        {
+            warn!(
+                "drain timeout after {:?}, abort connections",
+                self.drain_timeout
+            );
+            self.abort.cancel();
+            if timeout(Duration::from_secs(1), self.shutdown_complete_rx.recv())
+                .await
+                .is_err()
+            {
+                warn!("net io thread did not exit in time");
+            }
+        }
+
+        for hook in std::mem::take(&mut self.shutdown_hooks) {
+            if let Some(f) = hook(self) {
+                f.await;
+            }
+        }
+        info!("shutdown complete");
+    }
+
     pub async fn core_loop(&mut self) {
+        let mut next = Instant::now();
         while !self.quit {
-            sleep(Duration::from_millis(100)).await;
+            sleep_until(next).await;
+            let behind = Instant::now().saturating_duration_since(next);
+            let due = 1 + (behind.as_nanos() / self.tick.as_nanos().max(1)) as u32;
+            let steps = match self.overrun {
+                OverrunPolicy::CatchUp { max_steps } => due.min(max_steps.max(1)),
+                OverrunPolicy::Skip => 1,
+            };
+            self.stats.behind = behind;
+            if due > steps {
+                self.stats.skipped += (due - steps) as u64;
+                warn!(
+                    "game thread behind {:?}, skip {} frames",
+                    behind,
+                    due - steps
+                );
+            }
+            for _ in 0..steps {
+                self.step();
+                if self.quit {
+                    break;
+                }
+            }
+            next += self.tick * due;
+        }
+    }
+
+    /// 执行一帧
+    pub fn step(&mut self) {
+        let start = Instant::now();
+        self.frame.index += 1;
+        self.frame.delta = self.tick;
+        self.frame.time += self.tick;
+        let frame = self.frame;
+
+        let pending = std::mem::take(&mut self.pending_systems);
+        self.systems.merge(pending);
+        let mut systems = std::mem::take(&mut self.systems);
+        systems.run(self, &frame);
+        self.systems = systems;
+
+        self.stats.record(start.elapsed(), self.tick);
+    }
+
+    /// 处理 net io 线程投递过来的事件
+    pub fn process_net(&mut self) {
+        let Some(net) = self.net.as_mut() else {
+            return;
+        };
+        let mut events = Vec::new();
+        net.drain(self.max_events_per_frame, |event| events.push(event));
+        for event in events {
+            self.on_net_event(event);
+        }
+    }
+
+    fn on_net_event(&mut self, event: NetEvent) {
+        match event {
+            NetEvent::Connected {
+                session_id,
+                addr,
+                connected_at,
+                outbound,
+            } => {
+                self.sessions.add(session_id, addr, connected_at, outbound);
+            }
+            NetEvent::Message {
+                session_id,
+                message,
+            } => {
+                let Some(dispatcher) = self.dispatcher.take() else {
+                    return;
+                };
+                let Some(session) = self.sessions.get_mut(session_id) else {
+                    self.dispatcher = Some(dispatcher);
+                    return;
+                };
+                // 处理函数可以访问整个 Core，会话状态先取出来，处理完再放回
+                let addr = session.addr;
+                let msgcode = message.msgcode;
+                let mut state = std::mem::take(&mut session.state);
+                let mut ctx = Context::new(self, session_id, addr, &mut state);
+                let res = dispatcher.dispatch(&mut ctx, message);
+                let closed = ctx.is_closed();
+                let replies = ctx.take_replies();
+                self.dispatcher.get_or_insert(dispatcher);
+                // 处理函数中可能已经移除了会话
+                let Some(session) = self.sessions.get_mut(session_id) else {
+                    return;
+                };
+                session.state = state;
+                for reply in replies {
+                    if !session.send(reply) {
+                        break;
+                    }
+                }
+                if let Err(err) = res {
+                    warn!(
+                        "session {} message {} handler error: {}",
+                        session_id, msgcode, err
+                    );
+                    session.abort();
+                } else if closed {
+                    session.close();
+                }
+            }
+            NetEvent::Disconnected { session_id } => {
+                self.sessions.remove(session_id);
+            }
         }
     }
 
@@ -0,0 +1,189 @@
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    net::SocketAddr,
+};
+
+use tracing::warn;
+
+use crate::{core::Core, package::Message};
+
+pub type HandlerFn = fn(&mut Context, Message) -> crate::Result<()>;
+
+/// 消息处理函数注册项，通过 `register_handler!` 收集
+pub struct MessageHandler {
+    pub msgcode: i32,
+    pub f: HandlerFn,
+}
+
+impl MessageHandler {
+    pub const fn register_handler(msgcode: i32, f: HandlerFn) -> Self {
+        Self { msgcode, f }
+    }
+}
+
+inventory::collect!(MessageHandler);
+
+/// 未注册的消息码如何处理
+#[derive(Debug, Clone, Copy, Default)]
+pub enum UnknownPolicy {
+    /// 断开连接
+    #[default]
+    Disconnect,
+    /// 交给兜底处理函数
+    Fallback(HandlerFn),
+}
+
+/// 会话上的自定义状态，按类型存取
+#[derive(Debug, Default)]
+pub struct SessionState {
+    values: HashMap<TypeId, Box<dyn Any>>,
+}
+
+impl SessionState {
+    pub fn get<T: Any>(&self) -> Option<&T> {
+        self.values
+            .get(&TypeId::of::<T>())
+            .and_then(|v| v.downcast_ref::<T>())
+    }
+
+    pub fn get_mut<T: Any>(&mut self) -> Option<&mut T> {
+        self.values
+            .get_mut(&TypeId::of::<T>())
+            .and_then(|v| v.downcast_mut::<T>())
+    }
+
+    pub fn insert<T: Any>(&mut self, value: T) -> Option<T> {
+        self.values
+            .insert(TypeId::of::<T>(), Box::new(value))
+            .and_then(|old| old.downcast::<T>().ok())
+            .map(|old| *old)
+    }
+
+    pub fn remove<T: Any>(&mut self) -> Option<T> {
+        self.values
+            .remove(&TypeId::of::<T>())
+            .and_then(|old| old.downcast::<T>().ok())
+            .map(|old| *old)
+    }
+}
+
+/// 处理函数的上下文，可以回复消息、访问会话状态和游戏线程上的 `Core`
+///
+/// 处理期间会话状态和 `Core::dispatcher` 被临时取出，要通过 `state`、`state_mut` 访问状态。
+pub struct Context<'a> {
+    core: &'a mut Core,
+    session_id: u64,
+    addr: SocketAddr,
+    state: &'a mut SessionState,
+    replies: Vec<Message>,
+    closed: bool,
+}
+
+impl<'a> Context<'a> {
+    pub fn new(
+        core: &'a mut Core,
+        session_id: u64,
+        addr: SocketAddr,
+        state: &'a mut SessionState,
+    ) -> Self {
+        Self {
+            core,
+            session_id,
+            addr,
+            state,
+            replies: Vec::new(),
+            closed: false,
+        }
+    }
+
+    /// 访问会话、场景等游戏线程上的状态
+    pub fn core(&mut self) -> &mut Core {
+        self.core
+    }
+
+    pub fn session_id(&self) -> u64 {
+        self.session_id
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn state(&self) -> &SessionState {
+        self.state
+    }
+
+    pub fn state_mut(&mut self) -> &mut SessionState {
+        self.state
+    }
+
+    /// 回复消息，处理函数返回后按顺序发送
+    pub fn reply(&mut self, message: Message) {
+        self.replies.push(message);
+    }
+
+    /// 发送完回复后断开连接
+    pub fn close(&mut self) {
+        self.closed = true;
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    pub fn take_replies(&mut self) -> Vec<Message> {
+        std::mem::take(&mut self.replies)
+    }
+}
+
+#[derive(Debug)]
+pub struct Dispatcher {
+    handlers: HashMap<i32, HandlerFn>,
+    unknown: UnknownPolicy,
+}
+
+impl Dispatcher {
+    pub fn init(unknown: UnknownPolicy) -> Self {
+        let mut handlers = HashMap::new();
+        for handler in inventory::iter::<MessageHandler> {
+            if handlers.insert(handler.msgcode, handler.f).is_some() {
+                panic!("msgcode {} duplicate", handler.msgcode);
+            }
+        }
+        Self { handlers, unknown }
+    }
+
+    pub fn register(&mut self, msgcode: i32, f: HandlerFn) -> bool {
+        if self.handlers.contains_key(&msgcode) {
+            return false;
+        }
+        self.handlers.insert(msgcode, f);
+        true
+    }
+
+    pub fn set_unknown_policy(&mut self, unknown: UnknownPolicy) {
+        self.unknown = unknown;
+    }
+
+    pub fn has_handler(&self, msgcode: i32) -> bool {
+        self.handlers.contains_key(&msgcode)
+    }
+
+    /// 分发一条消息，返回错误时调用方应断开连接
+    pub fn dispatch(&self, ctx: &mut Context, message: Message) -> crate::Result<()> {
+        if let Some(f) = self.handlers.get(&message.msgcode) {
+            return f(ctx, message);
+        }
+        match self.unknown {
+            UnknownPolicy::Fallback(f) => f(ctx, message),
+            UnknownPolicy::Disconnect => {
+                warn!(
+                    "unknown msgcode {} from session {}",
+                    message.msgcode, ctx.session_id
+                );
+                Err(format!("unknown msgcode {}", message.msgcode).into())
+            }
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use std::time::Duration;
+
+use crate::core::Core;
+
+/// 每帧系统的执行阶段，同一帧内按声明顺序执行
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Stage {
+    /// 处理网络事件
+    Net,
+    /// 更新对象、定时器
+    Update,
+    /// 清理本帧删除的对象，例如 `Factory::clear_deleted`
+    Cleanup,
+    /// 同步属性变化给客户端
+    Replicate,
+}
+
+/// 帧处理超时后的追帧策略
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverrunPolicy {
+    /// 连续执行落下的帧，单次最多 `max_steps` 帧，多出的丢弃
+    CatchUp { max_steps: u32 },
+    /// 丢弃落下的帧，只执行一帧
+    Skip,
+}
+
+impl Default for OverrunPolicy {
+    fn default() -> Self {
+        OverrunPolicy::CatchUp { max_steps: 5 }
+    }
+}
+
+/// 传给系统的帧信息
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Frame {
+    /// 帧序号，从 1 开始
+    pub index: u64,
+    /// 固定的帧间隔
+    pub delta: Duration,
+    /// 逻辑时间，等于 `index * delta`
+    pub time: Duration,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FrameStats {
+    /// 已执行的帧数
+    pub frames: u64,
+    /// 因超时丢弃的帧数
+    pub skipped: u64,
+    /// 执行时间超过帧间隔的帧数
+    pub overruns: u64,
+    /// 最近一帧的执行时间
+    pub last: Duration,
+    /// 平均执行时间（指数滑动平均）
+    pub avg: Duration,
+    /// 最长执行时间
+    pub max: Duration,
+    /// 最近一次唤醒时落后计划的时间
+    pub behind: Duration,
+}
+
+impl FrameStats {
+    pub fn record(&mut self, cost: Duration, delta: Duration) {
+        self.frames += 1;
+        self.last = cost;
+        self.max = self.max.max(cost);
+        self.avg = if self.frames == 1 {
+            cost
+        } else {
+            (self.avg * 7 + cost) / 8
+        };
+        if cost > delta {
+            self.overruns += 1;
+        }
+    }
+}
+
+pub type SystemFn = Box<dyn FnMut(&mut Core, &Frame)>;
+
+#[derive(Default)]
+pub struct Systems {
+    systems: Vec<(Stage, SystemFn)>,
+}
+
+impl Systems {
+    /// 同一阶段的系统按注册顺序执行
+    pub fn add(&mut self, stage: Stage, system: SystemFn) {
+        let pos = self.systems.partition_point(|(s, _)| *s <= stage);
+        self.systems.insert(pos, (stage, system));
+    }
+
+    pub fn run(&mut self, core: &mut Core, frame: &Frame) {
+        for (_, system) in self.systems.iter_mut() {
+            system(core, frame);
+        }
+    }
+
+    /// 合并执行期间新注册的系统
+    pub fn merge(&mut self, other: Systems) {
+        for (stage, system) in other.systems {
+            self.add(stage, system);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.systems.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.systems.is_empty()
+    }
+}
@@ -1,37 +1,73 @@
-mod connection;
-mod package;
-
 pub const MAX_LEN: usize = 64 * 1024;
 
+pub mod bridge;
+pub mod config;
+pub mod connection;
 pub mod core;
+pub mod dispatcher;
+pub mod frame;
 pub mod macros;
 pub mod options;
+pub mod package;
 pub mod runtime;
+pub mod session;
 pub mod shutdown;
 pub mod tcp_server;
 pub mod tokio_util;
 
+pub use inventory;
+pub use package::Message;
+
 pub type Error = Box<dyn std::error::Error + Send + Sync>;
 pub type Result<T> = std::result::Result<T, Error>;
 
 #[cfg(test)]
 mod tests {
-    use std::rc::Rc;
+    use std::{cell::RefCell, rc::Rc, time::Duration};
 
-    use re_object::{
-        game_object::GameObject, game_scene::GameScene, object::Object, registry::Registry,
-    };
+    use bytes::{BufMut, Bytes, BytesMut};
+    use re_object::{game_scene::GameScene, object::Object, registry::Registry, ObjectPtr};
     use re_ops::def_entity;
     use time::macros::format_description;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+        sync::{broadcast, mpsc},
+        task::JoinHandle,
+        time::Instant,
+    };
+    use tokio_util::{
+        codec::{Decoder, Encoder},
+        sync::CancellationToken,
+    };
     use tracing_subscriber::{fmt::time::LocalTime, EnvFilter, FmtSubscriber};
 
-    #[def_entity]
+    use crate::{
+        bridge::{
+            self, GameBridge, InboundPolicy, NetEvent, Outbound, Outgoing, OverflowPolicy,
+            Priority, SendError,
+        },
+        config::{Config, LogFormat},
+        connection::{Connection, ConnectionConfig, Heartbeat},
+        core::Core,
+        dispatcher::{Context, Dispatcher, SessionState, UnknownPolicy},
+        frame::{OverrunPolicy, Stage},
+        options::{with_tick_rate, Options, MAX_TICK_RATE},
+        package::{Package, PackageCodec},
+        register_handler,
+        session::{Sessions, MSG_KICK, MSG_REPLICATE, MSG_SERVER_CLOSING},
+        shutdown::Shutdown,
+        tokio_util::run_local,
+        Message, MAX_LEN,
+    };
+
+    #[def_entity(class = Scene)]
     struct TestScene {
         #[attr()]
         name: &'static str,
     }
 
-    #[def_entity]
+    #[def_entity(class = Role)]
     struct TestPlayer {
         hp: i32,
         #[attr(save, replicated)]
@@ -40,13 +76,13 @@ mod tests {
         age: i32,
     }
 
-    #[def_entity]
+    #[def_entity(class = Container)]
     struct TestBox {
         #[attr(save, replicated)]
         name: String,
     }
 
-    #[def_entity]
+    #[def_entity(class = Item)]
     struct TestItem {
         #[attr(save, replicated)]
         name: String,
@@ -67,9 +103,6 @@ mod tests {
         let registry = Rc::new(Registry::init());
 
         let scene = GameScene::new(TestScene::ClassName(), registry.clone()).unwrap();
-        Object::model_map(&scene.scene_object, |scene: &TestScene| {
-            println!("{:?}", scene.__go.0);
-        });
         Object::model_map_mut(&scene.scene_object, |scene: &mut TestScene| {
             scene.set_name("test");
             println!("{:?}", scene.name);
@@ -91,4 +124,600 @@ mod tests {
         drop(scene_object);
         drop(factory);
     }
+
+    struct LoginCount(u32);
+
+    fn test_login(ctx: &mut Context, message: Message) -> crate::Result<()> {
+        let count = match ctx.state_mut().get_mut::<LoginCount>() {
+            Some(count) => {
+                count.0 += 1;
+                count.0
+            }
+            None => {
+                ctx.state_mut().insert(LoginCount(1));
+                1
+            }
+        };
+        ctx.reply(Message::new(
+            message.msgcode + 1,
+            Bytes::from(count.to_string()),
+        ));
+        Ok(())
+    }
+
+    fn test_fallback(ctx: &mut Context, _message: Message) -> crate::Result<()> {
+        ctx.close();
+        Ok(())
+    }
+
+    /// 在场景中创建玩家，保存在会话状态中，回复玩家的 uid
+    fn test_enter(ctx: &mut Context, message: Message) -> crate::Result<()> {
+        let scene = ctx.core().scene.as_ref().ok_or("no scene")?;
+        let player = scene
+            .create_in_scene(TestPlayer::ClassName(), 0)
+            .ok_or("create player failed")?;
+        let uid = player.borrow().uid;
+        ctx.state_mut().insert(player);
+        ctx.reply(Message::new(
+            message.msgcode + 1,
+            Bytes::from(uid.to_string()),
+        ));
+        Ok(())
+    }
+
+    register_handler!(100, test_login);
+    register_handler!(110, test_enter);
+
+    #[test]
+    fn test_dispatch() {
+        let addr = "127.0.0.1:7777".parse().unwrap();
+        let mut core = Core::new();
+        let mut state = SessionState::default();
+        let dispatcher = Dispatcher::init(UnknownPolicy::Disconnect);
+        for expect in ["1", "2"] {
+            let mut ctx = Context::new(&mut core, 1, addr, &mut state);
+            dispatcher
+                .dispatch(&mut ctx, Message::new_no_body(100))
+                .unwrap();
+            let replies = ctx.take_replies();
+            assert_eq!(replies.len(), 1);
+            assert_eq!(replies[0].msgcode, 101);
+            assert_eq!(replies[0].body.as_deref(), Some(expect.as_bytes()));
+        }
+
+        let mut ctx = Context::new(&mut core, 1, addr, &mut state);
+        assert!(dispatcher
+            .dispatch(&mut ctx, Message::new_no_body(200))
+            .is_err());
+
+        // 处理函数通过 Core 访问场景
+        let mut ctx = Context::new(&mut core, 1, addr, &mut state);
+        assert!(dispatcher
+            .dispatch(&mut ctx, Message::new_no_body(110))
+            .is_err());
+        let registry = Rc::new(Registry::init());
+        core.scene = GameScene::new(TestScene::ClassName(), registry);
+        let mut ctx = Context::new(&mut core, 1, addr, &mut state);
+        dispatcher
+            .dispatch(&mut ctx, Message::new_no_body(110))
+            .unwrap();
+        let replies = ctx.take_replies();
+        let scene = core.scene.take().unwrap();
+        let uid: u64 = std::str::from_utf8(replies[0].body.as_deref().unwrap())
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(scene.factory.borrow().find(uid).is_some());
+        let player = state.get::<ObjectPtr>().unwrap();
+        assert_eq!(player.borrow().uid, uid);
+        state.remove::<ObjectPtr>();
+        scene.clear_all();
+
+        let dispatcher = Dispatcher::init(UnknownPolicy::Fallback(test_fallback));
+        let mut ctx = Context::new(&mut core, 1, addr, &mut state);
+        dispatcher
+            .dispatch(&mut ctx, Message::new_no_body(200))
+            .unwrap();
+        assert!(ctx.is_closed());
+    }
+
+    #[test]
+    fn test_frame_systems() {
+        let order = Rc::new(RefCell::new(Vec::new()));
+        let mut core = Core::new();
+        for stage in [
+            Stage::Replicate,
+            Stage::Update,
+            Stage::Cleanup,
+            Stage::Update,
+        ] {
+            let order = order.clone();
+            core.add_system(stage, move |_, frame| {
+                order.borrow_mut().push((frame.index, stage))
+            });
+        }
+        core.step();
+        core.step();
+        assert_eq!(core.stats.frames, 2);
+        assert_eq!(core.frame.time, core.tick * 2);
+        let expect = [
+            Stage::Update,
+            Stage::Update,
+            Stage::Cleanup,
+            Stage::Replicate,
+        ];
+        let order = order.borrow();
+        assert_eq!(order.len(), 8);
+        for (i, &(index, stage)) in order.iter().enumerate() {
+            assert_eq!(index, i as u64 / 4 + 1);
+            assert_eq!(stage, expect[i % 4]);
+        }
+    }
+
+    #[test]
+    fn test_builtin_systems() {
+        let (net, _game) = bridge::channel(16, InboundPolicy::Block, 4, OverflowPolicy::Disconnect);
+        let addr = "127.0.0.1:7777".parse().unwrap();
+        let registry = Rc::new(Registry::init());
+        let mut core = Core::new();
+        core.add_builtin_systems();
+        let scene = GameScene::new(TestScene::ClassName(), registry).unwrap();
+        let player = scene.create_in_scene(TestPlayer::ClassName(), 0).unwrap();
+        core.scene = Some(scene);
+
+        let (outbound, mut rx) = net.session_channel();
+        core.sessions
+            .add(1, addr, std::time::SystemTime::now(), outbound);
+        core.replicator.show(1, &player);
+        core.step();
+        assert!(matches!(rx.rx.try_recv(), Ok(Outgoing::Message(m)) if m.msgcode == MSG_REPLICATE));
+
+        // 删除的对象在 Cleanup 阶段释放
+        let weak = Rc::downgrade(&player);
+        Object::destroy_self(&player);
+        drop(player);
+        assert!(weak.upgrade().is_some());
+        core.step();
+        assert!(weak.upgrade().is_none());
+        core.scene.take().unwrap().clear_all();
+    }
+
+    #[test]
+    fn test_timers() {
+        let registry = Rc::new(Registry::init());
+        let mut core = Core::new();
+        core.tick = Duration::from_millis(500);
+        core.add_builtin_systems();
+        let scene = GameScene::new(TestScene::ClassName(), registry).unwrap();
+        let player = scene.create_in_scene(TestPlayer::ClassName(), 0).unwrap();
+        let fired = Rc::new(RefCell::new(Vec::new()));
+        let timers = scene.timers();
+        core.scene = Some(scene);
+
+        let f = fired.clone();
+        timers.borrow_mut().every_object(
+            player.borrow().handle(),
+            Duration::from_secs(1),
+            move |obj| f.borrow_mut().push(obj.borrow().uid),
+        );
+        let f = fired.clone();
+        let once = timers
+            .borrow_mut()
+            .after(Duration::from_secs(5), move || f.borrow_mut().push(0));
+
+        // 定时器在 Update 阶段按帧的逻辑时间触发
+        for _ in 0..4 {
+            core.step();
+        }
+        assert_eq!(core.frame.time, Duration::from_secs(2));
+        assert_eq!(fired.borrow().len(), 2);
+
+        Object::destroy_self(&player);
+        assert_eq!(timers.borrow().len(), 1);
+        assert!(timers.borrow_mut().cancel(once));
+        for _ in 0..8 {
+            core.step();
+        }
+        assert_eq!(fired.borrow().len(), 2);
+        assert!(timers.borrow().is_empty());
+        core.scene.take().unwrap().clear_all();
+    }
+
+    #[test]
+    fn test_sessions() {
+        let (net, _game) = bridge::channel(16, InboundPolicy::Block, 4, OverflowPolicy::Disconnect);
+        let addr = "127.0.0.1:7777".parse().unwrap();
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let mut sessions = Sessions::new();
+        let e = events.clone();
+        sessions.on_connect(move |session| e.borrow_mut().push(session.id));
+        let e = events.clone();
+        sessions.on_disconnect(move |session| e.borrow_mut().push(session.id + 100));
+
+        let mut receivers = Vec::new();
+        for id in 1..=3 {
+            let (outbound, rx) = net.session_channel();
+            sessions.add(id, addr, std::time::SystemTime::now(), outbound);
+            receivers.push(rx);
+        }
+        assert_eq!(sessions.len(), 3);
+        assert_eq!(sessions.broadcast([1, 3, 4], &Message::new_no_body(10)), 2);
+        assert!(sessions.kick(2, 7));
+        sessions.remove(2);
+        assert_eq!(*events.borrow(), vec![1, 2, 3, 102]);
+
+        assert!(matches!(receivers[0].rx.try_recv(), Ok(Outgoing::Message(m)) if m.msgcode == 10));
+        assert!(matches!(
+            receivers[1].rx.try_recv(),
+            Ok(Outgoing::Message(m)) if m.msgcode == MSG_KICK
+        ));
+        assert!(matches!(receivers[1].rx.try_recv(), Ok(Outgoing::Close)));
+        assert!(receivers[1].rx.try_recv().is_err());
+
+        // 重复的 id 关闭并替换旧的会话
+        events.borrow_mut().clear();
+        sessions
+            .get_mut(3)
+            .unwrap()
+            .state
+            .insert("logged in".to_string());
+        let (outbound, mut rx) = net.session_channel();
+        sessions.add(3, addr, std::time::SystemTime::now(), outbound);
+        assert_eq!(*events.borrow(), vec![103, 3]);
+        assert_eq!(sessions.len(), 2);
+        assert!(sessions.get(3).unwrap().state.get::<String>().is_none());
+        assert!(matches!(receivers[2].rx.try_recv(), Ok(Outgoing::Message(m)) if m.msgcode == 10));
+        assert!(matches!(receivers[2].rx.try_recv(), Ok(Outgoing::Close)));
+        assert!(sessions.send(3, Message::new_no_body(11)));
+        assert!(matches!(rx.rx.try_recv(), Ok(Outgoing::Message(m)) if m.msgcode == 11));
+    }
+
+    #[test]
+    fn test_package_codec() {
+        let mut codec = PackageCodec::new(16);
+        let mut buf = BytesMut::new();
+        codec
+            .encode(Message::new(7, Bytes::from_static(b"hello")), &mut buf)
+            .unwrap();
+        codec.encode(Message::new_no_body(8), &mut buf).unwrap();
+        assert_eq!(&buf[..13], b"\x09\x00\x00\x00\x07\x00\x00\x00hello");
+        assert!(codec
+            .encode(
+                Message::new(9, Bytes::from(vec![0; 13])),
+                &mut BytesMut::new()
+            )
+            .is_err());
+
+        // 分段到达
+        let mut src = BytesMut::new();
+        for b in buf.iter() {
+            assert!(codec.decode(&mut src).unwrap().is_none());
+            src.put_u8(*b);
+            if src.len() == 13 {
+                break;
+            }
+        }
+        src.extend_from_slice(&buf[13..]);
+        let message = codec.decode(&mut src).unwrap().unwrap();
+        assert_eq!(message.msgcode, 7);
+        assert_eq!(message.body.as_deref(), Some(&b"hello"[..]));
+        let message = codec.decode(&mut src).unwrap().unwrap();
+        assert_eq!(message.msgcode, 8);
+        assert!(message.body.is_none());
+        assert!(codec.decode(&mut src).unwrap().is_none());
+
+        for size in [3i32, 17, -1] {
+            let mut src = BytesMut::new();
+            src.put_i32_le(size);
+            assert!(codec.decode(&mut src).is_err());
+        }
+    }
+
+    #[test]
+    fn test_outbound_overflow() {
+        let (net, _game) = bridge::channel(16, InboundPolicy::Block, 4, OverflowPolicy::DropLow);
+        let (outbound, _rx) = net.session_channel();
+        let send = |priority| outbound.send_with(Message::new_no_body(1), priority);
+        assert_eq!(send(Priority::Normal), Ok(()));
+        assert_eq!(send(Priority::Normal), Ok(()));
+        assert_eq!(send(Priority::Low), Ok(()));
+        assert_eq!(send(Priority::Low), Err(SendError::Dropped));
+        assert_eq!(send(Priority::Normal), Ok(()));
+        assert_eq!(send(Priority::Normal), Err(SendError::Full));
+    }
+
+    #[test]
+    fn test_local_outbound() {
+        let (net, _game) = bridge::channel(16, InboundPolicy::Block, 4, OverflowPolicy::Disconnect);
+        let (outbound, mut rx) = net.session_channel();
+        assert_eq!(rx.local.send(Message::new_no_body(1)), Ok(()));
+        assert!(matches!(rx.rx.try_recv(), Ok(Outgoing::Message(m)) if m.msgcode == 1));
+        // 游戏线程释放会话后接收端结束，连接自己的发送不会让它保持打开
+        drop(outbound);
+        assert!(matches!(
+            rx.rx.try_recv(),
+            Err(tokio::sync::mpsc::error::TryRecvError::Disconnected)
+        ));
+        assert_eq!(
+            rx.local.send(Message::new_no_body(2)),
+            Err(SendError::Closed)
+        );
+    }
+
+    /// 只有一个 `Connection` 的服务端和连上它的客户端
+    struct TestConn {
+        client: TcpStream,
+        game: GameBridge,
+        task: JoinHandle<crate::Result<()>>,
+        _outbound: Outbound,
+        _notify: broadcast::Sender<()>,
+    }
+
+    async fn test_conn(config: ConnectionConfig) -> TestConn {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, addr) = listener.accept().await.unwrap();
+        let (net, game) = bridge::channel(16, InboundPolicy::Block, 4, OverflowPolicy::Disconnect);
+        let (outbound, rx) = net.session_channel();
+        let (notify, _) = broadcast::channel(1);
+        let (complete, _) = mpsc::channel(1);
+        let mut connection = Connection::new(
+            1,
+            Package::new(socket, PackageCodec::new(MAX_LEN)),
+            addr,
+            config,
+            net,
+            rx,
+            Shutdown::new(notify.subscribe(), CancellationToken::new()),
+            complete,
+        );
+        TestConn {
+            client,
+            game,
+            task: tokio::spawn(async move { connection.io_loop().await }),
+            _outbound: outbound,
+            _notify: notify,
+        }
+    }
+
+    async fn send_to(client: &mut TcpStream, msgcode: i32) {
+        let mut buf = BytesMut::new();
+        PackageCodec::new(MAX_LEN)
+            .encode(Message::new_no_body(msgcode), &mut buf)
+            .unwrap();
+        client.write_all(&buf).await.unwrap();
+    }
+
+    async fn recv_from(client: &mut TcpStream) -> Message {
+        let mut codec = PackageCodec::new(MAX_LEN);
+        let mut buf = BytesMut::new();
+        loop {
+            if let Some(message) = codec.decode(&mut buf).unwrap() {
+                return message;
+            }
+            assert_ne!(client.read_buf(&mut buf).await.unwrap(), 0);
+        }
+    }
+
+    /// 取出最多 `max` 个投递到游戏线程的事件，消息返回消息码，断开事件为 None
+    fn posted(game: &mut GameBridge, max: usize) -> Vec<Option<i32>> {
+        let mut events = Vec::new();
+        game.drain(max, |event| match event {
+            NetEvent::Message { message, .. } => events.push(Some(message.msgcode)),
+            NetEvent::Disconnected { .. } => events.push(None),
+            NetEvent::Connected { .. } => {}
+        });
+        events
+    }
+
+    fn message_event(msgcode: i32) -> NetEvent {
+        NetEvent::Message {
+            session_id: 1,
+            message: Message::new_no_body(msgcode),
+        }
+    }
+
+    #[test]
+    fn test_inbound_block() {
+        run_local(async {
+            let (net, mut game) =
+                bridge::channel(2, InboundPolicy::Block, 4, OverflowPolicy::Disconnect);
+            net.post(message_event(1)).await.unwrap();
+            net.post(message_event(2)).await.unwrap();
+            // 队列满时等待，不丢弃也不断开
+            let blocked = tokio::spawn({
+                let net = net.clone();
+                async move { net.post(message_event(3)).await }
+            });
+            tokio::task::yield_now().await;
+            assert!(!blocked.is_finished());
+
+            assert_eq!(posted(&mut game, 1), [Some(1)]);
+            blocked.await.unwrap().unwrap();
+            assert_eq!(posted(&mut game, usize::MAX), [Some(2), Some(3)]);
+            assert_eq!(posted(&mut game, usize::MAX), []);
+        });
+    }
+
+    #[test]
+    fn test_inbound_disconnect() {
+        run_local(async {
+            let (net, mut game) =
+                bridge::channel(1, InboundPolicy::Disconnect, 4, OverflowPolicy::Disconnect);
+            net.post(message_event(1)).await.unwrap();
+            let err = net.post(message_event(2)).await.unwrap_err();
+            assert_eq!(err.to_string(), "inbound queue full");
+
+            // 断开事件在队列满时也会等待投递
+            let disconnect = tokio::spawn({
+                let net = net.clone();
+                async move { net.post_disconnect(1).await }
+            });
+            tokio::task::yield_now().await;
+            assert!(!disconnect.is_finished());
+            assert_eq!(posted(&mut game, 1), [Some(1)]);
+            disconnect.await.unwrap();
+            assert_eq!(posted(&mut game, usize::MAX), [None]);
+        });
+    }
+
+    #[test]
+    fn test_idle_timeout() {
+        run_local(async {
+            tokio::time::pause();
+            let start = Instant::now();
+            let mut conn = test_conn(ConnectionConfig {
+                idle_timeout: Some(Duration::from_secs(5)),
+                ..Default::default()
+            })
+            .await;
+            let err = conn.task.await.unwrap().unwrap_err();
+            assert_eq!(err.to_string(), "idle timeout");
+            assert!(start.elapsed() >= Duration::from_secs(5));
+            assert_eq!(posted(&mut conn.game, usize::MAX), [None]);
+        });
+    }
+
+    #[test]
+    fn test_frame_timeout() {
+        run_local(async {
+            tokio::time::pause();
+            let mut conn = test_conn(ConnectionConfig {
+                frame_timeout: Some(Duration::from_secs(2)),
+                ..Default::default()
+            })
+            .await;
+            send_to(&mut conn.client, 5).await;
+            // 空闲一段时间后只发来帧头的一部分
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            conn.client.write_all(&[8, 0]).await.unwrap();
+            let start = Instant::now();
+            let err = conn.task.await.unwrap().unwrap_err();
+            assert_eq!(err.to_string(), "frame timeout");
+            assert!(start.elapsed() >= Duration::from_secs(2));
+            assert!(start.elapsed() < Duration::from_secs(3));
+            assert_eq!(posted(&mut conn.game, usize::MAX), [Some(5), None]);
+        });
+    }
+
+    #[test]
+    fn test_heartbeat() {
+        run_local(async {
+            tokio::time::pause();
+            let TestConn {
+                mut client,
+                mut game,
+                task,
+                _outbound,
+                _notify,
+            } = test_conn(ConnectionConfig {
+                idle_timeout: Some(Duration::from_secs(10)),
+                heartbeat: Some(Heartbeat {
+                    ping: -10,
+                    pong: -11,
+                    interval: Some(Duration::from_secs(3)),
+                }),
+                ..Default::default()
+            })
+            .await;
+            send_to(&mut client, -10).await;
+            assert_eq!(recv_from(&mut client).await.msgcode, -11);
+            send_to(&mut client, -11).await;
+            send_to(&mut client, 5).await;
+
+            // 超过间隔没有收到数据时服务器主动 ping
+            let start = Instant::now();
+            assert_eq!(recv_from(&mut client).await.msgcode, -10);
+            assert!(start.elapsed() >= Duration::from_secs(3));
+            drop(client);
+            task.await.unwrap().unwrap();
+            // 心跳不会投递到游戏线程
+            assert_eq!(posted(&mut game, usize::MAX), [Some(5), None]);
+        });
+    }
+
+    #[test]
+    fn test_graceful_shutdown() {
+        let (net, game) = bridge::channel(16, InboundPolicy::Block, 4, OverflowPolicy::Disconnect);
+        let mut core = Core::new();
+        core.net = Some(game);
+        let (outbound, mut rx) = net.session_channel();
+        core.sessions.add(
+            1,
+            "127.0.0.1:7777".parse().unwrap(),
+            std::time::SystemTime::now(),
+            outbound,
+        );
+        let saved = Rc::new(RefCell::new(0));
+        let s = saved.clone();
+        core.on_shutdown(move |core| *s.borrow_mut() = core.sessions.len() + 1);
+        let s = saved.clone();
+        core.on_shutdown_async(move |_| async move {
+            tokio::task::yield_now().await;
+            *s.borrow_mut() *= 10;
+        });
+
+        run_local(core.graceful_shutdown());
+        assert_eq!(*saved.borrow(), 10);
+        assert!(core.net.is_none());
+        assert!(matches!(
+            rx.rx.try_recv(),
+            Ok(Outgoing::Message(m)) if m.msgcode == MSG_SERVER_CLOSING
+        ));
+        assert!(matches!(rx.rx.try_recv(), Ok(Outgoing::Close)));
+    }
+
+    #[test]
+    fn test_config_layers() {
+        let file = r#"
+            [server]
+            port = 9000
+            tick_rate = 20
+            [net]
+            idle_timeout = "0s"
+            [log]
+            format = "compact"
+        "#;
+        let env = [
+            ("RENGINE_SERVER_PORT".to_string(), "9001".to_string()),
+            ("RENGINE_NET_FRAME_TIMEOUT".to_string(), "3s".to_string()),
+            ("PATH".to_string(), "/bin".to_string()),
+            // 不认识的变量被忽略
+            ("RENGINE_HOME".to_string(), "/opt/rengine".to_string()),
+            ("RENGINE_SERVER_PROT".to_string(), "1".to_string()),
+            ("RENGINE_DEBUG_LEVEL".to_string(), "1".to_string()),
+        ];
+        let config =
+            Config::from_layers(Some(file), env, &[("server.host", "127.0.0.1".to_string())])
+                .unwrap();
+        assert_eq!(config.bind().unwrap(), "127.0.0.1:9001".parse().unwrap());
+        assert_eq!(config.log.format, LogFormat::Compact);
+        let options = config.to_options().unwrap();
+        assert_eq!(options.tick_rate, 20);
+        assert_eq!(options.idle_timeout, None);
+        assert_eq!(options.frame_timeout, Some(Duration::from_secs(3)));
+
+        let err = |file: &str| {
+            Config::from_layers(Some(file), [], &[])
+                .unwrap_err()
+                .to_string()
+        };
+        assert!(err("[server]\ntick_rate = 0").contains("server.tick_rate"));
+        assert!(err("[server]\nhost = \"nope\"").contains("server.host"));
+        assert!(err("[net]\nmax_frame_len = 2").contains("net.max_frame_len"));
+        assert!(err("[server]\nprot = 1").contains("prot"));
+        // 认识的变量值不对时报错
+        let env = [("RENGINE_SERVER_PORT".to_string(), "high".to_string())];
+        let err = Config::from_layers(None, env, &[]).unwrap_err().to_string();
+        assert!(err.contains("port"), "{}", err);
+
+        // 代码中设置的帧率同样限制范围
+        let mut options = Options::default();
+        with_tick_rate(u32::MAX, OverrunPolicy::Skip)(&mut options);
+        assert_eq!(options.tick_rate, MAX_TICK_RATE);
+        with_tick_rate(0, OverrunPolicy::Skip)(&mut options);
+        assert_eq!(options.tick_rate, 1);
+    }
 }
@@ -1 +1,14 @@
-
+/// 注册消息处理函数
+///
+/// ```ignore
+/// fn on_login(ctx: &mut Context, message: Message) -> re_core::Result<()> { Ok(()) }
+/// register_handler!(1001, on_login);
+/// ```
+#[macro_export]
+macro_rules! register_handler {
+    ($msgcode:expr, $f:expr) => {
+        $crate::inventory::submit! {
+            $crate::dispatcher::MessageHandler::register_handler($msgcode, $f)
+        }
+    };
+}
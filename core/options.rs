@@ -1,15 +1,146 @@
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    time::Duration,
+};
+
+use crate::{
+    bridge::{InboundPolicy, OverflowPolicy},
+    connection::Heartbeat,
+    core::Core,
+    dispatcher::{HandlerFn, UnknownPolicy},
+    frame::OverrunPolicy,
+    MAX_LEN,
+};
+
+/// 每秒帧数的上限，再高时每帧的时长不足一纳秒
+pub const MAX_TICK_RATE: u32 = 1000;
+
 pub struct Options {
-    pub port: i32,
+    pub bind: SocketAddr,
+    /// net io 线程的 tokio 工作线程数
+    pub worker_threads: usize,
+    pub unknown_msg: UnknownPolicy,
+    /// 单个消息帧长度字段的上限
+    pub max_frame_len: usize,
+    /// net io 线程到游戏线程的队列长度
+    pub inbound_capacity: usize,
+    pub inbound_policy: InboundPolicy,
+    /// 每个连接的出站队列长度
+    pub outbound_capacity: usize,
+    pub outbound_policy: OverflowPolicy,
+    /// 游戏线程每帧最多处理的网络事件数
+    pub max_events_per_frame: usize,
+    /// 超过这个时间没有收到消息时断开
+    pub idle_timeout: Option<Duration>,
+    /// 一帧开始到达后必须在这个时间内收完
+    pub frame_timeout: Option<Duration>,
+    pub heartbeat: Option<Heartbeat>,
+    /// 每秒帧数，`run` 时限制在 `1..=MAX_TICK_RATE`
+    pub tick_rate: u32,
+    pub overrun: OverrunPolicy,
+    /// 关闭时等待出站队列发送完的最长时间，超时后强制断开
+    pub drain_timeout: Duration,
+    /// 进入帧循环前在游戏线程上调用，用来创建场景和注册系统
+    pub setup: Option<fn(&mut Core)>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            bind: SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+            worker_threads: 4,
+            unknown_msg: UnknownPolicy::Disconnect,
+            max_frame_len: MAX_LEN,
+            inbound_capacity: 4096,
+            inbound_policy: InboundPolicy::Block,
+            outbound_capacity: 256,
+            outbound_policy: OverflowPolicy::Disconnect,
+            max_events_per_frame: 1024,
+            idle_timeout: Some(Duration::from_secs(60)),
+            frame_timeout: Some(Duration::from_secs(10)),
+            heartbeat: None,
+            tick_rate: 10,
+            overrun: OverrunPolicy::default(),
+            drain_timeout: Duration::from_secs(5),
+            setup: None,
+        }
+    }
 }
 
 pub fn load_option(opts: &[impl Fn(&mut Options)]) -> Options {
-    let mut options = Options { port: 0 };
+    apply_option(Options::default(), opts)
+}
+
+/// 在已有的配置上应用函数式选项，例如从 `Config` 生成的配置
+pub fn apply_option(mut options: Options, opts: &[impl Fn(&mut Options)]) -> Options {
     for opt in opts {
         opt(&mut options)
     }
     options
 }
 
-pub fn with_port(port: i32) -> impl Fn(&mut Options) {
-    move |options: &mut Options| options.port = port
+pub fn with_port(port: u16) -> impl Fn(&mut Options) {
+    move |options: &mut Options| options.bind.set_port(port)
+}
+
+pub fn with_bind(bind: SocketAddr) -> impl Fn(&mut Options) {
+    move |options: &mut Options| options.bind = bind
+}
+
+pub fn with_worker_threads(worker_threads: usize) -> impl Fn(&mut Options) {
+    move |options: &mut Options| options.worker_threads = worker_threads
+}
+
+/// 未注册的消息码交给 `f` 处理，默认断开连接
+pub fn with_fallback(f: HandlerFn) -> impl Fn(&mut Options) {
+    move |options: &mut Options| options.unknown_msg = UnknownPolicy::Fallback(f)
+}
+
+pub fn with_inbound(capacity: usize, policy: InboundPolicy) -> impl Fn(&mut Options) {
+    move |options: &mut Options| {
+        options.inbound_capacity = capacity;
+        options.inbound_policy = policy;
+    }
+}
+
+pub fn with_outbound(capacity: usize, policy: OverflowPolicy) -> impl Fn(&mut Options) {
+    move |options: &mut Options| {
+        options.outbound_capacity = capacity;
+        options.outbound_policy = policy;
+    }
+}
+
+/// 每秒帧数限制在 `1..=MAX_TICK_RATE`
+pub fn with_tick_rate(tick_rate: u32, overrun: OverrunPolicy) -> impl Fn(&mut Options) {
+    move |options: &mut Options| {
+        options.tick_rate = tick_rate.clamp(1, MAX_TICK_RATE);
+        options.overrun = overrun;
+    }
+}
+
+pub fn with_setup(setup: fn(&mut Core)) -> impl Fn(&mut Options) {
+    move |options: &mut Options| options.setup = Some(setup)
+}
+
+pub fn with_max_frame_len(max_frame_len: usize) -> impl Fn(&mut Options) {
+    move |options: &mut Options| options.max_frame_len = max_frame_len
+}
+
+/// `None` 表示不限制
+pub fn with_timeouts(
+    idle_timeout: Option<Duration>,
+    frame_timeout: Option<Duration>,
+) -> impl Fn(&mut Options) {
+    move |options: &mut Options| {
+        options.idle_timeout = idle_timeout;
+        options.frame_timeout = frame_timeout;
+    }
+}
+
+pub fn with_heartbeat(heartbeat: Heartbeat) -> impl Fn(&mut Options) {
+    move |options: &mut Options| options.heartbeat = Some(heartbeat)
+}
+
+pub fn with_drain_timeout(drain_timeout: Duration) -> impl Fn(&mut Options) {
+    move |options: &mut Options| options.drain_timeout = drain_timeout
 }
@@ -1,12 +1,23 @@
-use bytes::{Bytes, BytesMut};
+use std::{io, time::Duration};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt, BufWriter},
-    net::TcpStream,
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+    time::{timeout_at, Instant},
 };
-use tracing::info;
+use tokio_util::codec::{Decoder, Encoder};
+use tracing::debug;
 
 use crate::MAX_LEN;
 
+/// 长度字段和消息码各占 4 字节
+const HEADER_LEN: usize = 4;
+
+#[derive(Debug, Clone)]
 pub struct Message {
     pub msgcode: i32,
     pub body: Option<Bytes>,
@@ -27,64 +38,214 @@ impl Message {
             body: None,
         }
     }
+
+    /// 消息码加消息体的长度，即长度字段的值
+    pub fn frame_len(&self) -> usize {
+        4 + self.body.as_ref().map_or(0, |body| body.len())
+    }
+}
+
+/// `[len:i32le][msgcode:i32le][body]` 帧格式的编解码器
+///
+/// `len` 是消息码和消息体的总长度，不包括长度字段本身，
+/// 超过 `max_frame_len` 的帧解码和编码时都会返回 `InvalidData` 错误。
+#[derive(Debug, Clone, Copy)]
+pub struct PackageCodec {
+    max_frame_len: usize,
+}
+
+impl Default for PackageCodec {
+    fn default() -> Self {
+        Self::new(MAX_LEN)
+    }
+}
+
+impl PackageCodec {
+    pub fn new(max_frame_len: usize) -> Self {
+        assert!(max_frame_len >= 4 && max_frame_len <= i32::MAX as usize);
+        Self { max_frame_len }
+    }
+
+    pub fn max_frame_len(&self) -> usize {
+        self.max_frame_len
+    }
+}
+
+impl Decoder for PackageCodec {
+    type Item = Message;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Message>, io::Error> {
+        if src.len() < HEADER_LEN {
+            return Ok(None);
+        }
+        let size = i32::from_le_bytes(src[..HEADER_LEN].try_into().unwrap());
+        if size < 4 || size as usize > self.max_frame_len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("frame size error: {}", size),
+            ));
+        }
+        let size = size as usize;
+        if src.len() < HEADER_LEN + size {
+            src.reserve(HEADER_LEN + size - src.len());
+            return Ok(None);
+        }
+        src.advance(HEADER_LEN);
+        let msgcode = src.get_i32_le();
+        if size == 4 {
+            return Ok(Some(Message::new_no_body(msgcode)));
+        }
+
+        Ok(Some(Message::new(msgcode, src.split_to(size - 4).freeze())))
+    }
+}
+
+impl Encoder<Message> for PackageCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, message: Message, dst: &mut BytesMut) -> Result<(), io::Error> {
+        let size = message.frame_len();
+        if size > self.max_frame_len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("message size exceed: {}", size),
+            ));
+        }
+        dst.reserve(HEADER_LEN + size);
+        dst.put_i32_le(size as i32);
+        dst.put_i32_le(message.msgcode);
+        if let Some(body) = message.body {
+            dst.put(body);
+        }
+        Ok(())
+    }
 }
 
 pub struct Package {
-    stream: BufWriter<TcpStream>,
-    buffer: BytesMut,
+    stream: TcpStream,
+    codec: PackageCodec,
 }
 
 impl Package {
-    pub fn new(socket: TcpStream) -> Self {
+    pub fn new(socket: TcpStream, codec: PackageCodec) -> Self {
         Self {
-            stream: BufWriter::new(socket),
-            buffer: BytesMut::with_capacity(MAX_LEN),
+            stream: socket,
+            codec,
         }
     }
 
+    /// 拆分成读写两半，分别在不同的任务中使用
+    pub fn into_split(self) -> (PackageReader, PackageWriter) {
+        let (reader, writer) = self.stream.into_split();
+        (
+            PackageReader {
+                stream: reader,
+                codec: self.codec,
+                buffer: BytesMut::with_capacity(4 * 1024),
+                partial_since: None,
+                frame_timeout: None,
+            },
+            PackageWriter {
+                stream: writer,
+                codec: self.codec,
+                buffer: BytesMut::with_capacity(4 * 1024),
+            },
+        )
+    }
+}
+
+pub struct PackageReader {
+    stream: OwnedReadHalf,
+    codec: PackageCodec,
+    buffer: BytesMut,
+    partial_since: Option<Instant>,
+    frame_timeout: Option<Duration>,
+}
+
+impl PackageReader {
+    /// 一帧开始到达后必须在这个时间内收完，否则 `read_message` 返回错误
+    pub fn set_frame_timeout(&mut self, timeout: Option<Duration>) {
+        self.frame_timeout = timeout;
+    }
+
+    /// 读取一个完整的消息，可以安全地在 `select!` 中取消
     pub async fn read_message(&mut self) -> crate::Result<Option<Message>> {
-        let mut size = self.stream.read_i32_le().await? as usize;
-        if size < 4 || size > self.buffer.capacity() {
-            return Err("size error".into());
-        }
-        let msgcode = self.stream.read_i32_le().await?;
-        size -= 4;
-        if size == 0 {
-            return Ok(Some(Message {
-                msgcode: msgcode,
-                body: None,
-            }));
-        }
-        self.buffer.clear();
-        self.buffer.resize(size, 0);
-        let body_size = self.stream.read_exact(&mut self.buffer).await?;
-        if body_size != (size).try_into().unwrap() {
-            return Err("read body error".into());
+        loop {
+            if let Some(message) = self.codec.decode(&mut self.buffer)? {
+                // 缓冲区里剩下的是下一帧的开头
+                self.partial_since = match self.buffer.is_empty() {
+                    true => None,
+                    false => Some(Instant::now()),
+                };
+                return Ok(Some(message));
+            }
+
+            // 每次读取前重新计算，收到半帧后等待剩余部分的时间也受限制
+            let deadline = self
+                .frame_timeout
+                .zip(self.partial_since)
+                .map(|(timeout, since)| since + timeout);
+            let read = self.stream.read_buf(&mut self.buffer);
+            let n = match deadline {
+                Some(deadline) => match timeout_at(deadline, read).await {
+                    Ok(res) => res?,
+                    Err(_) => return Err("frame timeout".into()),
+                },
+                None => read.await?,
+            };
+            if n == 0 {
+                if self.buffer.is_empty() {
+                    return Ok(None);
+                }
+                return Err("connection reset by peer".into());
+            }
+            if self.partial_since.is_none() {
+                self.partial_since = Some(Instant::now());
+            }
         }
+    }
 
-        Ok(Some(Message {
-            msgcode: msgcode,
-            body: Some(Bytes::copy_from_slice(&self.buffer)),
-        }))
+    /// 当前未读完的帧开始到达的时间
+    pub fn partial_since(&self) -> Option<Instant> {
+        self.partial_since
     }
+}
 
-    pub async fn write_message(&mut self, mut messgae: Message) -> crate::Result<()> {
-        let mut size: usize = 4;
-        let msgcode = messgae.msgcode;
-        if let Some(mut body) = messgae.body.take() {
-            size += body.len();
-            if size + 4 >= MAX_LEN {
-                return Err("message size exceed".into());
-            }
-            self.stream.write_i32_le(size.try_into().unwrap()).await?;
-            self.stream.write_i32_le(msgcode).await?;
-            self.stream.write_buf(&mut body).await?;
-        } else {
-            self.stream.write_i32_le(size.try_into().unwrap()).await?;
-            self.stream.write_i32_le(msgcode).await?;
+pub struct PackageWriter {
+    stream: OwnedWriteHalf,
+    codec: PackageCodec,
+    buffer: BytesMut,
+}
+
+impl PackageWriter {
+    /// 编码到缓冲区，调用 `flush` 后才真正发送
+    pub fn write_message(&mut self, message: Message) -> crate::Result<()> {
+        debug!(
+            "send message code {} len {}",
+            message.msgcode,
+            message.frame_len()
+        );
+        self.codec.encode(message, &mut self.buffer)?;
+        Ok(())
+    }
+
+    /// 缓冲区中待发送的字节数
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub async fn flush(&mut self) -> crate::Result<()> {
+        if !self.buffer.is_empty() {
+            self.stream.write_all_buf(&mut self.buffer).await?;
         }
         self.stream.flush().await?;
-        info!("send message code {} len {}", msgcode, size);
+        Ok(())
+    }
+
+    pub async fn shutdown(&mut self) -> crate::Result<()> {
+        self.flush().await?;
+        self.stream.shutdown().await?;
         Ok(())
     }
 }
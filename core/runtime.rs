@@ -1,7 +1,6 @@
 use std::future::Future;
 
-use tokio::sync::{broadcast, mpsc};
-use tracing::{error, info};
+use tracing::error;
 
 use crate::{
     core::Core,
@@ -11,54 +10,31 @@ use crate::{
 
 pub struct Runtime {}
 
+impl Default for Runtime {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Runtime {
     pub fn new() -> Self {
-        let runtime = Self {};
-        runtime
+        Self {}
     }
 }
 
-pub async fn core_run(port: i32, shutdown: impl Future) {
-    let (notify_shutdown, _) = broadcast::channel(1);
-    let (shutdown_complete_tx, shutdown_complete_rx) = mpsc::channel(1);
-
-    let mut server = Core {
-        notify_shutdown,
-        shutdown_complete_tx,
-        shutdown_complete_rx,
-        quit: false,
-    };
-
-    tokio::select! {
-        res = server.run(port) => {
-            if let Err(err) = res {
-                error!(cause = %err, "failed to accept");
-            }
-        }
-        _ = shutdown => {
-            info!("shutting down");
-        }
+pub async fn core_run(options: Options, shutdown: impl Future) {
+    let mut server = Core::new();
+    if let Err(err) = server.run(&options, shutdown).await {
+        error!(cause = %err, "failed to run");
     }
-
-    server.shutdown();
-
-    let Core {
-        mut shutdown_complete_rx,
-        shutdown_complete_tx,
-        notify_shutdown,
-        ..
-    } = server;
-
-    drop(notify_shutdown);
-
-    drop(shutdown_complete_tx);
-
-    shutdown_complete_rx.recv().await;
 }
 
 pub fn run(options: &[impl Fn(&mut Options)], shutdown: impl Future) {
-    let options = load_option(options);
+    run_with(load_option(options), shutdown);
+}
+
+pub fn run_with(options: Options, shutdown: impl Future) {
     run_local(async {
-        core_run(options.port, shutdown).await;
+        core_run(options, shutdown).await;
     });
 }
@@ -0,0 +1,202 @@
+use std::{collections::HashMap, net::SocketAddr, time::SystemTime};
+
+use bytes::Bytes;
+use re_object::replication::Replicator;
+use tracing::{info, warn};
+
+use crate::{
+    bridge::{Outbound, Priority, SendError},
+    dispatcher::SessionState,
+    package::Message,
+};
+
+/// 踢人时发给客户端的消息码，消息体为 i32 小端的原因码
+pub const MSG_KICK: i32 = -1;
+/// 服务器关闭前发给每个会话的消息码，没有消息体
+pub const MSG_SERVER_CLOSING: i32 = -2;
+/// 属性同步包，消息体格式见 `Replicator`
+pub const MSG_REPLICATE: i32 = -3;
+
+/// 游戏线程上的会话
+#[derive(Debug)]
+pub struct Session {
+    pub id: u64,
+    pub addr: SocketAddr,
+    pub connected_at: SystemTime,
+    /// 用户数据，例如登录后的角色 uid
+    pub state: SessionState,
+    outbound: Outbound,
+}
+
+impl Session {
+    /// 出站队列满时断开连接
+    pub fn send(&self, message: Message) -> bool {
+        self.send_with(message, Priority::Normal)
+    }
+
+    /// 低优先级消息可能按 `OverflowPolicy` 被丢弃，此时返回 `false` 但不断开
+    pub fn send_with(&self, message: Message, priority: Priority) -> bool {
+        match self.outbound.send_with(message, priority) {
+            Ok(()) => true,
+            Err(SendError::Dropped) => false,
+            Err(SendError::Full) => {
+                warn!("session {} outbound queue full, kick", self.id);
+                self.outbound.kick();
+                false
+            }
+            Err(SendError::Closed) => false,
+        }
+    }
+
+    /// 通知客户端原因后断开
+    pub fn kick(&self, reason: i32) {
+        info!("kick session {}, reason {}", self.id, reason);
+        let body = Bytes::copy_from_slice(&reason.to_le_bytes());
+        if self.send(Message::new(MSG_KICK, body)) {
+            self.outbound.close();
+        }
+    }
+
+    /// 发送完已在队列中的消息后断开
+    pub fn close(&self) {
+        self.outbound.close();
+    }
+
+    /// 立即断开，不再发送队列中的消息
+    pub fn abort(&self) {
+        self.outbound.kick();
+    }
+}
+
+pub type SessionListener = Box<dyn FnMut(&Session)>;
+
+#[derive(Default)]
+pub struct Sessions {
+    sessions: HashMap<u64, Session>,
+    on_connect: Vec<SessionListener>,
+    on_disconnect: Vec<SessionListener>,
+}
+
+impl Sessions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 新会话加入后调用
+    pub fn on_connect(&mut self, f: impl FnMut(&Session) + 'static) {
+        self.on_connect.push(Box::new(f));
+    }
+
+    /// 会话移除后调用，此时已无法再发送消息
+    pub fn on_disconnect(&mut self, f: impl FnMut(&Session) + 'static) {
+        self.on_disconnect.push(Box::new(f));
+    }
+
+    /// 同一个 id 已有会话时先关闭旧的会话，执行 `on_disconnect` 后再加入新的
+    pub fn add(&mut self, id: u64, addr: SocketAddr, connected_at: SystemTime, outbound: Outbound) {
+        if let Some(old) = self.sessions.get(&id) {
+            warn!("session {} already exists, replace it", id);
+            old.close();
+            self.remove(id);
+        }
+        let session = self.sessions.entry(id).or_insert(Session {
+            id,
+            addr,
+            connected_at,
+            state: SessionState::default(),
+            outbound,
+        });
+        for f in self.on_connect.iter_mut() {
+            f(session);
+        }
+    }
+
+    pub fn remove(&mut self, id: u64) -> Option<Session> {
+        let session = self.sessions.remove(&id)?;
+        for f in self.on_disconnect.iter_mut() {
+            f(&session);
+        }
+        Some(session)
+    }
+
+    pub fn get(&self, id: u64) -> Option<&Session> {
+        self.sessions.get(&id)
+    }
+
+    pub fn get_mut(&mut self, id: u64) -> Option<&mut Session> {
+        self.sessions.get_mut(&id)
+    }
+
+    pub fn contains(&self, id: u64) -> bool {
+        self.sessions.contains_key(&id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.sessions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = u64> + '_ {
+        self.sessions.keys().copied()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Session> {
+        self.sessions.values()
+    }
+
+    pub fn send(&self, id: u64, message: Message) -> bool {
+        match self.sessions.get(&id) {
+            Some(session) => session.send(message),
+            None => false,
+        }
+    }
+
+    /// 发给指定的会话，返回成功投递的数量
+    pub fn broadcast(&self, ids: impl IntoIterator<Item = u64>, message: &Message) -> usize {
+        ids.into_iter()
+            .filter(|&id| self.send(id, message.clone()))
+            .count()
+    }
+
+    pub fn broadcast_all(&self, message: &Message) -> usize {
+        self.sessions
+            .values()
+            .filter(|session| session.send(message.clone()))
+            .count()
+    }
+
+    pub fn kick(&self, id: u64, reason: i32) -> bool {
+        match self.sessions.get(&id) {
+            Some(session) => {
+                session.kick(reason);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 收集属性变化并发给对应的会话，已断开的会话从 `replicator` 中移除
+    ///
+    /// 在 `Stage::Replicate` 阶段的系统中调用，返回发送的包数。
+    pub fn replicate(&self, replicator: &mut Replicator) -> usize {
+        let mut sent = 0;
+        let mut gone = Vec::new();
+        for (viewer, packet) in replicator.collect() {
+            match self.sessions.get(&viewer) {
+                Some(session) => {
+                    if session.send(Message::new(MSG_REPLICATE, Bytes::from(packet))) {
+                        sent += 1;
+                    }
+                }
+                None => gone.push(viewer),
+            }
+        }
+        for viewer in gone {
+            replicator.remove_viewer(viewer);
+        }
+        sent
+    }
+}
@@ -1,4 +1,5 @@
 use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
 
 /// Listens for the server shutdown signal.
 ///
@@ -9,6 +10,10 @@ use tokio::sync::broadcast;
 /// The `Shutdown` struct listens for the signal and tracks that the signal has
 /// been received. Callers may query for whether the shutdown signal has been
 /// received or not.
+///
+/// The shutdown signal is graceful: tasks stop taking new work but may finish
+/// what is in flight. When the drain deadline passes, the `abort` token is
+/// cancelled and tasks should stop immediately.
 #[derive(Debug)]
 pub struct Shutdown {
     /// `true` if the shutdown signal has been received
@@ -16,14 +21,19 @@ pub struct Shutdown {
 
     /// The receive half of the channel used to listen for shutdown.
     notify: broadcast::Receiver<()>,
+
+    /// Cancelled when the graceful shutdown takes too long.
+    abort: CancellationToken,
 }
 
 impl Shutdown {
-    /// Create a new `Shutdown` backed by the given `broadcast::Receiver`.
-    pub fn new(notify: broadcast::Receiver<()>) -> Shutdown {
+    /// Create a new `Shutdown` backed by the given `broadcast::Receiver` and
+    /// abort token.
+    pub fn new(notify: broadcast::Receiver<()>, abort: CancellationToken) -> Shutdown {
         Shutdown {
             shutdown: false,
             notify,
+            abort,
         }
     }
 
@@ -46,4 +56,14 @@ impl Shutdown {
         // Remember that the signal has been received.
         self.shutdown = true;
     }
+
+    /// Returns `true` if the shutdown has been forced.
+    pub fn is_aborted(&self) -> bool {
+        self.abort.is_cancelled()
+    }
+
+    /// A token that is cancelled together with the abort signal.
+    pub fn child_token(&self) -> CancellationToken {
+        self.abort.child_token()
+    }
 }
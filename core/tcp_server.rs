@@ -1,32 +1,69 @@
+use std::time::SystemTime;
+
 use tokio::{
     net::TcpListener,
     sync::{broadcast, mpsc},
 };
+use tokio_util::sync::CancellationToken;
 use tracing::info;
 
-use crate::{connection::Connection, shutdown::Shutdown};
+use crate::{
+    bridge::{NetBridge, NetEvent},
+    connection::{Connection, ConnectionConfig},
+    package::{Package, PackageCodec},
+    shutdown::Shutdown,
+};
 
 pub struct Listener {
     pub listener: TcpListener,
+    pub bridge: NetBridge,
+    pub codec: PackageCodec,
+    pub config: ConnectionConfig,
+    pub next_session_id: u64,
+    /// 游戏线程的关闭通知，连接和监听共用
+    pub notify_shutdown: broadcast::Sender<()>,
+    /// 排空超时后强制关闭所有连接
+    pub abort: CancellationToken,
+    /// 线程退出时释放，游戏线程据此判断网络已经关闭
     pub shutdown_complete_tx: mpsc::Sender<()>,
 }
 
 impl Listener {
+    /// 收到关闭通知后停止接受连接，等所有连接结束后返回
     pub async fn run(&mut self, mut shutdown: Shutdown) {
-        let (notify_shutdown, _) = broadcast::channel(1);
         let (tx, mut rx) = mpsc::channel(1);
         while !shutdown.is_shutdown() {
             tokio::select! {
                 res = async {
                         if let Ok((conn, addr)) = self.listener.accept().await {
+                            self.next_session_id += 1;
+                            let session_id = self.next_session_id;
+                            let connected_at = SystemTime::now();
+                            let (outbound, outbound_rx) = self.bridge.session_channel();
                             let mut connection = Connection::new(
-                                conn,
+                                session_id,
+                                Package::new(conn, self.codec),
                                 addr,
-                                Shutdown::new(notify_shutdown.subscribe()),
+                                self.config,
+                                self.bridge.clone(),
+                                outbound_rx,
+                                Shutdown::new(
+                                    self.notify_shutdown.subscribe(),
+                                    self.abort.clone(),
+                                ),
                                 tx.clone(),
                             );
+                            let bridge = self.bridge.clone();
                             tokio::spawn(async move {
-                                _ = connection.io_loop().await;
+                                let connected = NetEvent::Connected {
+                                    session_id,
+                                    addr,
+                                    connected_at,
+                                    outbound,
+                                };
+                                if bridge.post(connected).await.is_ok() {
+                                    _ = connection.io_loop().await;
+                                }
                                 info!("client closed");
                             });
                             return Ok(())
@@ -40,23 +77,27 @@ impl Listener {
                 _ = shutdown.recv() => {}
             }
         }
+        info!("listener stop accepting");
         drop(tx);
-        drop(notify_shutdown);
         let _ = rx.recv().await;
         info!("listener closed");
     }
 }
 
-pub fn run_server(mut server: Listener, shutdown: Shutdown) -> crate::Result<()> {
-    let thread_builder = std::thread::Builder::new().name(format!("net io"));
+pub fn run_server(
+    mut server: Listener,
+    worker_threads: usize,
+    shutdown: Shutdown,
+) -> crate::Result<()> {
+    let thread_builder = std::thread::Builder::new().name("net io".to_string());
     // Spawn it
     thread_builder.spawn(move || {
         let rt = tokio::runtime::Builder::new_multi_thread()
             .enable_all()
-            .worker_threads(4)
+            .worker_threads(worker_threads)
             .build()
             .unwrap();
-        _ = rt.block_on(async {
+        rt.block_on(async {
             server.run(shutdown).await;
         });
     })?;
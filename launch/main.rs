@@ -1,24 +1,96 @@
+use std::path::PathBuf;
+
 use clap::Parser;
-use re_core::{options::with_port, runtime};
+use re_core::{
+    config::{Config, LogConfig, LogFormat},
+    dispatcher::Context,
+    register_handler, runtime, Message,
+};
 use time::macros::format_description;
 use tokio::signal;
 use tracing_subscriber::{fmt::time::LocalTime, EnvFilter, FmtSubscriber};
 
+/// 命令行参数优先于环境变量和配置文件
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Flags {
-    #[arg(short, long, default_value_t = 7777)]
-    port: i32,
+    /// TOML 配置文件
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+    #[arg(long)]
+    host: Option<String>,
+    #[arg(short, long)]
+    port: Option<u16>,
+    #[arg(long)]
+    worker_threads: Option<usize>,
+    #[arg(long)]
+    tick_rate: Option<u32>,
+    #[arg(long)]
+    log_level: Option<String>,
+    /// full 或 compact
+    #[arg(long)]
+    log_format: Option<String>,
 }
 
-fn main() {
-    let subscriber = FmtSubscriber::builder()
-        .with_env_filter(EnvFilter::new("debug"))
+impl Flags {
+    fn overrides(&self) -> Vec<(&'static str, String)> {
+        let mut overrides = Vec::new();
+        // 字符串加引号，避免被当作其它 TOML 类型
+        let quote = |s: &String| format!("{:?}", s);
+        if let Some(host) = &self.host {
+            overrides.push(("server.host", quote(host)));
+        }
+        if let Some(port) = self.port {
+            overrides.push(("server.port", port.to_string()));
+        }
+        if let Some(worker_threads) = self.worker_threads {
+            overrides.push(("server.worker_threads", worker_threads.to_string()));
+        }
+        if let Some(tick_rate) = self.tick_rate {
+            overrides.push(("server.tick_rate", tick_rate.to_string()));
+        }
+        if let Some(level) = &self.log_level {
+            overrides.push(("log.level", quote(level)));
+        }
+        if let Some(format) = &self.log_format {
+            overrides.push(("log.format", quote(format)));
+        }
+        overrides
+    }
+}
+
+fn echo(ctx: &mut Context, message: Message) -> re_core::Result<()> {
+    if let Some(body) = message.body {
+        ctx.reply(Message::new(2, body));
+    }
+    Ok(())
+}
+
+register_handler!(1, echo);
+
+fn init_log(log: &LogConfig) {
+    let builder = FmtSubscriber::builder()
+        .with_env_filter(EnvFilter::new(&log.level))
         .with_timer(LocalTime::new(format_description!(
             "[year]-[month]-[day] [hour]:[minute]:[second].[subsecond digits:3]"
-        )))
-        .finish();
-    tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
+        )));
+    let res = match log.format {
+        LogFormat::Full => tracing::subscriber::set_global_default(builder.finish()),
+        LogFormat::Compact => tracing::subscriber::set_global_default(builder.compact().finish()),
+    };
+    res.expect("setting default subscriber failed");
+}
+
+fn main() {
     let args = Flags::parse();
-    runtime::run(&[with_port(args.port)], signal::ctrl_c());
+    let config = match Config::load(args.config.as_deref(), &args.overrides()) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("invalid config: {}", err);
+            std::process::exit(2);
+        }
+    };
+    init_log(&config.log);
+    let options = config.to_options().expect("config validated");
+    runtime::run_with(options, signal::ctrl_c());
 }
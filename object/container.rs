@@ -4,6 +4,7 @@ use tracing::warn;
 
 use crate::{
     game_object::GameObject,
+    lifecycle,
     object::{ClassType, Object},
     ObjectPtr, WeakObjectPtr,
 };
@@ -38,7 +39,7 @@ impl Container for Object {
 
         if cap < self.cap {
             // only empty can shrink
-            if self.children.len() > 0 {
+            if !self.children.is_empty() {
                 return false;
             }
             self.children.shrink_to(cap);
@@ -46,7 +47,7 @@ impl Container for Object {
             self.children.reserve(cap - self.children.len());
         }
         self.cap = cap;
-        return true;
+        true
     }
 
     fn child_count(&self) -> usize {
@@ -100,71 +101,18 @@ impl Container for Object {
 
     fn create_child(&mut self, entity: &str, cap: usize, pos: usize) -> Option<ObjectPtr> {
         assert!(self.get_factory().is_some());
-        if let Some(factory_ptr) = self.get_factory() {
-            let mut factory = factory_ptr.borrow_mut();
-            if let Some(new_object) = factory.create(entity, cap) {
-                if !self.add_child(new_object.clone(), pos) {
-                    factory.destroy(&new_object);
-                    return None;
-                }
-                new_object.borrow_mut().set_factory(&factory_ptr);
-                Object::created(&new_object);
-                return Some(new_object.clone());
-            }
-        }
-
-        None
+        let new_object = self.create_detached(entity, cap)?;
+        self.place_created(&new_object, pos).then_some(new_object)
     }
 
     fn add_child(&mut self, child: ObjectPtr, pos: usize) -> bool {
-        if child.borrow().is_deleted() {
-            warn!("object is delete");
-            return false;
-        }
-        assert!(!child.borrow().is_in_container());
-        if self.cap > 0 && self.child_num >= self.cap {
-            return false;
-        }
-        let index: usize;
-        let mut real_pos = pos;
-        if pos > 0 && self.cap > 0 {
-            if pos > self.cap {
-                return false;
+        match self.insert_child(&child, pos) {
+            Some(parent_changed) => {
+                self.entered(&child, parent_changed);
+                true
             }
-            let old_size = self.children.len();
-            if pos < old_size {
-                if self.children[pos - 1].is_none() {
-                    return false;
-                }
-            } else {
-                self.children.resize(pos, None);
-            }
-            index = pos - 1;
-        } else {
-            index = match self.find_child_container_free_index() {
-                Some(index) => index,
-                None => {
-                    return false;
-                }
-            };
-            real_pos = index + 1;
-        }
-        if self.children.len() == index {
-            self.children.push(Some(child.clone()));
-        } else {
-            self.children[index] = Some(child.clone());
+            None => false,
         }
-        self.child_num += 1;
-
-        {
-            let mut entity = child.borrow_mut();
-            entity.set_container_pos(real_pos);
-            if let Some(ptr) = &self.self_ptr {
-                entity.set_weak_parent(ptr.clone());
-            }
-        }
-        self.dirty = true;
-        true
     }
 
     fn remove_child(&mut self, child: &ObjectPtr) -> bool {
@@ -202,12 +150,12 @@ impl Container for Object {
             return false;
         }
         self.child_num -= 1;
-        {
-            let child = child.unwrap();
-            let mut mut_child = child.borrow_mut();
-            mut_child.set_container_pos(0);
-        }
-        self.dirty = true;
+        let child = child.unwrap();
+        child.borrow_mut().set_container_pos(0);
+        self.set_dirty();
+        lifecycle::fire(&child, |hooks, object| {
+            hooks.on_leave(object, self, index + 1)
+        });
         true
     }
 
@@ -236,3 +184,347 @@ impl Container for Object {
         self.container_pos > 0
     }
 }
+
+impl Object {
+    /// 把 `from` 中 `from_pos` 位置的子对象移到 `to` 的 `to_pos`
+    ///
+    /// `to_pos` 为 0 或者 `to` 不限容量时放到第一个空位。`from` 和 `to` 可以是同一个容器。
+    /// 失败时不做任何修改，成功时两个容器各标记一次脏，子对象同步一次位置变化。
+    pub fn move_child(from: &ObjectPtr, from_pos: usize, to: &ObjectPtr, to_pos: usize) -> bool {
+        let same = Rc::ptr_eq(from, to);
+        let Some(child) = from.borrow().child_at(from_pos) else {
+            return false;
+        };
+        if !Self::can_move(&child, to) {
+            return false;
+        }
+        let index = {
+            let to = to.borrow();
+            match to.target_index(to_pos) {
+                Some(index) => index,
+                // 同一个容器中原地不动
+                None if same && to_pos == from_pos => return true,
+                None => return false,
+            }
+        };
+        from.borrow_mut().take_slot(from_pos - 1);
+        to.borrow_mut().put_slot(&child, index);
+        Self::moved(from, to, &[(child, from_pos, from, to)]);
+        Self::flush_deferred(from);
+        true
+    }
+
+    /// 交换两个位置上的子对象，两个位置都必须有对象，可以在不同的容器中
+    ///
+    /// 只有一边有对象时使用 `move_child`。失败时不做任何修改。
+    pub fn swap_children(a: &ObjectPtr, a_pos: usize, b: &ObjectPtr, b_pos: usize) -> bool {
+        let (Some(child_a), Some(child_b)) =
+            (a.borrow().child_at(a_pos), b.borrow().child_at(b_pos))
+        else {
+            return false;
+        };
+        if Rc::ptr_eq(&child_a, &child_b) {
+            return true;
+        }
+        if !Self::can_move(&child_a, b) || !Self::can_move(&child_b, a) {
+            return false;
+        }
+        a.borrow_mut().take_slot(a_pos - 1);
+        b.borrow_mut().take_slot(b_pos - 1);
+        b.borrow_mut().put_slot(&child_a, b_pos - 1);
+        a.borrow_mut().put_slot(&child_b, a_pos - 1);
+        Self::moved(a, b, &[(child_a, a_pos, a, b), (child_b, b_pos, b, a)]);
+        Self::flush_deferred(a);
+        true
+    }
+
+    /// 创建还没有放入容器的对象，不执行回调，写入属性后由 `place_created` 放入
+    pub(crate) fn create_detached(&self, entity: &str, cap: usize) -> Option<ObjectPtr> {
+        let factory = self.get_factory()?;
+        let object = factory.borrow_mut().create(entity, cap)?;
+        object.borrow_mut().set_factory(&factory);
+        Some(object)
+    }
+
+    /// 放入 `create_detached` 创建的对象，执行 `on_create` 和进入容器的回调
+    ///
+    /// 放不下时销毁对象，返回 false。
+    pub(crate) fn place_created(&mut self, child: &ObjectPtr, pos: usize) -> bool {
+        let Some(parent_changed) = self.insert_child(child, pos) else {
+            if let Some(factory) = self.get_factory() {
+                factory.borrow_mut().destroy(child);
+            }
+            return false;
+        };
+        lifecycle::fire(child, |hooks, object| hooks.on_create(object));
+        self.entered(child, parent_changed);
+        true
+    }
+
+    /// 1 开始的位置上的子对象
+    pub fn child_at(&self, pos: usize) -> Option<ObjectPtr> {
+        self.children.get(pos.checked_sub(1)?)?.clone()
+    }
+
+    /// `object` 是否是 `ancestor` 或者它的子孙
+    pub fn is_descendant(object: &ObjectPtr, ancestor: &ObjectPtr) -> bool {
+        let mut current = Some(object.clone());
+        while let Some(object) = current {
+            if Rc::ptr_eq(&object, ancestor) {
+                return true;
+            }
+            current = object.borrow().get_parent();
+        }
+        false
+    }
+
+    fn can_move(child: &ObjectPtr, to: &ObjectPtr) -> bool {
+        {
+            let child = child.borrow();
+            if child.is_deleted() || child.destroying {
+                return false;
+            }
+        }
+        // 不能放到自己或者自己的子对象中
+        !Self::is_descendant(to, child)
+    }
+
+    /// 放入 `pos` 的下标，`pos` 为 0 或者不限容量时找第一个空位
+    pub(crate) fn target_index(&self, pos: usize) -> Option<usize> {
+        if pos > 0 && self.cap > 0 {
+            if pos > self.cap || self.child_at(pos).is_some() {
+                return None;
+            }
+            return Some(pos - 1);
+        }
+        self.find_child_container_free_index()
+    }
+
+    fn take_slot(&mut self, index: usize) -> Option<ObjectPtr> {
+        let child = self.children.get_mut(index)?.take()?;
+        self.child_num -= 1;
+        child.borrow_mut().set_container_pos(0);
+        Some(child)
+    }
+
+    /// 返回父对象是否改变
+    fn put_slot(&mut self, child: &ObjectPtr, index: usize) -> bool {
+        if self.children.len() <= index {
+            self.children.resize(index + 1, None);
+        }
+        self.children[index] = Some(child.clone());
+        self.child_num += 1;
+
+        let mut entity = child.borrow_mut();
+        entity.set_container_pos(index + 1);
+        let parent_changed = match (&entity.parent, &self.self_ptr) {
+            (Some(old), Some(new)) => !old.ptr_eq(new),
+            (old, new) => old.is_some() != new.is_some(),
+        };
+        if let Some(ptr) = &self.self_ptr {
+            entity.set_weak_parent(ptr.clone());
+        }
+        parent_changed
+    }
+
+    /// 移动完成后标记脏和位置变化，然后执行回调
+    fn moved(a: &ObjectPtr, b: &ObjectPtr, moves: &[(ObjectPtr, usize, &ObjectPtr, &ObjectPtr)]) {
+        a.borrow_mut().set_dirty();
+        if !Rc::ptr_eq(a, b) {
+            b.borrow_mut().set_dirty();
+        }
+        for (child, _, _, _) in moves {
+            child.borrow_mut().moved = true;
+        }
+        for (child, old_pos, from, to) in moves {
+            lifecycle::fire(child, |hooks, object| {
+                hooks.on_leave(object, &from.borrow(), *old_pos)
+            });
+            let parent_changed = !Rc::ptr_eq(from, to);
+            to.borrow().entered(child, parent_changed);
+        }
+    }
+
+    /// 放入容器，不执行回调，返回父对象是否改变
+    fn insert_child(&mut self, child: &ObjectPtr, pos: usize) -> Option<bool> {
+        if child.borrow().is_deleted() {
+            warn!("object is delete");
+            return None;
+        }
+        assert!(!child.borrow().is_in_container());
+        let index = self.target_index(pos)?;
+        let parent_changed = self.put_slot(child, index);
+        self.set_dirty();
+        Some(parent_changed)
+    }
+
+    fn entered(&self, child: &ObjectPtr, parent_changed: bool) {
+        let pos = child.borrow().get_container_pos();
+        lifecycle::fire(child, |hooks, object| {
+            hooks.on_enter(object, self, pos);
+            if parent_changed {
+                hooks.on_parent_changed(object, self);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        game_scene::GameScene,
+        replication::{Replicator, RECORD_MOVE},
+        testing::{self, TestBox, TestItem},
+    };
+
+    /// 解构时需要保留 `_scene`，否则 `Factory` 会被释放
+    struct Fixture {
+        _scene: GameScene,
+        player: ObjectPtr,
+        bag: ObjectPtr,
+        equip: ObjectPtr,
+    }
+
+    /// 4 格的背包第 1 格有物品，2 格的装备栏第 1 格有物品
+    fn fixture() -> Fixture {
+        let scene = testing::scene();
+        let player = testing::player(&scene);
+        let bag = Object::create(&player, TestBox::ClassName(), 4, 0).unwrap();
+        let equip = Object::create(&player, TestBox::ClassName(), 2, 0).unwrap();
+        testing::item(&bag, 1, "a");
+        testing::item(&equip, 1, "c");
+        bag.borrow_mut().clear_dirty();
+        equip.borrow_mut().clear_dirty();
+        Fixture {
+            _scene: scene,
+            player,
+            bag,
+            equip,
+        }
+    }
+
+    fn name_at(parent: &ObjectPtr, pos: usize) -> Option<String> {
+        let child = parent.borrow().child_at(pos)?;
+        Some(Object::model_map(&child, |i: &TestItem| i.name.clone()))
+    }
+
+    fn parent_of(child: &ObjectPtr) -> ObjectPtr {
+        child.borrow().get_parent().unwrap()
+    }
+
+    #[test]
+    fn test_create_child() {
+        let Fixture { _scene, bag, .. } = fixture();
+        assert!(Object::create(&bag, TestItem::ClassName(), 0, 1).is_none());
+        assert!(Object::create(&bag, TestItem::ClassName(), 0, 5).is_none());
+        let item = Object::create(&bag, TestItem::ClassName(), 0, 0).unwrap();
+        assert_eq!(item.borrow().get_container_pos(), 2);
+        assert!(Rc::ptr_eq(&parent_of(&item), &bag));
+        assert_eq!(bag.borrow().child_count(), 2);
+        assert!(bag.borrow().dirty());
+        assert!(bag.borrow().child_at(0).is_none());
+        // 有子对象时不能缩小容量
+        assert!(!bag.borrow_mut().set_capcity(2));
+        assert!(bag.borrow_mut().set_capcity(8));
+    }
+
+    #[test]
+    fn test_move_fails_without_change() {
+        let Fixture {
+            _scene,
+            player,
+            bag,
+            equip,
+        } = fixture();
+        let mut replicator = Replicator::default();
+        replicator.show(1, &bag.borrow().child_at(1).unwrap());
+        replicator.collect();
+
+        // 空位置、被占用的位置、超出容量
+        assert!(!Object::move_child(&bag, 2, &equip, 2));
+        assert!(!Object::move_child(&bag, 1, &equip, 1));
+        assert!(!Object::move_child(&bag, 1, &equip, 3));
+        // 不能放到自己的子对象中
+        let pos = bag.borrow().get_container_pos();
+        assert!(!Object::move_child(&player, pos, &bag, 0));
+        assert_eq!(name_at(&bag, 1).as_deref(), Some("a"));
+        assert!(!bag.borrow().dirty() && !equip.borrow().dirty());
+        assert!(replicator.collect().is_empty());
+    }
+
+    #[test]
+    fn test_move() {
+        let Fixture {
+            _scene, bag, equip, ..
+        } = fixture();
+        let a = bag.borrow().child_at(1).unwrap();
+        let mut replicator = Replicator::default();
+        replicator.show(1, &a);
+        replicator.collect();
+
+        assert!(Object::move_child(&bag, 1, &equip, 2));
+        assert_eq!(a.borrow().get_container_pos(), 2);
+        assert!(Rc::ptr_eq(&parent_of(&a), &equip));
+        assert_eq!(bag.borrow().child_count(), 0);
+        assert_eq!(equip.borrow().child_count(), 2);
+        assert!(bag.borrow().dirty() && equip.borrow().dirty());
+        let mut expect = vec![RECORD_MOVE];
+        expect.extend_from_slice(&a.borrow().uid().to_le_bytes());
+        expect.extend_from_slice(&equip.borrow().uid().to_le_bytes());
+        expect.extend_from_slice(&2u16.to_le_bytes());
+        assert_eq!(replicator.collect(), vec![(1, expect)]);
+        assert!(replicator.collect().is_empty());
+
+        // 容器已满
+        testing::item(&bag, 1, "b");
+        assert!(!Object::move_child(&bag, 1, &equip, 0));
+    }
+
+    #[test]
+    fn test_move_in_same_container() {
+        let Fixture { _scene, bag, .. } = fixture();
+        assert!(Object::move_child(&bag, 1, &bag, 4));
+        assert_eq!(name_at(&bag, 4).as_deref(), Some("a"));
+        assert!(bag.borrow().child_at(1).is_none());
+        assert_eq!(bag.borrow().child_count(), 1);
+        // 原地不动也算成功
+        assert!(Object::move_child(&bag, 4, &bag, 4));
+        assert_eq!(name_at(&bag, 4).as_deref(), Some("a"));
+    }
+
+    #[test]
+    fn test_swap() {
+        let Fixture {
+            _scene, bag, equip, ..
+        } = fixture();
+        assert!(Object::swap_children(&bag, 1, &equip, 1));
+        assert_eq!(name_at(&bag, 1).as_deref(), Some("c"));
+        assert_eq!(name_at(&equip, 1).as_deref(), Some("a"));
+        assert!(Rc::ptr_eq(
+            &parent_of(&bag.borrow().child_at(1).unwrap()),
+            &bag
+        ));
+        assert!(bag.borrow().dirty() && equip.borrow().dirty());
+
+        // 有一边是空位时失败
+        assert!(!Object::swap_children(&bag, 2, &equip, 1));
+        assert!(Object::swap_children(&bag, 1, &bag, 1));
+        assert_eq!(name_at(&bag, 1).as_deref(), Some("c"));
+    }
+
+    #[test]
+    fn test_is_descendant() {
+        let Fixture {
+            _scene,
+            player,
+            bag,
+            equip,
+        } = fixture();
+        let a = bag.borrow().child_at(1).unwrap();
+        assert!(Object::is_descendant(&a, &player));
+        assert!(Object::is_descendant(&bag, &bag));
+        assert!(!Object::is_descendant(&a, &equip));
+        assert!(!Object::is_descendant(&player, &a));
+    }
+}
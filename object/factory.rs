@@ -1,8 +1,21 @@
-use std::{cell::RefCell, collections::VecDeque, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    rc::{Rc, Weak},
+};
 
 use tracing::{debug, warn};
 
-use crate::{game_object::GameObject, object::Object, registry::Registry, ObjectPtr};
+use crate::{
+    game_object::GameObject,
+    handle::{ObjectHandle, MAX_SERIAL},
+    object::Object,
+    observer::{Observers, ObserversPtr},
+    query::{Indexes, IndexesPtr},
+    registry::Registry,
+    timer::{Timers, TimersPtr},
+    FactoryPtr, ObjectPtr, WeakObjectPtr,
+};
 
 #[derive(Debug)]
 pub struct Factory {
@@ -11,8 +24,13 @@ pub struct Factory {
     free_list: VecDeque<usize>,
     deletes: VecDeque<ObjectPtr>,
     used_size: usize,
-    serial: usize,
+    serial: u32,
     owner: ObjectPtr,
+    timers: TimersPtr,
+    observers: ObserversPtr,
+    indexes: IndexesPtr,
+    // 修改留到稍后处理的对象，见 `Object::defer_changes`
+    deferred: Vec<WeakObjectPtr>,
 }
 
 impl Drop for Factory {
@@ -24,41 +42,66 @@ impl Drop for Factory {
 
 impl Factory {
     pub fn new(registry: Rc<Registry>, owner: ObjectPtr) -> Self {
+        let indexes = Indexes::new(&registry);
         let mut s = Self {
-            registry: registry,
+            registry,
             objects: Vec::with_capacity(16),
             free_list: VecDeque::with_capacity(16),
             deletes: VecDeque::new(),
             used_size: 1, // ignore 0
             serial: 0,
-            owner: owner,
+            owner,
+            timers: Rc::new(RefCell::new(Timers::new())),
+            observers: Rc::new(RefCell::new(Observers::new())),
+            indexes: Rc::new(RefCell::new(indexes)),
+            deferred: Vec::new(),
         };
         s.objects.resize(16, None);
         s
     }
 
+    pub fn get_registry(&self) -> Rc<Registry> {
+        self.registry.clone()
+    }
+
     pub fn get_owner(&self) -> ObjectPtr {
         self.owner.clone()
     }
 
+    pub fn get_timers(&self) -> TimersPtr {
+        self.timers.clone()
+    }
+
+    pub fn get_observers(&self) -> ObserversPtr {
+        self.observers.clone()
+    }
+
+    pub fn get_indexes(&self) -> IndexesPtr {
+        self.indexes.clone()
+    }
+
     pub fn init(&mut self) {
         self.objects[0] = Some(self.owner.clone());
-        self.owner.borrow_mut().set_uid(1 << 32);
+        let uid = ObjectHandle::new(0, 1).uid();
+        let mut owner = self.owner.borrow_mut();
+        owner.set_uid(uid);
+        owner.game_model.borrow_mut().changes().track(
+            uid,
+            owner.model.class_name,
+            &self.observers,
+            &self.indexes,
+        );
     }
 
     pub fn create(&mut self, ent: &str, cap: usize) -> Option<ObjectPtr> {
-        let new_data = self.registry.create_object(ent);
-        if new_data.is_none() {
-            return None;
-        }
-        let new_obj;
-        if cap == 0 {
-            new_obj = Rc::new(RefCell::new(Object::new(new_data.unwrap())));
+        let new_data = self.registry.create_object(ent)?;
+        let new_obj = if cap == 0 {
+            Rc::new(RefCell::new(Object::new(new_data)))
         } else {
-            new_obj = Rc::new(RefCell::new(Object::new_with_cap(new_data.unwrap(), cap)));
-        }
+            Rc::new(RefCell::new(Object::new_with_cap(new_data, cap)))
+        };
         let index;
-        if self.free_list.len() == 0 {
+        if self.free_list.is_empty() {
             if self.used_size == self.objects.len() {
                 if self.used_size > 0x1000000 {
                     panic!("too many objects created, abort!");
@@ -71,18 +114,24 @@ impl Factory {
         } else {
             index = self.free_list.pop_back().unwrap();
         }
-        if self.serial >= 0x7FFFFFFF {
+        if self.serial >= MAX_SERIAL {
             self.serial = 1;
         } else {
             self.serial += 1;
         }
-        let mut id = (self.serial << 32) as u64;
-        id += index as u64;
+        let id = ObjectHandle::new(index as u32, self.serial).uid();
 
         Object::object_map_mut(&new_obj, |obj| {
             obj.set_ptr(&new_obj);
             obj.set_uid(id);
+            obj.game_model.borrow_mut().changes().track(
+                id,
+                obj.model.class_name,
+                &self.observers,
+                &self.indexes,
+            );
         });
+        self.indexes.borrow_mut().insert_object(&new_obj.borrow());
         let ret = new_obj.clone();
         self.objects[index] = Some(new_obj);
         Some(ret)
@@ -101,7 +150,7 @@ impl Factory {
             id = entity.uid();
         }
 
-        let index = (id & 0x7FFFFFFF) as usize;
+        let index = ObjectHandle::from_uid(id).index() as usize;
         if index > self.objects.len() {
             panic!("object id error");
         }
@@ -116,9 +165,12 @@ impl Factory {
                 panic!("object is null");
             }
         }
+        self.indexes.borrow_mut().remove_object(&obj_ptr.borrow());
         obj_ptr.borrow_mut().delete();
         self.objects[index] = None;
         self.free_list.push_back(index);
+        self.timers.borrow_mut().cancel_object(id);
+        self.observers.borrow_mut().remove_object(id);
     }
 
     /// 设置删除标志
@@ -134,7 +186,7 @@ impl Factory {
                 id = entity.uid();
             }
 
-            let index = (id & 0x7FFFFFFF) as usize;
+            let index = ObjectHandle::from_uid(id).index() as usize;
             if index > self.objects.len() {
                 panic!("object id error");
             }
@@ -149,28 +201,54 @@ impl Factory {
                     panic!("object is null");
                 }
             }
+            self.indexes.borrow_mut().remove_object(&obj_ptr.borrow());
             obj_ptr.borrow_mut().delete();
             self.objects[index] = None;
             self.free_list.push_back(index);
+            self.timers.borrow_mut().cancel_object(id);
+            self.observers.borrow_mut().remove_object(id);
         }
         self.deletes.push_back(obj_ptr.clone());
     }
 
     /// 立即销毁标志为删除的对象
     pub fn clear_deleted(&mut self) {
-        if self.deletes.len() == 0 {
+        if self.deletes.is_empty() {
             return;
         }
 
-        while self.deletes.len() > 0 {
+        while !self.deletes.is_empty() {
             self.deletes.pop_front();
         }
     }
 
+    /// 记录修改需要稍后处理的对象
+    pub fn defer_changes(&mut self, object: &ObjectPtr) {
+        let ptr = Rc::as_ptr(object);
+        if !self.deferred.iter().any(|weak| weak.as_ptr() == ptr) {
+            self.deferred.push(Rc::downgrade(object));
+        }
+    }
+
+    /// 处理延迟的修改，返回处理的对象数
+    ///
+    /// 只处理调用时已记录的对象，实体仍被借用的对象留到下一次。
+    pub fn flush_deferred(this: &FactoryPtr) -> usize {
+        let deferred = std::mem::take(&mut this.borrow_mut().deferred);
+        let mut count = 0;
+        for object in deferred.iter().filter_map(Weak::upgrade) {
+            if !object.borrow().is_deleted() {
+                Object::flush_changes(&object);
+                count += 1;
+            }
+        }
+        count
+    }
+
     /// 查找对象
     pub fn find(&self, uid: u64) -> Option<ObjectPtr> {
-        let index = (uid & 0x7FFFFFFF) as usize;
-        if index > self.objects.len() {
+        let index = ObjectHandle::from_uid(uid).index() as usize;
+        if index >= self.objects.len() {
             return None;
         }
 
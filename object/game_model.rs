@@ -1,23 +1,178 @@
 use std::any::Any;
+use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
+use std::rc::{Rc, Weak};
 
-use crate::object::Object;
+use crate::{
+    lifecycle::Lifecycle,
+    object::ClassType,
+    observer::{ObserversPtr, WeakObserversPtr},
+    query::{IndexModel, Indexes, IndexesPtr},
+    stack::StackModel,
+    value::{Value, ValueError},
+};
 
+/// 实体数据，由 `#[def_entity]` 生成
+///
+/// setter 只把修改记录在实体自己的 `Changes` 中，由 `Object::flush_changes` 取出处理。
+/// 直接借用 `game_model` 修改属性后需要调用 `Object::flush_changes`。
 pub trait GameModel: Debug {
     fn get_model(&self) -> Model;
-    fn set_gameobj(&mut self, go: *mut Object);
-    fn get_attr_by_name<'a>(&'a self, attr: &str) -> Option<&'a dyn Any>;
+    /// 记录的修改
+    fn changes(&mut self) -> &mut Changes;
+    fn get_attr_by_name(&self, attr: &str) -> Option<&dyn Any>;
     fn set_attr_by_name(&mut self, attr: &str, val: &dyn Any) -> bool;
-    fn get_attr_by_index<'a>(&'a self, index: u32) -> Option<&'a dyn Any>;
+    fn get_attr_by_index(&self, index: u32) -> Option<&dyn Any>;
     fn set_attr_by_index(&mut self, index: u32, val: &dyn Any) -> bool;
-    fn get_any<'a>(&'a self) -> &'a dyn Any;
-    fn get_mut_any<'a>(&'a mut self) -> &'a mut dyn Any;
+    /// 只有带 save、replicated、索引或堆叠标记的属性有值，其它属性返回 None
+    fn get_value(&self, index: u32) -> Option<Value>;
+    /// 通过属性的 setter 修改，会记录修改和脏标记
+    fn set_value(&mut self, index: u32, value: &Value) -> Result<(), ValueError>;
+    fn get_any(&self) -> &dyn Any;
+    fn get_mut_any(&mut self) -> &mut dyn Any;
+
+    /// `#[def_entity(hooks)]` 的实体返回自己
+    fn lifecycle(&mut self) -> Option<&mut dyn Lifecycle> {
+        None
+    }
+
+    fn encode_attr(&self, index: u32, buf: &mut Vec<u8>) -> bool {
+        match self.get_value(index) {
+            Some(value) => {
+                value.encode(buf);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn decode_attr(&mut self, index: u32, buf: &mut &[u8]) -> Result<(), ValueError> {
+        let value = Value::decode(buf)?;
+        self.set_value(index, &value)
+    }
+}
+
+/// setter 记录的一次属性修改
+///
+/// 属性有观察者时才保存修改前后的值，其它修改只记录下标，处理时读取当前值。
+pub struct Change {
+    pub index: u32,
+    pub values: Option<(Box<dyn Any>, Box<dyn Any>)>,
+}
+
+/// 实体的修改缓冲
+///
+/// 对象由 `Factory` 创建时通过 `track` 关联观察者，之前的修改都只记录下标。
+#[derive(Default)]
+pub struct Changes {
+    changes: Vec<Change>,
+    tracker: Option<Tracker>,
+}
+
+struct Tracker {
+    uid: u64,
+    class_name: &'static str,
+    observers: WeakObserversPtr,
+    indexes: Weak<RefCell<Indexes>>,
+}
+
+impl Changes {
+    pub fn track(
+        &mut self,
+        uid: u64,
+        class_name: &'static str,
+        observers: &ObserversPtr,
+        indexes: &IndexesPtr,
+    ) {
+        self.tracker = Some(Tracker {
+            uid,
+            class_name,
+            observers: Rc::downgrade(observers),
+            indexes: Rc::downgrade(indexes),
+        });
+    }
+
+    /// 唯一索引中是否有其它对象使用这个值，由唯一属性的 setter 检查
+    pub fn is_taken(&self, attr: &'static str, value: &Value) -> bool {
+        let Some(tracker) = &self.tracker else {
+            return false;
+        };
+        let Some(indexes) = tracker.indexes.upgrade() else {
+            return false;
+        };
+        let is_taken = indexes
+            .try_borrow()
+            .is_ok_and(|i| i.is_taken(tracker.class_name, attr, value, tracker.uid));
+        is_taken
+    }
+
+    /// 属性是否有观察者，由 setter 决定是否保存修改前后的值
+    pub fn watched(&self, attr: &'static str) -> bool {
+        let Some(tracker) = &self.tracker else {
+            return false;
+        };
+        let Some(observers) = tracker.observers.upgrade() else {
+            return false;
+        };
+        // 观察者列表正被修改时保守处理
+        observers
+            .try_borrow()
+            .map_or(true, |o| o.watches(tracker.class_name, tracker.uid, attr))
+    }
+
+    /// 只记录下标，同一个属性没有处理之前只记录一次
+    pub fn push(&mut self, index: u32) {
+        if !self
+            .changes
+            .iter()
+            .any(|change| change.index == index && change.values.is_none())
+        {
+            self.changes.push(Change {
+                index,
+                values: None,
+            });
+        }
+    }
+
+    pub fn push_values<T: 'static>(&mut self, index: u32, old: T, new: T) {
+        self.changes.push(Change {
+            index,
+            values: Some((Box::new(old), Box::new(new))),
+        });
+    }
+
+    pub fn take(&mut self) -> Vec<Change> {
+        std::mem::take(&mut self.changes)
+    }
+
+    pub fn len(&self) -> usize {
+        self.changes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+impl Debug for Changes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(self.changes.iter().map(|change| change.index))
+            .finish()
+    }
 }
 
 #[derive(Default, Debug, Clone)]
 pub struct Model {
     pub class_name: &'static str,
+    /// 数据版本，由 `#[def_entity(version = N)]` 指定，默认为 0
+    pub version: u32,
+    pub class_type: ClassType,
+    /// 可堆叠的实体才有
+    pub stack: Option<StackModel>,
+    /// 有索引的属性
+    pub indexes: Vec<IndexModel>,
     pub attrs: Vec<&'static str>,
     pub index: HashMap<&'static str, u32>,
     pub saves_index: Vec<u32>,
@@ -43,12 +198,12 @@ impl Model {
         let mut reps_index = Vec::new();
         let mut saves_set = HashSet::new();
         let mut reps_set = HashSet::new();
-        saves.iter().enumerate().for_each(|(_, &attr)| {
+        saves.iter().for_each(|&attr| {
             let idx = index[attr];
             saves_index.push(idx);
             saves_set.insert(idx);
         });
-        reps.iter().enumerate().for_each(|(_, &attr)| {
+        reps.iter().for_each(|&attr| {
             let idx = index[attr];
             reps_index.push(idx);
             reps_set.insert(idx);
@@ -56,8 +211,12 @@ impl Model {
 
         Self {
             class_name,
-            attrs: attrs,
-            index: index,
+            version: 0,
+            class_type: ClassType::None,
+            stack: None,
+            indexes: Vec::new(),
+            attrs,
+            index,
             saves_index,
             reps_index,
             saves,
@@ -67,3 +226,136 @@ impl Model {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use re_ops::def_entity;
+
+    use crate::{
+        game_object::GameObject,
+        object::Object,
+        observer::{AttrChange, Dispatch},
+        registry::Registry,
+        testing,
+    };
+
+    #[def_entity]
+    struct TestMage {
+        #[attr(save)]
+        level: i32,
+        #[attr(save)]
+        mana: u64,
+    }
+
+    #[test]
+    fn test_model_without_object() {
+        // 没有对象的实体也可以修改，修改留在缓冲中
+        let registry = Registry::init();
+        let model = registry.create_object(TestMage::ClassName()).unwrap();
+        let mut model = model.borrow_mut();
+        let mage = model.get_mut_any().downcast_mut::<TestMage>().unwrap();
+        mage.set_level(3);
+        mage.set_level(4);
+        let changes = model.changes().take();
+        // 没有观察者，同一个属性只记录一次下标
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].index, 1);
+        assert!(changes[0].values.is_none());
+        assert!(model.changes().is_empty());
+    }
+
+    #[test]
+    fn test_values_only_when_watched() {
+        let scene = testing::scene();
+        let mage = scene.create_in_scene(TestMage::ClassName(), 0).unwrap();
+        let model = mage.borrow().game_model.clone();
+        let set = |level: i32, mana: u64| {
+            let mut model = model.borrow_mut();
+            let m = model.get_mut_any().downcast_mut::<TestMage>().unwrap();
+            m.set_level(level);
+            m.set_mana(mana);
+            model.changes().take()
+        };
+        assert!(set(1, 1).iter().all(|change| change.values.is_none()));
+
+        scene.observers().borrow_mut().on_object(
+            mage.borrow().handle(),
+            "level",
+            Dispatch::EndOfTick,
+            |_: &AttrChange<i32>| {},
+        );
+        let changes = set(2, 2);
+        let (old, new) = changes[0].values.as_ref().unwrap();
+        assert_eq!(old.downcast_ref::<i32>(), Some(&1));
+        assert_eq!(new.downcast_ref::<i32>(), Some(&2));
+        assert!(changes[1].values.is_none());
+    }
+
+    #[test]
+    fn test_flush_after_direct_borrow() {
+        let scene = testing::scene();
+        let mage = scene.create_in_scene(TestMage::ClassName(), 0).unwrap();
+        let model = mage.borrow().game_model.clone();
+        model
+            .borrow_mut()
+            .get_mut_any()
+            .downcast_mut::<TestMage>()
+            .unwrap()
+            .set_mana(5);
+        // 直接借用实体修改后需要处理记录的修改
+        assert!(!mage.borrow().dirty());
+        Object::flush_changes(&mage);
+        assert!(mage.borrow().dirty());
+    }
+
+    #[test]
+    fn test_flush_while_borrowed() {
+        let scene = testing::scene();
+        let mage = scene.create_in_scene(TestMage::ClassName(), 0).unwrap();
+        let model = mage.borrow().game_model.clone();
+        let mut guard = model.borrow_mut();
+        guard
+            .get_mut_any()
+            .downcast_mut::<TestMage>()
+            .unwrap()
+            .set_mana(5);
+        // 实体正被借用，修改留到帧末尾处理
+        Object::flush_changes(&mage);
+        assert!(!mage.borrow().dirty());
+        drop(guard);
+        assert_eq!(scene.flush_changes(), 1);
+        assert!(mage.borrow().dirty());
+        assert_eq!(scene.flush_changes(), 0);
+    }
+
+    #[test]
+    fn test_observer_reenters_object() {
+        let scene = testing::scene();
+        let mage = scene.create_in_scene(TestMage::ClassName(), 0).unwrap();
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let log = seen.clone();
+        // 立即执行的观察者可以读取和修改同一个对象
+        scene.observers().borrow_mut().on_class(
+            TestMage::ClassName(),
+            "level",
+            Dispatch::Immediate,
+            move |change: &AttrChange<i32>| {
+                let mana = Object::model_map(change.object, |m: &TestMage| m.mana);
+                log.borrow_mut()
+                    .push((*change.new, mana, change.object.borrow().dirty()));
+                if *change.new > 10 {
+                    Object::model_map_mut(change.object, |m: &mut TestMage| m.set_level(10));
+                }
+            },
+        );
+        Object::model_map_mut(&mage, |m: &mut TestMage| {
+            m.set_level(5);
+            m.set_mana(7);
+        });
+        Object::model_map_mut(&mage, |m: &mut TestMage| m.set_level(20));
+        assert_eq!(*seen.borrow(), [(5, 7, true), (20, 7, true), (10, 7, true)]);
+        Object::model_map(&mage, |m: &TestMage| assert_eq!(m.level, 10));
+    }
+}
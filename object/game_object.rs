@@ -1,9 +1,10 @@
-use std::{any::Any, rc::Rc};
+use std::rc::Rc;
 
-use tracing::{debug, warn};
+use tracing::warn;
 
 use crate::{
     container::Container,
+    lifecycle,
     object::{ClassType, Object},
     FactoryPtr, ObjectPtr,
 };
@@ -22,24 +23,28 @@ pub trait GameObject: Container {
     fn is_deleted(&self) -> bool;
     fn delete(&mut self);
     fn dirty(&self) -> bool;
+    fn set_dirty(&mut self);
     fn clear_dirty(&mut self);
+    /// 每次标记为脏时加一，用来判断存盘期间是否又有修改
+    fn save_version(&self) -> u64;
     fn modify(&self) -> bool;
     fn clear_modify(&mut self);
-    fn get_modify<'a>(&'a self) -> &'a Vec<u32>;
-    fn get_attrs<'a>(&'a self) -> &'a Vec<&str>;
-    fn save_attrs<'a>(&'a self) -> &'a Vec<&str>;
-    fn rep_attrs<'a>(&'a self) -> &'a Vec<&str>;
+    fn get_modify(&self) -> &Vec<u32>;
+    fn get_attrs(&self) -> &Vec<&'static str>;
+    fn save_attrs(&self) -> &Vec<&'static str>;
+    fn rep_attrs(&self) -> &Vec<&'static str>;
     fn save_attrs_index(&self) -> &Vec<u32>;
     fn rep_attrs_index(&self) -> &Vec<u32>;
     fn get_attr_count(&self) -> u32;
-    fn get_attr_name<'a>(&'a self, index: u32) -> Option<&'a str>;
+    fn get_attr_name(&self, index: u32) -> Option<&str>;
     fn get_attr_index(&self, attr: &str) -> Option<u32>;
-    fn change_attr(&mut self, index: u32, old: &dyn Any);
+    /// 属性修改后更新脏标记和同步记录，由 `Object::flush_changes` 调用
+    fn change_attr(&mut self, index: u32);
 }
 
 impl GameObject for Object {
     fn set_ptr(&mut self, self_ptr: &ObjectPtr) {
-        self.self_ptr = Some(Rc::downgrade(&self_ptr));
+        self.self_ptr = Some(Rc::downgrade(self_ptr));
     }
 
     fn set_factory(&mut self, factory: &FactoryPtr) {
@@ -74,6 +79,7 @@ impl GameObject for Object {
             return;
         }
         child.borrow_mut().destroying = true;
+        lifecycle::fire(child, |hooks, object| hooks.on_destroy(object));
         let in_container = child.borrow().is_in_container();
         if in_container {
             self.remove_child(child);
@@ -82,7 +88,7 @@ impl GameObject for Object {
         child.borrow_mut().destroy_children();
 
         if let Some(f) = self.get_factory() {
-            f.borrow_mut().delete(&child);
+            f.borrow_mut().delete(child);
         }
     }
 
@@ -119,31 +125,40 @@ impl GameObject for Object {
         self.dirty
     }
 
+    fn set_dirty(&mut self) {
+        self.dirty = true;
+        self.save_version += 1;
+    }
+
     fn clear_dirty(&mut self) {
         self.dirty = false;
     }
 
+    fn save_version(&self) -> u64 {
+        self.save_version
+    }
+
     fn modify(&self) -> bool {
-        self.modify_attrs.len() > 0
+        !self.modify_attrs.is_empty()
     }
 
     fn clear_modify(&mut self) {
         self.modify_attrs.clear();
     }
 
-    fn get_modify<'a>(&'a self) -> &'a Vec<u32> {
+    fn get_modify(&self) -> &Vec<u32> {
         &self.modify_attrs
     }
 
-    fn get_attrs<'a>(&'a self) -> &'a Vec<&str> {
+    fn get_attrs(&self) -> &Vec<&'static str> {
         &self.model.attrs
     }
 
-    fn save_attrs<'a>(&'a self) -> &'a Vec<&str> {
+    fn save_attrs(&self) -> &Vec<&'static str> {
         &self.model.saves
     }
 
-    fn rep_attrs<'a>(&'a self) -> &'a Vec<&str> {
+    fn rep_attrs(&self) -> &Vec<&'static str> {
         &self.model.reps
     }
 
@@ -160,26 +175,19 @@ impl GameObject for Object {
     }
 
     fn get_attr_index(&self, attr: &str) -> Option<u32> {
-        match self.model.index.get(attr) {
-            Some(&i) => Some(i),
-            None => None,
-        }
+        self.model.index.get(attr).copied()
     }
 
-    fn get_attr_name<'a>(&'a self, index: u32) -> Option<&'a str> {
-        match self.model.attrs.get(index as usize) {
-            Some(&attr) => Some(attr),
-            None => None,
-        }
+    fn get_attr_name(&self, index: u32) -> Option<&str> {
+        self.model.attrs.get(index as usize).copied()
     }
 
-    fn change_attr(&mut self, index: u32, old: &dyn Any) {
+    fn change_attr(&mut self, index: u32) {
         if self.model.saves_set.contains(&index) {
-            self.dirty = true;
+            self.set_dirty();
         }
-        if self.model.reps_set.contains(&index) {
+        if self.model.reps_set.contains(&index) && !self.modify_attrs.contains(&index) {
             self.modify_attrs.push(index);
         }
-        debug!("old:{:?}\n", old);
     }
 }
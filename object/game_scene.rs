@@ -1,8 +1,17 @@
-use std::{cell::RefCell, rc::Rc};
+use std::{cell::RefCell, rc::Rc, time::Duration};
 
 use crate::{
-    container::Container, factory::Factory, game_object::GameObject, object::Object,
-    registry::Registry, FactoryPtr, ObjectPtr,
+    factory::Factory,
+    game_object::GameObject,
+    handle::ObjectHandle,
+    lifecycle,
+    object::Object,
+    observer::{Observers, ObserversPtr},
+    query::Query,
+    registry::Registry,
+    snapshot::{Snapshot, SnapshotError},
+    timer::{Timers, TimersPtr},
+    FactoryPtr, ObjectPtr,
 };
 
 pub struct GameScene {
@@ -19,9 +28,11 @@ impl GameScene {
         let scene = Rc::new(RefCell::new(Object::new(scene_model.unwrap())));
         let factory = Rc::new(RefCell::new(Factory::new(registry, scene.clone())));
 
+        scene.borrow_mut().set_ptr(&scene);
         scene.borrow_mut().set_factory(&factory);
-        Object::created(&scene);
         factory.borrow_mut().init();
+        lifecycle::fire(&scene, |hooks, object| hooks.on_create(object));
+        Factory::flush_deferred(&factory);
 
         Some(Self {
             scene_object: scene,
@@ -36,7 +47,58 @@ impl GameScene {
         self.factory.borrow_mut().clear_deleted();
     }
 
+    pub fn timers(&self) -> TimersPtr {
+        self.factory.borrow().get_timers()
+    }
+
+    /// 由帧循环调用，`now` 为逻辑时间
+    pub fn update_timers(&self, now: Duration) {
+        Timers::update(&self.timers(), &self.factory, now);
+    }
+
+    pub fn observers(&self) -> ObserversPtr {
+        self.factory.borrow().get_observers()
+    }
+
+    /// 执行延迟的属性观察者，在帧末尾调用
+    pub fn flush_observers(&self) -> usize {
+        Observers::flush(&self.observers())
+    }
+
+    /// 解析句柄，对象已销毁时返回 None
+    pub fn resolve(&self, handle: ObjectHandle) -> Option<ObjectPtr> {
+        handle.resolve(&self.factory.borrow())
+    }
+
+    /// 查询场景中的对象，用 `within` 限定在某个对象下
+    pub fn query(&self) -> Query {
+        Query::new(self.factory.clone(), self.scene_object.clone())
+    }
+
     pub fn create_in_scene(&self, entity: &str, cap: usize) -> Option<ObjectPtr> {
-        self.scene_object.borrow_mut().create_child(entity, cap, 0)
+        Object::create(&self.scene_object, entity, cap, 0)
+    }
+
+    /// 处理延迟的属性修改，例如生命周期回调中的修改，在帧末尾调用
+    pub fn flush_changes(&self) -> usize {
+        Factory::flush_deferred(&self.factory)
+    }
+
+    /// 整个场景的快照
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot::capture(&self.scene_object)
+    }
+
+    /// 清空场景后按快照重建，场景类需要和快照一致
+    pub fn restore(&self, snapshot: &Snapshot) -> Result<(), SnapshotError> {
+        let class_name = self.scene_object.borrow().model.class_name;
+        if snapshot.root.class_name != class_name {
+            return Err(SnapshotError::Class {
+                expected: class_name.to_string(),
+                found: snapshot.root.class_name.clone(),
+            });
+        }
+        self.clear_all();
+        snapshot.apply(&self.scene_object)
     }
 }
@@ -0,0 +1,170 @@
+use std::fmt;
+
+use crate::{
+    factory::Factory,
+    game_object::GameObject,
+    object::Object,
+    value::{AttrValue, Value, ValueError},
+    ObjectPtr,
+};
+
+// uid 的低 31 位是工厂中的槽位，高 32 位是序号
+const INDEX_MASK: u64 = 0x7FFFFFFF;
+
+/// 序号的最大值，超过后从 1 重新开始
+pub(crate) const MAX_SERIAL: u32 = 0x7FFFFFFF;
+
+/// 对象的弱引用，由工厂的槽位和序号组成
+///
+/// 和 `ObjectPtr` 不同，句柄不会让已销毁的对象继续存在；和 uid 相同，
+/// 对象销毁或者槽位被复用后解析得到 None。记住其它对象（目标、仇恨、定时器参数）时使用句柄。
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ObjectHandle {
+    index: u32,
+    serial: u32,
+}
+
+impl ObjectHandle {
+    /// 不指向任何对象的句柄
+    pub const NULL: ObjectHandle = ObjectHandle {
+        index: 0,
+        serial: 0,
+    };
+
+    pub(crate) fn new(index: u32, serial: u32) -> Self {
+        Self { index, serial }
+    }
+
+    pub fn from_uid(uid: u64) -> Self {
+        Self {
+            index: (uid & INDEX_MASK) as u32,
+            serial: (uid >> 32) as u32,
+        }
+    }
+
+    pub fn uid(&self) -> u64 {
+        ((self.serial as u64) << 32) | self.index as u64
+    }
+
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    pub fn serial(&self) -> u32 {
+        self.serial
+    }
+
+    pub fn is_null(&self) -> bool {
+        self.serial == 0
+    }
+
+    pub fn resolve(&self, factory: &Factory) -> Option<ObjectPtr> {
+        if self.is_null() {
+            return None;
+        }
+        factory
+            .find(self.uid())
+            .filter(|object| !object.borrow().is_deleted())
+    }
+}
+
+impl fmt::Display for ObjectHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.index, self.serial)
+    }
+}
+
+/// 按 uid 保存，重新载入的对象 uid 会改变，所以句柄属性通常只用于同步
+impl AttrValue for ObjectHandle {
+    fn to_value(&self) -> Value {
+        Value::UInt(self.uid())
+    }
+
+    fn from_value(value: &Value) -> Result<Self, ValueError> {
+        u64::from_value(value).map(Self::from_uid)
+    }
+}
+
+impl Object {
+    pub fn handle(&self) -> ObjectHandle {
+        ObjectHandle::from_uid(self.uid)
+    }
+
+    /// 通过 `this` 所在的工厂解析句柄
+    pub fn resolve(this: &ObjectPtr, handle: ObjectHandle) -> Option<ObjectPtr> {
+        let factory = this.borrow().get_factory()?;
+        let factory = factory.borrow();
+        handle.resolve(&factory)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn test_uid_roundtrip() {
+        let handle = ObjectHandle::from_uid((3 << 32) | 7);
+        assert_eq!((handle.index(), handle.serial()), (7, 3));
+        assert_eq!(ObjectHandle::from_uid(handle.uid()), handle);
+        assert_eq!(handle.to_string(), "7:3");
+        assert_eq!(ObjectHandle::from_value(&handle.to_value()), Ok(handle));
+        assert!(ObjectHandle::NULL.is_null());
+        assert_eq!(ObjectHandle::default(), ObjectHandle::NULL);
+    }
+
+    #[test]
+    fn test_resolve() {
+        let scene = testing::scene();
+        let player = testing::player(&scene);
+        let item = testing::item(&player, 0, "sword");
+        let handle = item.borrow().handle();
+        assert_eq!(handle.uid(), item.borrow().uid());
+        assert!(Rc::ptr_eq(&scene.resolve(handle).unwrap(), &item));
+        assert!(Rc::ptr_eq(
+            &Object::resolve(&player, handle).unwrap(),
+            &item
+        ));
+        let owner = scene.scene_object.borrow().handle();
+        assert!(Rc::ptr_eq(
+            &scene.resolve(owner).unwrap(),
+            &scene.scene_object
+        ));
+    }
+
+    #[test]
+    fn test_resolve_invalid() {
+        let scene = testing::scene();
+        assert!(scene.resolve(ObjectHandle::NULL).is_none());
+        // 槽位超出范围
+        assert!(scene
+            .resolve(ObjectHandle::from_uid((1 << 32) | 0x7FFFFFF0))
+            .is_none());
+    }
+
+    #[test]
+    fn test_stale_after_reuse() {
+        let scene = testing::scene();
+        let player = testing::player(&scene);
+        let item = testing::item(&player, 0, "sword");
+        let handle = item.borrow().handle();
+
+        // 销毁后槽位复用，旧句柄失效
+        Object::destroy_self(&item);
+        assert!(scene.resolve(handle).is_none());
+        let other = testing::item(&player, 0, "shield");
+        let new_handle = other.borrow().handle();
+        assert_eq!(new_handle.index(), handle.index());
+        assert_ne!(new_handle.serial(), handle.serial());
+        assert!(scene.resolve(handle).is_none());
+        assert!(Rc::ptr_eq(&scene.resolve(new_handle).unwrap(), &other));
+
+        // 外部还持有指针时，销毁后也解析不到
+        player.borrow_mut().destroy_children();
+        assert!(scene.resolve(new_handle).is_none());
+        assert!(other.borrow().is_deleted());
+    }
+}
@@ -1,5 +1,3 @@
-#![feature(trait_upcasting)]
-
 use std::{
     cell::RefCell,
     rc::{Rc, Weak},
@@ -9,13 +7,31 @@ use factory::Factory;
 use game_model::GameModel;
 use object::Object;
 
+// 测试中展开 `def_entity` 生成的 `re_object::` 路径
+#[cfg(test)]
+extern crate self as re_object;
+
 pub mod container;
 pub mod factory;
 pub mod game_model;
 pub mod game_object;
+pub mod game_scene;
+pub mod handle;
+pub mod lifecycle;
+pub mod migration;
 pub mod object;
+pub mod observer;
+pub mod query;
+pub mod record;
 pub mod registry;
-pub mod game_scene;
+pub mod replication;
+pub mod snapshot;
+pub mod stack;
+#[cfg(test)]
+mod testing;
+pub mod timer;
+pub mod value;
+pub mod walk;
 
 pub type ObjectPtr = Rc<RefCell<Object>>;
 pub type WeakObjectPtr = Weak<RefCell<Object>>;
@@ -23,12 +39,3 @@ pub type FactoryPtr = Rc<RefCell<Factory>>;
 pub type WeakFactoryPtr = Weak<RefCell<Factory>>;
 pub type GameModelPtr = Rc<RefCell<dyn GameModel>>;
 pub type WeakGameModelPtr = Weak<RefCell<dyn GameModel>>;
-
-#[derive(Debug)]
-pub struct MutObjectPtr(pub *mut Object);
-
-impl Default for MutObjectPtr {
-    fn default() -> Self {
-        Self(std::ptr::null_mut())
-    }
-}
\ No newline at end of file
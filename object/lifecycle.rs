@@ -0,0 +1,216 @@
+use crate::{object::Object, ObjectPtr};
+
+/// 对象生命周期回调，用 `#[def_entity(hooks)]` 声明后为实体实现
+///
+/// 回调执行时实体的数据已被借用，`object` 可以借用，但不能再通过它访问实体数据。
+/// 回调中直接修改 `self` 的属性，这些修改在父对象的借用结束后处理，
+/// 例如 `Object::create` 返回之前，最迟在 `GameScene::flush_changes` 中。
+/// 父对象此时也正被修改，只能通过参数中的 `&Object` 读取，不能再借用父对象的指针。
+#[allow(unused_variables)]
+pub trait Lifecycle {
+    /// 创建完成并且已经放入父对象，在 `on_enter` 之前
+    ///
+    /// 从存档载入时属性已经载入，子对象还没有载入。
+    fn on_create(&mut self, object: &ObjectPtr) {}
+
+    /// 从存档或快照载入之后，此时属性和子对象都已载入
+    ///
+    /// 载入时不通知观察者，需要根据载入的属性重建的状态在这里处理。
+    fn on_load(&mut self, object: &ObjectPtr) {}
+
+    /// 销毁之前，此时对象和子对象都还完整
+    fn on_destroy(&mut self, object: &ObjectPtr) {}
+
+    /// 放入容器之后，`pos` 从 1 开始
+    fn on_enter(&mut self, object: &ObjectPtr, container: &Object, pos: usize) {}
+
+    /// 从容器移除之后，`pos` 是原来的位置
+    fn on_leave(&mut self, object: &ObjectPtr, container: &Object, pos: usize) {}
+
+    /// 父对象改变之后，在 `on_enter` 之后执行
+    fn on_parent_changed(&mut self, object: &ObjectPtr, parent: &Object) {}
+}
+
+/// 执行对象的回调，实体没有声明 `hooks` 时什么也不做
+pub(crate) fn fire(object: &ObjectPtr, f: impl FnOnce(&mut dyn Lifecycle, &ObjectPtr)) {
+    let model = object.borrow().game_model.clone();
+    let mut model = model.borrow_mut();
+    if let Some(hooks) = model.lifecycle() {
+        f(hooks, object);
+    }
+    // 父对象可能正被借用，观察者中不能访问
+    if !model.changes().is_empty() {
+        drop(model);
+        Object::defer_changes(object);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use re_ops::def_entity;
+
+    use super::*;
+    use crate::{
+        container::Container,
+        game_object::GameObject,
+        observer::{AttrChange, Dispatch},
+        testing::{self, TestBox},
+    };
+
+    #[def_entity(class = Item, hooks)]
+    struct TestGem {
+        #[attr(save)]
+        name: String,
+    }
+
+    thread_local! {
+        static EVENTS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+    }
+
+    fn push(event: String) {
+        EVENTS.with(|e| e.borrow_mut().push(event));
+    }
+
+    fn take() -> Vec<String> {
+        EVENTS.with(|e| std::mem::take(&mut *e.borrow_mut()))
+    }
+
+    impl Lifecycle for TestGem {
+        fn on_create(&mut self, _object: &ObjectPtr) {
+            self.set_name("gem".to_string());
+            push("create".to_string());
+        }
+
+        fn on_destroy(&mut self, object: &ObjectPtr) {
+            push(format!("destroy {}", object.borrow().uid() > 0));
+        }
+
+        fn on_enter(&mut self, _object: &ObjectPtr, container: &Object, pos: usize) {
+            push(format!("enter {} {}", container.model.class_name, pos));
+        }
+
+        fn on_leave(&mut self, _object: &ObjectPtr, container: &Object, pos: usize) {
+            push(format!("leave {} {}", container.model.class_name, pos));
+        }
+
+        fn on_parent_changed(&mut self, _object: &ObjectPtr, parent: &Object) {
+            push(format!("parent {}", parent.uid()));
+        }
+    }
+
+    fn bag(parent: &ObjectPtr, cap: usize) -> ObjectPtr {
+        Object::create(parent, TestBox::ClassName(), cap, 0).unwrap()
+    }
+
+    #[test]
+    fn test_create() {
+        let scene = testing::scene();
+        let bag = bag(&testing::player(&scene), 4);
+        let gem = Object::create(&bag, TestGem::ClassName(), 0, 2).unwrap();
+        assert_eq!(
+            take(),
+            [
+                "create".to_string(),
+                "enter TestBox 2".to_string(),
+                format!("parent {}", bag.borrow().uid()),
+            ]
+        );
+        // 回调中的修改在回调结束后处理
+        Object::model_map(&gem, |g: &TestGem| assert_eq!(g.name, "gem"));
+        assert!(gem.borrow().dirty());
+    }
+
+    #[test]
+    fn test_move() {
+        let scene = testing::scene();
+        let bag = bag(&testing::player(&scene), 4);
+        let other = testing::item(&scene.scene_object, 0, "other");
+        let gem = Object::create(&bag, TestGem::ClassName(), 0, 2).unwrap();
+        take();
+
+        // 移到其它容器，再放回同一个容器
+        assert!(bag.borrow_mut().remove_child(&gem));
+        assert!(other.borrow_mut().add_child(gem.clone(), 0));
+        assert!(other.borrow_mut().remove_child(&gem));
+        assert!(other.borrow_mut().add_child(gem.clone(), 0));
+        assert_eq!(
+            take(),
+            [
+                "leave TestBox 2".to_string(),
+                "enter TestItem 1".to_string(),
+                format!("parent {}", other.borrow().uid()),
+                "leave TestItem 1".to_string(),
+                "enter TestItem 1".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_move_child() {
+        let scene = testing::scene();
+        let player = testing::player(&scene);
+        let (bag, equip) = (bag(&player, 4), bag(&player, 2));
+        Object::create(&bag, TestGem::ClassName(), 0, 2).unwrap();
+        testing::item(&equip, 1, "ring");
+        take();
+
+        assert!(Object::swap_children(&bag, 2, &equip, 1));
+        assert_eq!(
+            take(),
+            [
+                "leave TestBox 2".to_string(),
+                "enter TestBox 1".to_string(),
+                format!("parent {}", equip.borrow().uid()),
+            ]
+        );
+        // 同一个容器中移动不改变父对象
+        assert!(Object::move_child(&equip, 1, &equip, 2));
+        assert_eq!(take(), ["leave TestBox 1", "enter TestBox 2"]);
+    }
+
+    #[test]
+    fn test_destroy() {
+        let scene = testing::scene();
+        let bag = bag(&testing::player(&scene), 4);
+        let gem = Object::create(&bag, TestGem::ClassName(), 0, 1).unwrap();
+        Object::create(&bag, TestGem::ClassName(), 0, 2).unwrap();
+        take();
+
+        Object::destroy_self(&gem);
+        assert_eq!(take(), ["destroy true", "leave TestBox 1"]);
+        // 销毁父对象时子对象也执行回调
+        Object::destroy_self(&bag);
+        assert_eq!(take(), ["destroy true", "leave TestBox 2"]);
+        scene.clear_all();
+        assert!(take().is_empty());
+    }
+
+    #[test]
+    fn test_changes_after_parent_borrow() {
+        let scene = testing::scene();
+        let bag = bag(&testing::player(&scene), 4);
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let log = seen.clone();
+        // 观察者中借用父对象，回调时父对象已不再被借用
+        scene.observers().borrow_mut().on_class(
+            TestGem::ClassName(),
+            "name",
+            Dispatch::Immediate,
+            move |change: &AttrChange<String>| {
+                let parent = change.object.borrow().get_parent().unwrap();
+                log.borrow_mut().push(parent.borrow_mut().child_count());
+            },
+        );
+        Object::create(&bag, TestGem::ClassName(), 0, 1).unwrap();
+        assert_eq!(*seen.borrow(), [1]);
+
+        // 直接借用父对象创建时，修改留到帧末尾
+        let gem = bag.borrow_mut().create_child(TestGem::ClassName(), 0, 2);
+        assert_eq!(seen.borrow().len(), 1);
+        assert!(!gem.unwrap().borrow().dirty());
+        assert_eq!(scene.flush_changes(), 1);
+        assert_eq!(*seen.borrow(), [1, 2]);
+    }
+}
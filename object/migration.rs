@@ -0,0 +1,262 @@
+use crate::value::{Value, ValueError};
+
+/// 按名字保存的属性列表
+pub type Attrs = Vec<(String, Value)>;
+
+/// 存档数据的升级步骤，载入时按版本依次执行
+///
+/// `version` 是引入这个修改的类版本，存档版本小于它时执行。
+/// 通过 `inventory::submit!` 注册：
+///
+/// ```ignore
+/// inventory::submit! { Migration::rename("Player", 2, "nick", "name") }
+/// inventory::submit! { Migration::default("Player", 3, "gold", || Value::UInt(100)) }
+/// ```
+#[derive(Debug)]
+pub struct Migration {
+    pub class_name: &'static str,
+    pub version: u32,
+    pub step: MigrationStep,
+}
+
+#[derive(Debug)]
+pub enum MigrationStep {
+    /// 属性改名
+    Rename {
+        from: &'static str,
+        to: &'static str,
+    },
+    /// 新增的属性，存档中没有时使用默认值
+    Default {
+        attr: &'static str,
+        value: fn() -> Value,
+    },
+    /// 转换属性的值，例如修改类型或单位
+    Transform {
+        attr: &'static str,
+        f: fn(&Value) -> Result<Value, ValueError>,
+    },
+    /// 其它修改，直接处理所有属性
+    Custom(fn(&mut Attrs) -> Result<(), ValueError>),
+}
+
+inventory::collect!(Migration);
+
+impl Migration {
+    pub const fn new(class_name: &'static str, version: u32, step: MigrationStep) -> Self {
+        Self {
+            class_name,
+            version,
+            step,
+        }
+    }
+
+    pub const fn rename(
+        class_name: &'static str,
+        version: u32,
+        from: &'static str,
+        to: &'static str,
+    ) -> Self {
+        Self::new(class_name, version, MigrationStep::Rename { from, to })
+    }
+
+    pub const fn default(
+        class_name: &'static str,
+        version: u32,
+        attr: &'static str,
+        value: fn() -> Value,
+    ) -> Self {
+        Self::new(class_name, version, MigrationStep::Default { attr, value })
+    }
+
+    pub const fn transform(
+        class_name: &'static str,
+        version: u32,
+        attr: &'static str,
+        f: fn(&Value) -> Result<Value, ValueError>,
+    ) -> Self {
+        Self::new(class_name, version, MigrationStep::Transform { attr, f })
+    }
+
+    /// 修改属性列表，出错时返回出错的属性名
+    pub fn apply(&self, attrs: &mut Attrs) -> Result<(), (String, ValueError)> {
+        match &self.step {
+            MigrationStep::Rename { from, to } => {
+                if let Some(attr) = attrs.iter_mut().find(|(name, _)| name == from) {
+                    attr.0 = to.to_string();
+                }
+            }
+            MigrationStep::Default { attr, value } => {
+                if !attrs.iter().any(|(name, _)| name == attr) {
+                    attrs.push((attr.to_string(), value()));
+                }
+            }
+            MigrationStep::Transform { attr, f } => {
+                if let Some((_, value)) = attrs.iter_mut().find(|(name, _)| name == attr) {
+                    *value = f(value).map_err(|err| (attr.to_string(), err))?;
+                }
+            }
+            MigrationStep::Custom(f) => f(attrs).map_err(|err| (String::new(), err))?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use re_ops::def_entity;
+
+    use super::*;
+    use crate::{
+        container::Container,
+        game_object::GameObject,
+        object::Object,
+        observer::{AttrChange, Dispatch},
+        record::{ObjectRecord, RecordError},
+        testing,
+        value::AttrValue,
+    };
+
+    /// 版本 2 把 nick 改名为 name，版本 3 加入 gold，等级改为十倍
+    #[def_entity(version = 3)]
+    struct TestHero {
+        #[attr(save)]
+        name: String,
+        #[attr(save)]
+        level: i32,
+        #[attr(save)]
+        gold: u64,
+    }
+
+    inventory::submit! { Migration::rename("TestHero", 2, "nick", "name") }
+    inventory::submit! { Migration::default("TestHero", 3, "gold", || Value::UInt(100)) }
+    inventory::submit! {
+        Migration::transform("TestHero", 3, "level", |v| Ok(Value::Int(i32::from_value(v)? as i64 * 10)))
+    }
+
+    fn attrs(list: &[(&str, Value)]) -> Attrs {
+        list.iter()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect()
+    }
+
+    fn v1() -> ObjectRecord {
+        ObjectRecord {
+            class_name: TestHero::ClassName().to_string(),
+            version: 1,
+            attrs: attrs(&[
+                ("nick", Value::Str("hero".to_string())),
+                ("level", Value::Int(2)),
+                ("removed", Value::Bool(true)),
+            ]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_steps() {
+        let mut list = attrs(&[("a", Value::Int(1))]);
+        Migration::rename("X", 1, "missing", "b")
+            .apply(&mut list)
+            .unwrap();
+        Migration::default("X", 1, "a", || Value::Int(2))
+            .apply(&mut list)
+            .unwrap();
+        assert_eq!(list, attrs(&[("a", Value::Int(1))]));
+
+        Migration::rename("X", 1, "a", "b")
+            .apply(&mut list)
+            .unwrap();
+        Migration::default("X", 1, "c", || Value::Int(3))
+            .apply(&mut list)
+            .unwrap();
+        assert_eq!(list, attrs(&[("b", Value::Int(1)), ("c", Value::Int(3))]));
+
+        let fail = Migration::transform("X", 1, "b", |v| Err(v.mismatch("str")));
+        assert_eq!(
+            fail.apply(&mut list),
+            Err(("b".to_string(), Value::Int(1).mismatch("str")))
+        );
+        let custom = Migration::new(
+            "X",
+            1,
+            MigrationStep::Custom(|attrs| {
+                attrs.clear();
+                Ok(())
+            }),
+        );
+        custom.apply(&mut list).unwrap();
+        assert!(list.is_empty());
+    }
+
+    #[test]
+    fn test_restore_old_record() {
+        let scene = testing::scene();
+        let hero = scene.create_in_scene(TestHero::ClassName(), 0).unwrap();
+        assert_eq!(ObjectRecord::capture(&hero).version, 3);
+        scene.observers().borrow_mut().on_class(
+            TestHero::ClassName(),
+            "level",
+            Dispatch::Immediate,
+            |_: &AttrChange<i32>| panic!("migrated attrs are loaded raw"),
+        );
+
+        let loaded = v1().restore(&scene.scene_object).unwrap();
+        Object::model_map(&loaded, |h: &TestHero| {
+            assert_eq!(h.name, "hero");
+            assert_eq!(h.level, 20);
+            assert_eq!(h.gold, 100);
+        });
+        assert!(!loaded.borrow().dirty());
+        assert_eq!(ObjectRecord::capture(&loaded).version, 3);
+    }
+
+    #[test]
+    fn test_skip_applied_steps() {
+        let scene = testing::scene();
+        // 版本 2 的存档已经改过名，只执行版本 3 的步骤
+        let mut v2 = ObjectRecord::capture(&v1().restore(&scene.scene_object).unwrap());
+        v2.version = 2;
+        v2.attrs.retain(|(name, _)| name != "gold");
+        let loaded = v2.restore(&scene.scene_object).unwrap();
+        Object::model_map(&loaded, |h: &TestHero| {
+            assert_eq!((h.name.as_str(), h.level, h.gold), ("hero", 200, 100))
+        });
+
+        // 当前版本的存档不做修改
+        let current = ObjectRecord::capture(&loaded);
+        let again = current.restore(&scene.scene_object).unwrap();
+        assert_eq!(ObjectRecord::capture(&again).attrs, current.attrs);
+    }
+
+    #[test]
+    fn test_errors() {
+        let scene = testing::scene();
+        let mut bad = v1();
+        bad.attrs[1].1 = Value::Str("x".to_string());
+        assert!(matches!(
+            bad.restore(&scene.scene_object),
+            Err(RecordError::Attr { ref attr, .. }) if attr == "level"
+        ));
+        let newer = ObjectRecord { version: 4, ..v1() };
+        assert!(matches!(
+            newer.restore(&scene.scene_object),
+            Err(RecordError::Version {
+                found: 4,
+                current: 3,
+                ..
+            })
+        ));
+        assert_eq!(scene.scene_object.borrow().child_count(), 0);
+    }
+
+    #[test]
+    fn test_record_without_version() {
+        // 旧格式的存档没有版本字段
+        let mut value = v1().to_value();
+        if let Value::Map(fields) = &mut value {
+            fields.retain(|(name, _)| name != "version");
+        }
+        assert_eq!(ObjectRecord::from_value(&value).unwrap().version, 0);
+    }
+}
@@ -1,13 +1,21 @@
-use std::ops::DerefMut;
-
-use tracing::debug;
+use tracing::{debug, warn};
 
 use crate::{
-    container::Container, game_model::Model, game_object::GameObject, GameModelPtr, ObjectPtr,
-    WeakFactoryPtr, WeakObjectPtr,
+    container::Container,
+    factory::Factory,
+    game_model::{Change, Model},
+    game_object::GameObject,
+    observer::Observers,
+    snapshot::Snapshot,
+    value::{Value, ValueError},
+    GameModelPtr, ObjectPtr, WeakFactoryPtr, WeakObjectPtr,
 };
 
-#[derive(Debug, Default, Clone, Copy, PartialEq)]
+/// 对象的类别，由 `#[def_entity(class = Item)]` 指定
+///
+/// 游戏自己的类别使用 `Custom`，例如 `const MOUNT: ClassType = ClassType::Custom(1);`，
+/// 然后写成 `#[def_entity(class = MOUNT)]`。
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum ClassType {
     #[default]
     None,
@@ -17,6 +25,7 @@ pub enum ClassType {
     Item,
     Aide,
     Container,
+    Custom(u16),
 }
 
 #[derive(Debug)]
@@ -26,7 +35,10 @@ pub struct Object {
     pub deleted: bool,
     pub destroying: bool,
     pub dirty: bool,
+    pub save_version: u64,
     pub modify_attrs: Vec<u32>,
+    /// 通过 `move_child` 或 `swap_children` 改变了位置，下一次同步时发送
+    pub moved: bool,
     pub children: Vec<Option<ObjectPtr>>,
     pub cap: usize,
     pub container_pos: usize,
@@ -47,14 +59,18 @@ impl Drop for Object {
 
 impl Object {
     pub fn new(game_model: GameModelPtr) -> Self {
+        // 创建对象之前的修改不需要处理
+        game_model.borrow_mut().changes().take();
         let model = game_model.borrow().get_model();
         Self {
             uid: 0,
-            class_type: ClassType::None,
+            class_type: model.class_type,
             deleted: false,
             destroying: false,
             dirty: false,
+            save_version: 0,
             modify_attrs: Vec::new(),
+            moved: false,
             children: Vec::new(),
             cap: 0,
             container_pos: 0,
@@ -62,54 +78,55 @@ impl Object {
             parent: None,
             factory: None,
             self_ptr: None,
-            game_model: game_model,
-            model: model,
+            game_model,
+            model,
         }
     }
 
     pub fn new_with_cap(game_model: GameModelPtr, cap: usize) -> Self {
         assert!(cap > 0);
+        // 创建对象之前的修改不需要处理
+        game_model.borrow_mut().changes().take();
         let model = game_model.borrow().get_model();
         Self {
             uid: 0,
-            class_type: ClassType::None,
+            class_type: model.class_type,
             deleted: false,
             destroying: false,
             dirty: false,
+            save_version: 0,
             modify_attrs: Vec::new(),
+            moved: false,
             children: Vec::with_capacity(cap),
-            cap: cap,
+            cap,
             container_pos: 0,
             child_num: 0,
             parent: None,
             factory: None,
             self_ptr: None,
-            game_model: game_model,
-            model: model,
+            game_model,
+            model,
         }
     }
 
     pub fn create(parent: &ObjectPtr, entity: &str, cap: usize, pos: usize) -> Option<ObjectPtr> {
-        parent.borrow_mut().create_child(entity, cap, pos)
-    }
-
-    pub fn created(object: &ObjectPtr) {
-        let obj_ptr = object.borrow_mut().deref_mut() as *mut Object;
-        object.borrow().game_model.borrow_mut().set_gameobj(obj_ptr);
+        let object = parent.borrow_mut().create_child(entity, cap, pos);
+        Self::flush_deferred(parent);
+        object
     }
 
     pub fn object_map<F, U>(this: &ObjectPtr, f: F) -> U
     where
         F: FnOnce(&Object) -> U,
     {
-        f(&*this.borrow())
+        f(&this.borrow())
     }
 
     pub fn object_map_mut<F, U>(this: &ObjectPtr, f: F) -> U
     where
         F: FnOnce(&mut Object) -> U,
     {
-        f(&mut *this.borrow_mut())
+        f(&mut this.borrow_mut())
     }
 
     pub fn model_map<T, F, U>(this: &ObjectPtr, f: F) -> U
@@ -134,22 +151,117 @@ impl Object {
         T: 'static,
         F: FnOnce(&mut T) -> U,
     {
-        if let Some(gm) = this
-            .borrow()
-            .game_model
+        let model = this.borrow().game_model.clone();
+        let result = match model.borrow_mut().get_mut_any().downcast_mut::<T>() {
+            Some(gm) => f(gm),
+            None => panic!("parse failed"),
+        };
+        Self::flush_changes(this);
+        result
+    }
+
+    /// 通过属性的 setter 修改
+    pub fn set_value(this: &ObjectPtr, index: u32, value: &Value) -> Result<(), ValueError> {
+        let model = this.borrow().game_model.clone();
+        let result = model.borrow_mut().set_value(index, value);
+        Self::flush_changes(this);
+        result
+    }
+
+    /// 处理实体记录的修改：脏标记、同步记录、索引和观察者
+    ///
+    /// `model_map_mut` 和 `set_value` 结束时自动调用，此时不再借用对象和实体，
+    /// 观察者中可以访问对象。实体正被借用时记录警告，修改留到 `GameScene::flush_changes`。
+    pub fn flush_changes(this: &ObjectPtr) {
+        let model = this.borrow().game_model.clone();
+        loop {
+            let changes = match model.try_borrow_mut() {
+                Ok(mut model) => model.changes().take(),
+                Err(_) => {
+                    let object = this.borrow();
+                    warn!(
+                        "{} {} is borrowed, changes deferred",
+                        object.model.class_name, object.uid
+                    );
+                    drop(object);
+                    Self::defer_changes(this);
+                    return;
+                }
+            };
+            if changes.is_empty() {
+                return;
+            }
+            for change in changes {
+                Self::changed(this, change);
+            }
+        }
+    }
+
+    /// 直接写入属性之后调用，例如从存档载入：丢弃修改记录，更新一次索引
+    ///
+    /// 不标记脏、不同步，也不通知观察者。
+    pub(crate) fn loaded(&self) {
+        self.game_model.borrow_mut().changes().take();
+        if let Some(factory) = self.get_factory() {
+            let indexes = factory.borrow().get_indexes();
+            indexes.borrow_mut().update_object(self);
+        }
+    }
+
+    /// 修改留到稍后处理，例如生命周期回调中父对象还正被借用
+    pub(crate) fn defer_changes(this: &ObjectPtr) {
+        let factory = this.borrow().get_factory();
+        let Some(factory) = factory else {
+            warn!("no factory, changes of {} dropped", this.borrow().uid);
+            return;
+        };
+        factory.borrow_mut().defer_changes(this);
+    }
+
+    /// 处理 `this` 所在工厂中延迟的修改，在父对象的借用结束后调用
+    pub(crate) fn flush_deferred(this: &ObjectPtr) {
+        let factory = this.borrow().get_factory();
+        if let Some(factory) = factory {
+            Factory::flush_deferred(&factory);
+        }
+    }
+
+    fn changed(this: &ObjectPtr, change: Change) {
+        let (factory, uid, class_name, attr) = {
+            let mut object = this.borrow_mut();
+            object.change_attr(change.index);
+            (
+                object.get_factory(),
+                object.uid,
+                object.model.class_name,
+                object.model.attrs[change.index as usize],
+            )
+        };
+        let Some(factory) = factory else {
+            return;
+        };
+        let (indexes, observers) = {
+            let factory = factory.borrow();
+            (factory.get_indexes(), factory.get_observers())
+        };
+        indexes
             .borrow_mut()
-            .get_mut_any()
-            .downcast_mut::<T>()
-        {
-            return f(gm);
+            .change_attr(&this.borrow(), change.index);
+        if let Some((old, new)) = &change.values {
+            Observers::notify(&observers, this, uid, class_name, attr, &**old, &**new);
         }
-        panic!("parse failed")
+    }
+
+    /// 对象和所有子对象的快照，见 `Snapshot`
+    pub fn snapshot(this: &ObjectPtr) -> Snapshot {
+        Snapshot::capture(this)
     }
 
     pub fn destroy_object(parent: &ObjectPtr, target: &ObjectPtr) {
         assert!(target.borrow().parent.is_some());
         if Self::check_parent(target, parent) {
             parent.borrow_mut().destroy_child(target);
+            Self::flush_deferred(parent);
         }
     }
 
@@ -158,6 +270,7 @@ impl Object {
         let parent = this.borrow().get_parent();
         if let Some(parent) = parent {
             parent.borrow_mut().destroy_child(this);
+            Self::flush_deferred(&parent);
         }
     }
 
@@ -165,6 +278,64 @@ impl Object {
         if let Some(parent_ptr) = child.borrow().get_parent() {
             return parent_ptr.as_ptr() == parent.as_ptr();
         }
-        return false;
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use re_ops::def_entity;
+
+    use super::*;
+    use crate::{
+        container::Container,
+        testing::{self, TestBox, TestItem},
+    };
+
+    const TEST_PET: ClassType = ClassType::Custom(1);
+
+    #[def_entity(class = TEST_PET)]
+    struct TestPet {}
+
+    #[def_entity]
+    struct TestRock {}
+
+    #[test]
+    fn test_class_type() {
+        let scene = testing::scene();
+        assert_eq!(
+            scene.scene_object.borrow().get_class_type(),
+            ClassType::Scene
+        );
+        let player = testing::player(&scene);
+        assert_eq!(player.borrow().get_class_type(), ClassType::Role);
+        let rock = scene.create_in_scene(TestRock::ClassName(), 0).unwrap();
+        assert_eq!(rock.borrow().get_class_type(), ClassType::None);
+        let pet = scene.create_in_scene(TestPet::ClassName(), 0).unwrap();
+        assert_eq!(pet.borrow().get_class_type(), TEST_PET);
+        assert_ne!(TEST_PET, ClassType::Custom(2));
+    }
+
+    #[test]
+    fn test_child_id_list() {
+        let scene = testing::scene();
+        let item_box =
+            Object::create(&testing::player(&scene), TestBox::ClassName(), 4, 0).unwrap();
+        let item = Object::create(&item_box, TestItem::ClassName(), 0, 0).unwrap();
+        let pet = Object::create(&item_box, TestPet::ClassName(), 0, 0).unwrap();
+        Object::create(&item_box, TestRock::ClassName(), 0, 0).unwrap();
+
+        let item_box = item_box.borrow();
+        assert_eq!(
+            item_box.get_child_id_list(ClassType::Item),
+            vec![item.borrow().uid()]
+        );
+        assert_eq!(
+            item_box.get_child_id_list(TEST_PET),
+            vec![pet.borrow().uid()]
+        );
+        // None 表示所有子对象
+        assert_eq!(item_box.get_child_id_list(ClassType::None).len(), 3);
+        assert!(item_box.get_child_id_list(ClassType::Npc).is_empty());
     }
 }
@@ -0,0 +1,388 @@
+use std::{
+    any::Any,
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
+    rc::{Rc, Weak},
+};
+
+use tracing::warn;
+
+use crate::{game_object::GameObject, handle::ObjectHandle, ObjectPtr};
+
+pub type ObserversPtr = Rc<RefCell<Observers>>;
+pub type WeakObserversPtr = Weak<RefCell<Observers>>;
+
+/// 观察者句柄，用来移除观察者
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ObserverId(u64);
+
+/// 观察者的执行时机
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dispatch {
+    /// 修改结束时立即执行，例如 `model_map_mut` 返回之前，回调中可以访问同一个对象
+    Immediate,
+    /// 记录下来，在 `Observers::flush` 中执行，通常在帧末尾
+    EndOfTick,
+}
+
+/// 属性修改事件，`old` 和 `new` 是修改前后的值
+///
+/// 需要在回调之外记住对象时保存 `handle`。
+pub struct AttrChange<'a, T> {
+    pub object: &'a ObjectPtr,
+    pub handle: ObjectHandle,
+    pub attr: &'static str,
+    pub old: &'a T,
+    pub new: &'a T,
+}
+
+type Deferred = Box<dyn FnOnce()>;
+type Handler =
+    Rc<dyn Fn(&ObjectPtr, ObjectHandle, &'static str, &dyn Any, &dyn Any) -> Option<Deferred>>;
+
+/// 属性修改的观察者，按类或者按对象注册
+///
+/// 对象被 `Factory::delete` 或 `Factory::destroy` 移除时自动移除它的观察者，
+/// 延迟执行的回调也不会在已删除的对象上触发。
+#[derive(Default)]
+pub struct Observers {
+    next_id: u64,
+    classes: HashMap<(&'static str, &'static str), Vec<(u64, Handler)>>,
+    objects: HashMap<u64, Vec<(&'static str, u64, Handler)>>,
+    pending: VecDeque<Deferred>,
+}
+
+impl std::fmt::Debug for Observers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Observers")
+            .field("count", &self.len())
+            .field("pending", &self.pending.len())
+            .finish()
+    }
+}
+
+impl Observers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.classes.values().map(Vec::len).sum::<usize>()
+            + self.objects.values().map(Vec::len).sum::<usize>()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.classes.is_empty() && self.objects.is_empty()
+    }
+
+    /// 等待 `flush` 的回调数
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// 观察一个类所有对象的属性，`T` 是属性的类型
+    pub fn on_class<T: Clone + 'static>(
+        &mut self,
+        class_name: &'static str,
+        attr: &'static str,
+        dispatch: Dispatch,
+        f: impl Fn(&AttrChange<T>) + 'static,
+    ) -> ObserverId {
+        let id = self.next_id();
+        self.classes
+            .entry((class_name, attr))
+            .or_default()
+            .push((id, handler(dispatch, f)));
+        ObserverId(id)
+    }
+
+    /// 观察一个对象的属性
+    pub fn on_object<T: Clone + 'static>(
+        &mut self,
+        object: ObjectHandle,
+        attr: &'static str,
+        dispatch: Dispatch,
+        f: impl Fn(&AttrChange<T>) + 'static,
+    ) -> ObserverId {
+        let id = self.next_id();
+        self.objects
+            .entry(object.uid())
+            .or_default()
+            .push((attr, id, handler(dispatch, f)));
+        ObserverId(id)
+    }
+
+    /// 对象的属性是否有观察者
+    pub fn watches(&self, class_name: &'static str, uid: u64, attr: &'static str) -> bool {
+        self.classes.contains_key(&(class_name, attr))
+            || self
+                .objects
+                .get(&uid)
+                .is_some_and(|handlers| handlers.iter().any(|(name, _, _)| *name == attr))
+    }
+
+    fn next_id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    pub fn remove(&mut self, id: ObserverId) -> bool {
+        for handlers in self.classes.values_mut() {
+            if let Some(i) = handlers.iter().position(|(i, _)| *i == id.0) {
+                handlers.remove(i);
+                self.classes.retain(|_, handlers| !handlers.is_empty());
+                return true;
+            }
+        }
+        for handlers in self.objects.values_mut() {
+            if let Some(i) = handlers.iter().position(|(_, i, _)| *i == id.0) {
+                handlers.remove(i);
+                self.objects.retain(|_, handlers| !handlers.is_empty());
+                return true;
+            }
+        }
+        false
+    }
+
+    /// 移除绑定到对象的所有观察者
+    pub fn remove_object(&mut self, uid: u64) {
+        self.objects.remove(&uid);
+    }
+
+    /// 由 `Object::flush_changes` 调用，先执行类的观察者，再执行对象的观察者
+    pub fn notify(
+        this: &ObserversPtr,
+        object: &ObjectPtr,
+        uid: u64,
+        class_name: &'static str,
+        attr: &'static str,
+        old: &dyn Any,
+        new: &dyn Any,
+    ) {
+        let handlers: Vec<Handler> = {
+            let observers = this.borrow();
+            if observers.is_empty() {
+                return;
+            }
+            let class = observers.classes.get(&(class_name, attr)).into_iter();
+            let object = observers
+                .objects
+                .get(&uid)
+                .into_iter()
+                .flatten()
+                .filter(|(name, _, _)| *name == attr);
+            class
+                .flatten()
+                .map(|(_, handler)| handler.clone())
+                .chain(object.map(|(_, _, handler)| handler.clone()))
+                .collect()
+        };
+        let handle = ObjectHandle::from_uid(uid);
+        for handler in handlers {
+            if let Some(deferred) = handler(object, handle, attr, old, new) {
+                this.borrow_mut().pending.push_back(deferred);
+            }
+        }
+    }
+
+    /// 执行延迟的回调，回调中产生的新修改也在这次执行，返回执行的数量
+    pub fn flush(this: &ObserversPtr) -> usize {
+        let mut count = 0;
+        loop {
+            let deferred = this.borrow_mut().pending.pop_front();
+            let Some(deferred) = deferred else {
+                return count;
+            };
+            deferred();
+            count += 1;
+        }
+    }
+}
+
+fn handler<T: Clone + 'static>(
+    dispatch: Dispatch,
+    f: impl Fn(&AttrChange<T>) + 'static,
+) -> Handler {
+    let f = Rc::new(f);
+    Rc::new(move |object, handle, attr, old, new| {
+        let (Some(old), Some(new)) = (old.downcast_ref::<T>(), new.downcast_ref::<T>()) else {
+            warn!("observer of {} has wrong type", attr);
+            return None;
+        };
+        match dispatch {
+            Dispatch::Immediate => {
+                f(&AttrChange {
+                    object,
+                    handle,
+                    attr,
+                    old,
+                    new,
+                });
+                None
+            }
+            Dispatch::EndOfTick => {
+                let f = f.clone();
+                let weak = Rc::downgrade(object);
+                let (old, new) = (old.clone(), new.clone());
+                Some(Box::new(move || match weak.upgrade() {
+                    Some(object) if !object.borrow().is_deleted() => f(&AttrChange {
+                        object: &object,
+                        handle,
+                        attr,
+                        old: &old,
+                        new: &new,
+                    }),
+                    _ => {}
+                }))
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use re_ops::def_entity;
+
+    use super::*;
+    use crate::{game_scene::GameScene, object::Object, testing};
+
+    #[def_entity]
+    struct TestGuard {
+        #[attr(save)]
+        level: i32,
+        #[attr(save)]
+        gold: u64,
+    }
+
+    type Log = Rc<RefCell<Vec<(i32, i32)>>>;
+
+    fn guard(scene: &GameScene) -> ObjectPtr {
+        scene.create_in_scene(TestGuard::ClassName(), 0).unwrap()
+    }
+
+    fn set_level(guard: &ObjectPtr, level: i32) {
+        Object::model_map_mut(guard, |g: &mut TestGuard| g.set_level(level));
+    }
+
+    fn watch_levels(scene: &GameScene, dispatch: Dispatch) -> Log {
+        let levels = Log::default();
+        let log = levels.clone();
+        scene.observers().borrow_mut().on_class(
+            TestGuard::ClassName(),
+            "level",
+            dispatch,
+            move |change: &AttrChange<i32>| log.borrow_mut().push((*change.old, *change.new)),
+        );
+        levels
+    }
+
+    #[test]
+    fn test_class_observer() {
+        let scene = testing::scene();
+        let levels = watch_levels(&scene, Dispatch::Immediate);
+        let (a, b) = (guard(&scene), guard(&scene));
+        set_level(&a, 5);
+        set_level(&b, 7);
+        Object::model_map_mut(&a, |g: &mut TestGuard| g.set_gold(1));
+        assert_eq!(*levels.borrow(), [(0, 5), (0, 7)]);
+        assert_eq!(scene.observers().borrow().pending(), 0);
+    }
+
+    #[test]
+    fn test_object_observer() {
+        let scene = testing::scene();
+        let (a, b) = (guard(&scene), guard(&scene));
+        let golds = Rc::new(RefCell::new(Vec::new()));
+        let log = golds.clone();
+        scene.observers().borrow_mut().on_object(
+            a.borrow().handle(),
+            "gold",
+            Dispatch::Immediate,
+            move |change: &AttrChange<u64>| log.borrow_mut().push((*change.new, change.handle)),
+        );
+        Object::model_map_mut(&a, |g: &mut TestGuard| g.set_gold(3));
+        Object::model_map_mut(&b, |g: &mut TestGuard| g.set_gold(4));
+        set_level(&a, 1);
+        assert_eq!(*golds.borrow(), [(3, a.borrow().handle())]);
+    }
+
+    #[test]
+    fn test_end_of_tick() {
+        let scene = testing::scene();
+        let levels = watch_levels(&scene, Dispatch::EndOfTick);
+        let a = guard(&scene);
+        // 延迟执行的回调里可以修改同一个对象，新的修改在同一次 flush 中执行
+        scene.observers().borrow_mut().on_object(
+            a.borrow().handle(),
+            "gold",
+            Dispatch::EndOfTick,
+            |change: &AttrChange<u64>| {
+                if *change.new == 0 {
+                    set_level(change.object, 0);
+                }
+            },
+        );
+        Object::model_map_mut(&a, |g: &mut TestGuard| {
+            g.set_level(5);
+            g.set_gold(10);
+            g.set_gold(0);
+        });
+        assert!(levels.borrow().is_empty());
+        assert_eq!(scene.observers().borrow().pending(), 3);
+        assert_eq!(scene.flush_observers(), 4);
+        assert_eq!(*levels.borrow(), [(0, 5), (5, 0)]);
+        assert_eq!(scene.flush_observers(), 0);
+    }
+
+    #[test]
+    fn test_wrong_type_is_ignored() {
+        let scene = testing::scene();
+        scene.observers().borrow_mut().on_class(
+            TestGuard::ClassName(),
+            "level",
+            Dispatch::Immediate,
+            |_: &AttrChange<String>| panic!("wrong type"),
+        );
+        let levels = watch_levels(&scene, Dispatch::Immediate);
+        set_level(&guard(&scene), 2);
+        assert_eq!(*levels.borrow(), [(0, 2)]);
+    }
+
+    #[test]
+    fn test_remove() {
+        let scene = testing::scene();
+        let observers = scene.observers();
+        let a = guard(&scene);
+        let id = observers.borrow_mut().on_class(
+            TestGuard::ClassName(),
+            "level",
+            Dispatch::Immediate,
+            |_: &AttrChange<i32>| panic!("removed"),
+        );
+        observers.borrow_mut().on_object(
+            a.borrow().handle(),
+            "level",
+            Dispatch::Immediate,
+            |_: &AttrChange<i32>| {},
+        );
+        assert_eq!(observers.borrow().len(), 2);
+        assert!(observers.borrow_mut().remove(id));
+        assert!(!observers.borrow_mut().remove(id));
+        set_level(&a, 1);
+
+        // 对象销毁时移除它的观察者
+        Object::destroy_self(&a);
+        assert!(observers.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_deferred_skips_deleted() {
+        let scene = testing::scene();
+        let levels = watch_levels(&scene, Dispatch::EndOfTick);
+        let a = guard(&scene);
+        set_level(&a, 3);
+        Object::destroy_self(&a);
+        assert_eq!(scene.flush_observers(), 1);
+        assert!(levels.borrow().is_empty());
+    }
+}
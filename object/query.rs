@@ -0,0 +1,573 @@
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
+
+use tracing::warn;
+
+use crate::{
+    game_object::GameObject,
+    handle::ObjectHandle,
+    object::{ClassType, Object},
+    registry::Registry,
+    value::{AttrValue, Value},
+    walk::Order,
+    FactoryPtr, ObjectPtr,
+};
+
+pub type IndexesPtr = Rc<RefCell<Indexes>>;
+
+type Filter = Box<dyn Fn(&Object) -> bool>;
+
+/// 属性索引的描述，由 `#[attr(index)]` 或 `#[attr(unique)]` 生成
+#[derive(Debug, Clone)]
+pub struct IndexModel {
+    pub attr: u32,
+    pub unique: bool,
+}
+
+#[derive(Debug, Default)]
+struct Index {
+    unique: bool,
+    // 唯一索引不收录默认值，刚创建、还没有设置的对象不会互相冲突
+    default: Option<Vec<u8>>,
+    entries: HashMap<Vec<u8>, Vec<u64>>,
+    // 对象当前的值，属性修改时不需要旧值
+    keys: HashMap<u64, Vec<u8>>,
+}
+
+impl Index {
+    fn insert(&mut self, key: Vec<u8>, uid: u64, name: &(&str, &str)) {
+        self.remove(uid);
+        if self.is_default(&key) {
+            return;
+        }
+        let uids = self.entries.entry(key.clone()).or_default();
+        if self.unique && !uids.is_empty() {
+            warn!(
+                "duplicate value of unique index {}.{}, {} not indexed",
+                name.0, name.1, uid
+            );
+            return;
+        }
+        uids.push(uid);
+        self.keys.insert(uid, key);
+    }
+
+    fn is_default(&self, key: &[u8]) -> bool {
+        self.unique && self.default.as_deref() == Some(key)
+    }
+
+    fn remove(&mut self, uid: u64) {
+        let Some(key) = self.keys.remove(&uid) else {
+            return;
+        };
+        if let Some(uids) = self.entries.get_mut(&key) {
+            uids.retain(|&id| id != uid);
+            if uids.is_empty() {
+                self.entries.remove(&key);
+            }
+        }
+    }
+}
+
+/// 属性的二级索引，由 `Factory` 在创建、销毁对象和属性修改时维护
+///
+/// 值按编码后的字节比较，和 `AttrValue::to_value` 的结果一致。
+/// 唯一属性的 setter 拒绝其它对象已经使用的值，默认值不算使用，也不加入索引。
+/// 载入时出现重复值，记录警告，后来的对象不加入索引。
+#[derive(Debug, Default)]
+pub struct Indexes {
+    indexes: HashMap<(&'static str, &'static str), Index>,
+    // 有这个属性的类，没有指定类名的查询据此判断能否使用索引
+    classes: HashMap<&'static str, Vec<&'static str>>,
+}
+
+impl Indexes {
+    /// 按注册的实体建立空的索引
+    pub fn new(registry: &Registry) -> Self {
+        let mut indexes = Self::default();
+        for f in &registry.entity_vec {
+            let entity = f();
+            let entity = entity.borrow();
+            let model = entity.get_model();
+            for attr in &model.attrs {
+                indexes
+                    .classes
+                    .entry(attr)
+                    .or_default()
+                    .push(model.class_name);
+            }
+            for index_model in &model.indexes {
+                let name = (model.class_name, model.attrs[index_model.attr as usize]);
+                indexes.indexes.insert(
+                    name,
+                    Index {
+                        unique: index_model.unique,
+                        default: entity.get_value(index_model.attr).map(|v| encode_key(&v)),
+                        ..Default::default()
+                    },
+                );
+            }
+        }
+        indexes
+    }
+
+    pub fn has_index(&self, class_name: &'static str, attr: &'static str) -> bool {
+        self.indexes.contains_key(&(class_name, attr))
+    }
+
+    /// 按索引查找对象的 uid，没有这个索引或查找唯一属性的默认值时返回 None
+    pub fn find(
+        &self,
+        class_name: &'static str,
+        attr: &'static str,
+        value: &Value,
+    ) -> Option<&[u64]> {
+        let index = self.indexes.get(&(class_name, attr))?;
+        let key = encode_key(value);
+        if index.is_default(&key) {
+            return None;
+        }
+        Some(index.entries.get(&key).map_or(&[], Vec::as_slice))
+    }
+
+    /// 不指定类名时查找所有类，有这个属性的类都有索引时才返回
+    pub fn find_any(&self, attr: &'static str, value: &Value) -> Option<Vec<u64>> {
+        let classes = self.classes.get(attr)?;
+        let mut uids = Vec::new();
+        for class_name in classes {
+            uids.extend_from_slice(self.find(class_name, attr, value)?);
+        }
+        Some(uids)
+    }
+
+    /// 唯一索引中是否有 `uid` 以外的对象使用这个值
+    pub fn is_taken(
+        &self,
+        class_name: &'static str,
+        attr: &'static str,
+        value: &Value,
+        uid: u64,
+    ) -> bool {
+        self.indexes
+            .get(&(class_name, attr))
+            .filter(|index| index.unique)
+            .and_then(|index| index.entries.get(&encode_key(value)))
+            .is_some_and(|uids| uids.iter().any(|&id| id != uid))
+    }
+
+    /// 对象创建后加入索引
+    pub fn insert_object(&mut self, object: &Object) {
+        let game_model = object.game_model.borrow();
+        for index_model in &object.model.indexes {
+            let Some(value) = game_model.get_value(index_model.attr) else {
+                continue;
+            };
+            let name = (
+                object.model.class_name,
+                object.model.attrs[index_model.attr as usize],
+            );
+            self.indexes
+                .entry(name)
+                .or_insert_with(|| Index {
+                    unique: index_model.unique,
+                    ..Default::default()
+                })
+                .insert(encode_key(&value), object.uid, &name);
+        }
+    }
+
+    /// 重新读取对象所有有索引的属性
+    pub fn update_object(&mut self, object: &Object) {
+        self.remove_object(object);
+        self.insert_object(object);
+    }
+
+    /// 对象销毁时移除
+    pub fn remove_object(&mut self, object: &Object) {
+        for index_model in &object.model.indexes {
+            let name = (
+                object.model.class_name,
+                object.model.attrs[index_model.attr as usize],
+            );
+            if let Some(index) = self.indexes.get_mut(&name) {
+                index.remove(object.uid);
+            }
+        }
+    }
+
+    /// 处理修改时调用，读取属性当前的值
+    pub fn change_attr(&mut self, object: &Object, index: u32) {
+        if !object.model.indexes.iter().any(|i| i.attr == index) {
+            return;
+        }
+        let name = (object.model.class_name, object.model.attrs[index as usize]);
+        let Some(entry) = self.indexes.get_mut(&name) else {
+            return;
+        };
+        let Ok(game_model) = object.game_model.try_borrow() else {
+            warn!("{}.{} is borrowed, index not updated", name.0, name.1);
+            return;
+        };
+        match game_model.get_value(index) {
+            Some(value) => entry.insert(encode_key(&value), object.uid, &name),
+            None => entry.remove(object.uid),
+        }
+    }
+}
+
+fn encode_key(value: &Value) -> Vec<u8> {
+    let mut buf = Vec::new();
+    value.encode(&mut buf);
+    buf
+}
+
+/// 场景中的对象查询，由 `GameScene::query` 创建
+///
+/// 条件中的属性有索引时取所有索引结果的交集，否则遍历子树。
+/// 没有指定类名时，有这个属性的类都有索引才使用索引。
+pub struct Query {
+    factory: FactoryPtr,
+    root: ObjectPtr,
+    class_name: Option<&'static str>,
+    class_type: Option<ClassType>,
+    attrs: Vec<(&'static str, Value)>,
+    filters: Vec<Filter>,
+}
+
+impl Query {
+    pub fn new(factory: FactoryPtr, root: ObjectPtr) -> Self {
+        Self {
+            factory,
+            root,
+            class_name: None,
+            class_type: None,
+            attrs: Vec::new(),
+            filters: Vec::new(),
+        }
+    }
+
+    /// 只查找 `parent` 的后代，不包含 `parent` 自己
+    pub fn within(mut self, parent: &ObjectPtr) -> Self {
+        self.root = parent.clone();
+        self
+    }
+
+    pub fn class(mut self, class_name: &'static str) -> Self {
+        self.class_name = Some(class_name);
+        self
+    }
+
+    pub fn class_type(mut self, class_type: ClassType) -> Self {
+        self.class_type = Some(class_type);
+        self
+    }
+
+    /// 属性等于 `value`，`value` 使用属性本身的类型
+    pub fn attr<T: AttrValue>(mut self, attr: &'static str, value: T) -> Self {
+        self.attrs.push((attr, value.to_value()));
+        self
+    }
+
+    pub fn filter(mut self, f: impl Fn(&Object) -> bool + 'static) -> Self {
+        self.filters.push(Box::new(f));
+        self
+    }
+
+    pub fn all(&self) -> Vec<ObjectPtr> {
+        self.iter().collect()
+    }
+
+    /// 找到第一个就返回，不读取其它结果
+    pub fn first(&self) -> Option<ObjectPtr> {
+        self.iter().next()
+    }
+
+    /// 需要在查询之外记住结果时使用
+    pub fn handles(&self) -> Vec<ObjectHandle> {
+        self.iter().map(|object| object.borrow().handle()).collect()
+    }
+
+    pub fn count(&self) -> usize {
+        self.iter().count()
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = ObjectPtr> + '_> {
+        match self.indexed() {
+            Some(uids) => Box::new(
+                uids.into_iter()
+                    .filter_map(|uid| self.factory.borrow().find(uid))
+                    .filter(|object| self.in_root(object) && self.matches(object)),
+            ),
+            None => Box::new(
+                Object::descendants(&self.root, Order::DepthFirst)
+                    .filter(|object| self.matches(object)),
+            ),
+        }
+    }
+
+    /// 可以使用索引时返回候选的 uid，多个属性有索引时取交集
+    fn indexed(&self) -> Option<Vec<u64>> {
+        let indexes = self.factory.borrow().get_indexes();
+        let indexes = indexes.borrow();
+        let mut found = self
+            .attrs
+            .iter()
+            .filter_map(|(attr, value)| match self.class_name {
+                Some(class_name) => indexes.find(class_name, attr, value).map(<[u64]>::to_vec),
+                None => indexes.find_any(attr, value),
+            });
+        let mut uids = found.next()?;
+        for other in found {
+            let other: HashSet<u64> = other.into_iter().collect();
+            uids.retain(|uid| other.contains(uid));
+        }
+        Some(uids)
+    }
+
+    fn in_root(&self, object: &ObjectPtr) -> bool {
+        !Rc::ptr_eq(object, &self.root) && Object::is_descendant(object, &self.root)
+    }
+
+    fn matches(&self, object: &ObjectPtr) -> bool {
+        let object = object.borrow();
+        if object.is_deleted()
+            || self
+                .class_name
+                .is_some_and(|name| name != object.model.class_name)
+            || self.class_type.is_some_and(|ty| ty != object.class_type)
+        {
+            return false;
+        }
+        if !self.attrs.is_empty() {
+            let game_model = object.game_model.borrow();
+            let equal = |(attr, value): &(&str, Value)| {
+                object
+                    .get_attr_index(attr)
+                    .and_then(|index| game_model.get_value(index))
+                    .is_some_and(|v| &v == value)
+            };
+            if !self.attrs.iter().all(equal) {
+                return false;
+            }
+        }
+        self.filters.iter().all(|f| f(&object))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use re_ops::def_entity;
+
+    use super::*;
+    use crate::{
+        container::Container,
+        game_scene::GameScene,
+        testing::{self, TestBox, TestItem},
+        value::ValueError,
+    };
+
+    #[def_entity(class = Role)]
+    struct TestKnight {
+        #[attr(save, unique)]
+        name: String,
+        #[attr(save, index)]
+        level: u32,
+        #[attr(index)]
+        rank: u32,
+    }
+
+    fn knight(scene: &GameScene, name: &str, level: u32) -> ObjectPtr {
+        let knight = scene.create_in_scene(TestKnight::ClassName(), 2).unwrap();
+        Object::model_map_mut(&knight, |k: &mut TestKnight| {
+            k.set_name(name.to_string()).unwrap();
+            k.set_level(level);
+        });
+        knight
+    }
+
+    fn by_name(scene: &GameScene, name: &str) -> Option<ObjectPtr> {
+        scene
+            .query()
+            .class(TestKnight::ClassName())
+            .attr("name", name.to_string())
+            .first()
+    }
+
+    fn by_level(scene: &GameScene, level: u32) -> Query {
+        scene
+            .query()
+            .class(TestKnight::ClassName())
+            .attr("level", level)
+    }
+
+    #[test]
+    fn test_indexes() {
+        let scene = testing::scene();
+        knight(&scene, "arthur", 5);
+        let indexes = scene.factory.borrow().get_indexes();
+        let indexes = indexes.borrow();
+        assert!(indexes.has_index(TestKnight::ClassName(), "name"));
+        assert!(indexes.has_index(TestKnight::ClassName(), "level"));
+        assert!(!indexes.has_index(TestItem::ClassName(), "name"));
+        let found = indexes.find(TestKnight::ClassName(), "level", &Value::UInt(5));
+        assert_eq!(found.map(<[u64]>::len), Some(1));
+        let missing = indexes.find(TestKnight::ClassName(), "level", &Value::UInt(6));
+        assert_eq!(missing, Some(&[][..]));
+        assert!(indexes
+            .find(TestItem::ClassName(), "name", &Value::None)
+            .is_none());
+    }
+
+    #[test]
+    fn test_index_follows_changes() {
+        let scene = testing::scene();
+        let arthur = knight(&scene, "arthur", 5);
+        assert!(Rc::ptr_eq(&by_name(&scene, "arthur").unwrap(), &arthur));
+        Object::model_map_mut(&arthur, |k: &mut TestKnight| {
+            k.set_name("artorius".to_string())
+        })
+        .unwrap();
+        assert!(by_name(&scene, "arthur").is_none());
+        assert!(Rc::ptr_eq(&by_name(&scene, "artorius").unwrap(), &arthur));
+
+        Object::destroy_self(&arthur);
+        assert!(by_name(&scene, "artorius").is_none());
+    }
+
+    #[test]
+    fn test_indexed_query() {
+        let scene = testing::scene();
+        let arthur = knight(&scene, "arthur", 5);
+        knight(&scene, "lancelot", 5);
+        let galahad = knight(&scene, "galahad", 3);
+        Object::create(&arthur, TestBox::ClassName(), 2, 0).unwrap();
+
+        assert_eq!(by_level(&scene, 5).count(), 2);
+        assert_eq!(by_level(&scene, 4).count(), 0);
+        assert_eq!(by_level(&scene, 5).within(&arthur).count(), 0);
+        let handles = by_level(&scene, 3).handles();
+        assert_eq!(handles.len(), 1);
+        assert!(Rc::ptr_eq(&scene.resolve(handles[0]).unwrap(), &galahad));
+        let found = by_level(&scene, 5).filter(|o| o.child_count() > 0).all();
+        assert_eq!(found.len(), 1);
+        assert!(Rc::ptr_eq(&found[0], &arthur));
+        // 多个条件都要满足
+        assert_eq!(
+            by_level(&scene, 5)
+                .attr("name", "galahad".to_string())
+                .count(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_scan() {
+        let scene = testing::scene();
+        let arthur = knight(&scene, "arthur", 5);
+        let bag = Object::create(&arthur, TestBox::ClassName(), 2, 0).unwrap();
+        testing::item(&bag, 0, "sword");
+        testing::item(&bag, 0, "shield");
+
+        // 没有索引时遍历
+        let items = scene.query().class_type(ClassType::Item);
+        assert_eq!(items.count(), 2);
+        let shield = items.within(&bag).attr("name", "shield".to_string());
+        assert_eq!(shield.count(), 1);
+        // 属性类型不一致时不匹配
+        assert_eq!(scene.query().attr("name", 1u32).count(), 0);
+        assert_eq!(scene.query().within(&arthur).count(), 3);
+        assert!(scene
+            .query()
+            .within(&bag)
+            .class_type(ClassType::Role)
+            .first()
+            .is_none());
+    }
+
+    #[test]
+    fn test_unique_rejects_duplicate() {
+        let scene = testing::scene();
+        let arthur = knight(&scene, "arthur", 5);
+        let other = knight(&scene, "lancelot", 5);
+        let result = Object::model_map_mut(&other, |k: &mut TestKnight| {
+            k.set_name("arthur".to_string())
+        });
+        assert_eq!(result, Err(ValueError::Duplicate("name")));
+        Object::model_map(&other, |k: &TestKnight| assert_eq!(k.name, "lancelot"));
+        // 通过值修改时返回同样的错误
+        let index = other.borrow().get_attr_index("name").unwrap();
+        let value = Value::Str("arthur".to_string());
+        assert_eq!(
+            Object::set_value(&other, index, &value),
+            Err(ValueError::Duplicate("name"))
+        );
+        // 自己原来的值和释放的值可以使用
+        assert!(Object::set_value(&arthur, index, &value).is_ok());
+        Object::destroy_self(&arthur);
+        assert!(Object::set_value(&other, index, &value).is_ok());
+        assert!(Rc::ptr_eq(&by_name(&scene, "arthur").unwrap(), &other));
+    }
+
+    #[test]
+    fn test_unique_skips_default() {
+        let scene = testing::scene();
+        // 创建时还是默认值，互不冲突
+        let knights: Vec<_> = (0..3)
+            .map(|_| scene.create_in_scene(TestKnight::ClassName(), 2).unwrap())
+            .collect();
+        let indexes = scene.factory.borrow().get_indexes();
+        let empty = Value::Str(String::new());
+        assert!(indexes
+            .borrow()
+            .find(TestKnight::ClassName(), "name", &empty)
+            .is_none());
+        assert_eq!(
+            by_name(&scene, "").map(|k| k.borrow().uid()),
+            Some(knights[0].borrow().uid())
+        );
+        assert_eq!(scene.query().attr("name", String::new()).count(), 3);
+
+        let index = knights[0].borrow().get_attr_index("name").unwrap();
+        for (knight, name) in knights.iter().zip(["arthur", "lancelot", "galahad"]) {
+            let value = Value::Str(name.to_string());
+            assert!(Object::set_value(knight, index, &value).is_ok());
+            assert!(Rc::ptr_eq(&by_name(&scene, name).unwrap(), knight));
+        }
+        // 改回默认值不占用
+        assert!(Object::set_value(&knights[0], index, &empty).is_ok());
+        assert!(Object::set_value(&knights[1], index, &empty).is_ok());
+        assert!(by_name(&scene, "arthur").is_none());
+        assert_eq!(by_level(&scene, 0).count(), 3);
+    }
+
+    #[test]
+    fn test_intersect_indexes() {
+        let scene = testing::scene();
+        let arthur = knight(&scene, "arthur", 5);
+        knight(&scene, "lancelot", 5);
+        let query = by_level(&scene, 5).attr("name", "arthur".to_string());
+        assert_eq!(query.indexed().map(|uids| uids.len()), Some(1));
+        assert!(Rc::ptr_eq(&query.first().unwrap(), &arthur));
+        // 没有索引的条件只用来过滤
+        let query = by_level(&scene, 5).filter(|o| o.child_count() == 0);
+        assert_eq!(query.indexed().map(|uids| uids.len()), Some(2));
+    }
+
+    #[test]
+    fn test_index_without_class() {
+        let scene = testing::scene();
+        let arthur = knight(&scene, "arthur", 5);
+        Object::model_map_mut(&arthur, |k: &mut TestKnight| k.set_rank(2));
+        // rank 只有 TestKnight 有，使用索引
+        let query = scene.query().attr("rank", 2u32);
+        assert_eq!(query.indexed(), Some(vec![arthur.borrow().uid()]));
+        assert_eq!(query.count(), 1);
+        // name 在其它类中没有索引，遍历
+        let query = scene.query().attr("name", "arthur".to_string());
+        assert!(query.indexed().is_none());
+        assert!(Rc::ptr_eq(&query.first().unwrap(), &arthur));
+    }
+}
@@ -0,0 +1,426 @@
+use std::{borrow::Cow, fmt};
+
+use tracing::warn;
+
+use crate::{
+    container::Container,
+    game_object::GameObject,
+    lifecycle,
+    object::Object,
+    value::{AttrValue, Value, ValueError},
+    ObjectPtr,
+};
+
+/// 对象子树的存档数据，只包含 `#[attr(save)]` 属性
+///
+/// 不保存 uid，uid 在载入时由 `Factory` 重新分配。属性按名字保存，
+/// 载入时如果 `version` 比类的版本旧，先执行注册的 `Migration`。
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ObjectRecord {
+    pub class_name: String,
+    pub version: u32,
+    pub cap: usize,
+    /// 在父对象容器中的位置，从 1 开始
+    pub pos: usize,
+    pub attrs: Vec<(String, Value)>,
+    pub children: Vec<ObjectRecord>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RecordError {
+    /// 类不存在或者容器位置已被占用
+    Create { class_name: String, pos: usize },
+    /// 存档比当前代码的版本新
+    Version {
+        class_name: String,
+        found: u32,
+        current: u32,
+    },
+    Attr {
+        class_name: String,
+        attr: String,
+        error: ValueError,
+    },
+}
+
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordError::Create { class_name, pos } => {
+                write!(f, "create {} at {} failed", class_name, pos)
+            }
+            RecordError::Version {
+                class_name,
+                found,
+                current,
+            } => write!(
+                f,
+                "{} version {} is newer than {}",
+                class_name, found, current
+            ),
+            RecordError::Attr {
+                class_name,
+                attr,
+                error,
+            } => write!(f, "{}.{}: {}", class_name, attr, error),
+        }
+    }
+}
+
+impl std::error::Error for RecordError {}
+
+impl ObjectRecord {
+    /// 保存对象和所有子对象
+    pub fn capture(object: &ObjectPtr) -> Self {
+        Self::capture_with(object, false)
+    }
+
+    /// 和 `capture` 一样，但包含所有有 `Value` 读写的属性，用于快照
+    pub fn capture_all(object: &ObjectPtr) -> Self {
+        Self::capture_with(object, true)
+    }
+
+    fn capture_with(object: &ObjectPtr, all: bool) -> Self {
+        let object = object.borrow();
+        let model = object.game_model.borrow();
+        let value = |index: u32, name: &str| Some((name.to_string(), model.get_value(index)?));
+        let attrs = if all {
+            (0..object.get_attr_count())
+                .zip(object.get_attrs())
+                .filter_map(|(index, &name)| value(index, name))
+                .collect()
+        } else {
+            object
+                .save_attrs_index()
+                .iter()
+                .zip(object.save_attrs())
+                .filter_map(|(&index, &name)| value(index, name))
+                .collect()
+        };
+        let children = object
+            .children
+            .iter()
+            .flatten()
+            .map(|child| Self::capture_with(child, all))
+            .collect();
+        Self {
+            class_name: object.model.class_name.to_string(),
+            version: object.model.version,
+            cap: object.capacity(),
+            pos: object.get_container_pos(),
+            attrs,
+            children,
+        }
+    }
+
+    /// 在 `parent` 下创建对象并载入，失败时销毁已创建的部分
+    ///
+    /// 属性在放入 `parent` 之前写入，`on_create` 时属性已经就绪。
+    pub fn restore(&self, parent: &ObjectPtr) -> Result<ObjectPtr, RecordError> {
+        let create_error = || RecordError::Create {
+            class_name: self.class_name.clone(),
+            pos: self.pos,
+        };
+        let object = parent
+            .borrow()
+            .create_detached(&self.class_name, self.cap)
+            .ok_or_else(create_error)?;
+        if let Err(err) = self.load(&object) {
+            let factory = object.borrow().get_factory();
+            if let Some(factory) = factory {
+                factory.borrow_mut().destroy(&object);
+            }
+            return Err(err);
+        }
+        let placed = parent.borrow_mut().place_created(&object, self.pos);
+        Object::flush_deferred(parent);
+        if !placed {
+            return Err(create_error());
+        }
+        if let Err(err) = self.load_children(&object) {
+            Object::destroy_self(&object);
+            return Err(err);
+        }
+        Ok(object)
+    }
+
+    /// 载入到已有的对象，例如场景根对象，载入后清除脏标记和修改记录
+    pub fn apply(&self, object: &ObjectPtr) -> Result<(), RecordError> {
+        self.load(object)?;
+        self.load_children(object)
+    }
+
+    /// 直接写入属性，不标记脏，也不通知观察者
+    fn load(&self, object: &ObjectPtr) -> Result<(), RecordError> {
+        let attrs = self.migrate(object)?;
+        let object = object.borrow();
+        let result = {
+            let mut model = object.game_model.borrow_mut();
+            attrs.iter().try_for_each(|(name, value)| {
+                let Some(index) = object.get_attr_index(name) else {
+                    warn!("{} has no attribute {}, skip", self.class_name, name);
+                    return Ok(());
+                };
+                model
+                    .set_value(index, value)
+                    .map_err(|error| RecordError::Attr {
+                        class_name: self.class_name.clone(),
+                        attr: name.clone(),
+                        error,
+                    })
+            })
+        };
+        object.loaded();
+        result
+    }
+
+    fn load_children(&self, object: &ObjectPtr) -> Result<(), RecordError> {
+        for child in &self.children {
+            child.restore(object)?;
+        }
+        {
+            let mut object = object.borrow_mut();
+            object.clear_dirty();
+            object.clear_modify();
+        }
+        lifecycle::fire(object, |hooks, object| hooks.on_load(object));
+        Object::flush_deferred(object);
+        Ok(())
+    }
+
+    fn migrate(&self, object: &ObjectPtr) -> Result<Cow<'_, [(String, Value)]>, RecordError> {
+        let object = object.borrow();
+        let current = object.model.version;
+        if self.version > current {
+            return Err(RecordError::Version {
+                class_name: self.class_name.clone(),
+                found: self.version,
+                current,
+            });
+        }
+        let registry = match object.get_factory() {
+            Some(factory) if self.version < current => factory.borrow().get_registry(),
+            _ => return Ok(Cow::Borrowed(&self.attrs)),
+        };
+        let mut attrs = self.attrs.clone();
+        registry
+            .migrate(&self.class_name, self.version, current, &mut attrs)
+            .map_err(|(attr, error)| RecordError::Attr {
+                class_name: self.class_name.clone(),
+                attr,
+                error,
+            })?;
+        Ok(Cow::Owned(attrs))
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        self.to_value().encode(&mut buf);
+        buf
+    }
+
+    pub fn decode(mut buf: &[u8]) -> Result<Self, ValueError> {
+        Self::from_value(&Value::decode(&mut buf)?)
+    }
+}
+
+impl AttrValue for ObjectRecord {
+    fn to_value(&self) -> Value {
+        Value::Map(vec![
+            ("class".to_string(), self.class_name.to_value()),
+            ("version".to_string(), self.version.to_value()),
+            ("cap".to_string(), self.cap.to_value()),
+            ("pos".to_string(), self.pos.to_value()),
+            ("attrs".to_string(), Value::Map(self.attrs.clone())),
+            ("children".to_string(), self.children.to_value()),
+        ])
+    }
+
+    fn from_value(value: &Value) -> Result<Self, ValueError> {
+        let attrs = match value.field("attrs")? {
+            Value::Map(attrs) => attrs.clone(),
+            other => return Err(other.mismatch("map")),
+        };
+        // 加入版本之前的存档没有这个字段
+        let version = match value.field("version") {
+            Ok(version) => u32::from_value(version)?,
+            Err(ValueError::MissingField(_)) => 0,
+            Err(err) => return Err(err),
+        };
+        Ok(Self {
+            class_name: String::from_value(value.field("class")?)?,
+            version,
+            cap: usize::from_value(value.field("cap")?)?,
+            pos: usize::from_value(value.field("pos")?)?,
+            attrs,
+            children: Vec::from_value(value.field("children")?)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use re_ops::def_entity;
+
+    use super::*;
+    use crate::{
+        lifecycle::Lifecycle,
+        observer::{AttrChange, Dispatch},
+        testing::{self, TestBox, TestItem, TestPlayer},
+    };
+
+    #[def_entity(class = Container, hooks)]
+    struct TestLamp {
+        #[attr(save, index)]
+        fuel: u32,
+    }
+
+    thread_local! {
+        static EVENTS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+    }
+
+    impl Lifecycle for TestLamp {
+        fn on_create(&mut self, _object: &ObjectPtr) {
+            let event = format!("create {}", self.fuel);
+            EVENTS.with(|e| e.borrow_mut().push(event));
+        }
+
+        fn on_load(&mut self, object: &ObjectPtr) {
+            let event = format!("load {} {}", self.fuel, object.borrow().child_count());
+            EVENTS.with(|e| e.borrow_mut().push(event));
+        }
+    }
+
+    /// 玩家带一个 4 格的背包，背包第 3 格有一把剑
+    fn hero(parent: &ObjectPtr) -> ObjectPtr {
+        let player = Object::create(parent, TestPlayer::ClassName(), 0, 0).unwrap();
+        Object::model_map_mut(&player, |p: &mut TestPlayer| {
+            p.set_name("hero".to_string());
+            p.set_age(30);
+        });
+        let bag = Object::create(&player, TestBox::ClassName(), 4, 0).unwrap();
+        testing::item(&bag, 3, "sword");
+        player
+    }
+
+    #[test]
+    fn test_capture() {
+        let scene = testing::scene();
+        let record = ObjectRecord::capture(&hero(&scene.scene_object));
+        // age 没有 save 标记
+        assert_eq!(
+            record.attrs,
+            vec![("name".to_string(), Value::Str("hero".to_string()))]
+        );
+        assert_eq!(record.children[0].cap, 4);
+        assert_eq!(record.children[0].children[0].pos, 3);
+        assert_eq!(ObjectRecord::decode(&record.encode()), Ok(record.clone()));
+
+        let all = ObjectRecord::capture_all(&scene.scene_object.borrow().child_at(1).unwrap());
+        assert!(all.attrs.contains(&("age".to_string(), Value::Int(30))));
+        assert!(ObjectRecord::decode(&[1]).is_err());
+    }
+
+    #[test]
+    fn test_restore() {
+        let scene = testing::scene();
+        let record = ObjectRecord::capture(&hero(&scene.scene_object));
+
+        let other = testing::scene();
+        let loaded = record.restore(&other.scene_object).unwrap();
+        assert!(!loaded.borrow().dirty());
+        assert!(!loaded.borrow().modify());
+        assert_eq!(ObjectRecord::capture(&loaded), record);
+        Object::model_map(&loaded, |p: &TestPlayer| assert_eq!(p.name, "hero"));
+        let sword = loaded.borrow().child_at(1).unwrap().borrow().child_at(3);
+        Object::model_map(&sword.unwrap(), |i: &TestItem| assert_eq!(i.name, "sword"));
+    }
+
+    #[test]
+    fn test_restore_skips_unknown_attr() {
+        let scene = testing::scene();
+        let mut record = ObjectRecord::capture(&hero(&scene.scene_object));
+        record
+            .attrs
+            .push(("removed".to_string(), Value::Bool(true)));
+        assert!(record.restore(&scene.scene_object).is_ok());
+    }
+
+    #[test]
+    fn test_restore_rollback() {
+        let scene = testing::scene();
+        let record = ObjectRecord::capture(&hero(&scene.scene_object));
+        let other = testing::scene();
+
+        // 子对象的属性类型不对，已创建的部分被销毁
+        let mut bad = record.clone();
+        bad.children[0].children[0].attrs[0].1 = Value::Int(1);
+        assert!(matches!(
+            bad.restore(&other.scene_object),
+            Err(RecordError::Attr { ref attr, .. }) if attr == "name"
+        ));
+        assert_eq!(other.scene_object.borrow().child_count(), 0);
+
+        bad.class_name = "Missing".to_string();
+        assert!(matches!(
+            bad.restore(&other.scene_object),
+            Err(RecordError::Create { .. })
+        ));
+
+        // 位置已被占用
+        let mut taken = record.clone();
+        taken.children[0]
+            .children
+            .push(record.children[0].children[0].clone());
+        assert!(matches!(
+            taken.restore(&other.scene_object),
+            Err(RecordError::Create { pos: 3, .. })
+        ));
+        assert_eq!(other.scene_object.borrow().child_count(), 0);
+    }
+
+    #[test]
+    fn test_load_hooks() {
+        let scene = testing::scene();
+        let lamp = Object::create(&scene.scene_object, TestLamp::ClassName(), 2, 0).unwrap();
+        Object::model_map_mut(&lamp, |l: &mut TestLamp| l.set_fuel(3));
+        testing::item(&lamp, 1, "wick");
+        let record = ObjectRecord::capture(&lamp);
+        EVENTS.with(|e| e.borrow_mut().clear());
+
+        let other = testing::scene();
+        let changes = Rc::new(RefCell::new(0));
+        let count = changes.clone();
+        other.observers().borrow_mut().on_class(
+            TestLamp::ClassName(),
+            "fuel",
+            Dispatch::Immediate,
+            move |_: &AttrChange<u32>| *count.borrow_mut() += 1,
+        );
+        let loaded = record.restore(&other.scene_object).unwrap();
+        // 属性在 on_create 之前载入，on_load 时子对象也已载入
+        let events = EVENTS.with(|e| std::mem::take(&mut *e.borrow_mut()));
+        assert_eq!(events, ["create 3", "load 3 1"]);
+        // 载入不通知观察者，索引只更新一次
+        assert_eq!(*changes.borrow(), 0);
+        let found = other
+            .query()
+            .class(TestLamp::ClassName())
+            .attr("fuel", 3u32);
+        assert!(Rc::ptr_eq(&found.first().unwrap(), &loaded));
+        let stale = other
+            .query()
+            .class(TestLamp::ClassName())
+            .attr("fuel", 0u32);
+        assert_eq!(stale.count(), 0);
+
+        // 载入到已有的对象也执行 on_load
+        loaded.borrow_mut().destroy_children();
+        record.apply(&loaded).unwrap();
+        let events = EVENTS.with(|e| std::mem::take(&mut *e.borrow_mut()));
+        assert_eq!(events, ["load 3 1"]);
+        assert_eq!(*changes.borrow(), 0);
+    }
+}
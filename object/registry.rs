@@ -1,6 +1,10 @@
 use std::collections::HashMap;
 
-use crate::GameModelPtr;
+use crate::{
+    migration::{Attrs, Migration},
+    value::ValueError,
+    GameModelPtr,
+};
 
 #[allow(dead_code)]
 pub struct ObjectInitializer {
@@ -20,6 +24,8 @@ inventory::collect!(ObjectInitializer);
 pub struct Registry {
     pub entity_vec: Vec<fn() -> GameModelPtr>,
     pub entity_index: HashMap<&'static str, usize>,
+    /// 按类名分组，按版本排序
+    pub migrations: HashMap<&'static str, Vec<&'static Migration>>,
 }
 
 impl Registry {
@@ -34,10 +40,42 @@ impl Registry {
             entity_vec.push(initializer.f);
             index_map.insert(initializer.name, entity_idx);
         }
+        let mut migrations: HashMap<&'static str, Vec<&'static Migration>> = HashMap::new();
+        for migration in inventory::iter::<Migration> {
+            migrations
+                .entry(migration.class_name)
+                .or_default()
+                .push(migration);
+        }
+        migrations
+            .values_mut()
+            .for_each(|steps| steps.sort_by_key(|step| step.version));
         Self {
-            entity_vec: entity_vec,
+            entity_vec,
             entity_index: index_map,
+            migrations,
+        }
+    }
+
+    /// 把 `from` 版本的存档属性升级到 `to` 版本
+    ///
+    /// 同一版本的多个步骤执行顺序不确定，有依赖的修改放到不同版本或者用 `Custom`。
+    pub fn migrate(
+        &self,
+        class_name: &str,
+        from: u32,
+        to: u32,
+        attrs: &mut Attrs,
+    ) -> Result<(), (String, ValueError)> {
+        let Some(steps) = self.migrations.get(class_name) else {
+            return Ok(());
+        };
+        for step in steps {
+            if step.version > from && step.version <= to {
+                step.apply(attrs)?;
+            }
         }
+        Ok(())
     }
 
     pub fn get_class_index(&self, entity: &str) -> Option<usize> {
@@ -0,0 +1,458 @@
+use std::{
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
+
+use tracing::error;
+
+use crate::{
+    container::Container, game_object::GameObject, object::Object, ObjectPtr, WeakObjectPtr,
+};
+
+/// 对象第一次对观察者可见，带所有同步属性
+pub const RECORD_SNAPSHOT: u8 = 1;
+/// 上一次同步后变化的属性
+pub const RECORD_DELTA: u8 = 2;
+/// 对象对观察者不再可见，或者已经删除
+pub const RECORD_REMOVE: u8 = 3;
+/// 对象被移动到另一个容器或者位置
+pub const RECORD_MOVE: u8 = 4;
+
+/// 把同步属性的变化按观察者打包
+///
+/// 每个包由若干条记录组成，整数都是小端：
+///
+/// ```text
+/// snapshot: [1:u8][uid:u64][class_len:u16][class:utf8][count:u16]([index:u16][value])*
+/// delta:    [2:u8][uid:u64][count:u16]([index:u16][value])*
+/// remove:   [3:u8][uid:u64]
+/// move:     [4:u8][uid:u64][parent:u64][pos:u16]
+/// ```
+///
+/// `value` 的编码见 `Value::encode`。
+///
+/// 同一个观察者的记录按 remove、snapshot、move 和 delta 的顺序排列，
+/// 超过 `max_packet_len` 时拆成多个包，记录不会跨包。单条记录超过 `max_packet_len`
+/// 时无法发送，记录错误并丢弃。
+#[derive(Debug)]
+pub struct Replicator {
+    viewers: HashMap<u64, Viewer>,
+    objects: HashMap<u64, Watched>,
+    max_packet_len: usize,
+}
+
+impl Default for Replicator {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_PACKET_LEN)
+    }
+}
+
+/// 留出消息头的空间，保证小于默认的最大帧长度
+pub const DEFAULT_MAX_PACKET_LEN: usize = 60 * 1024;
+
+#[derive(Debug, Default)]
+struct Viewer {
+    visible: HashSet<u64>,
+    // 本次 collect 需要发送快照的对象
+    spawned: Vec<u64>,
+    removed: Vec<u64>,
+}
+
+#[derive(Debug)]
+struct Watched {
+    object: WeakObjectPtr,
+    viewers: HashSet<u64>,
+}
+
+impl Replicator {
+    pub fn new(max_packet_len: usize) -> Self {
+        Self {
+            viewers: HashMap::new(),
+            objects: HashMap::new(),
+            max_packet_len,
+        }
+    }
+
+    /// 例如按连接的最大帧长度调整，之后收集的包生效
+    pub fn set_max_packet_len(&mut self, max_packet_len: usize) {
+        self.max_packet_len = max_packet_len;
+    }
+
+    /// 对象对观察者可见，下一次 `collect` 时发送快照
+    pub fn show(&mut self, viewer: u64, object: &ObjectPtr) -> bool {
+        let uid = object.borrow().uid();
+        let state = self.viewers.entry(viewer).or_default();
+        if !state.visible.insert(uid) {
+            return false;
+        }
+        match state.removed.iter().position(|&id| id == uid) {
+            // 同一帧内先隐藏再显示，重新发送快照
+            Some(pos) => {
+                state.removed.swap_remove(pos);
+                state.spawned.push(uid);
+            }
+            None => state.spawned.push(uid),
+        }
+        self.objects
+            .entry(uid)
+            .or_insert_with(|| Watched {
+                object: Rc::downgrade(object),
+                viewers: HashSet::new(),
+            })
+            .viewers
+            .insert(viewer);
+        true
+    }
+
+    pub fn hide(&mut self, viewer: u64, uid: u64) -> bool {
+        let Some(state) = self.viewers.get_mut(&viewer) else {
+            return false;
+        };
+        if !state.visible.remove(&uid) {
+            return false;
+        }
+        match state.spawned.iter().position(|&id| id == uid) {
+            // 快照还没有发出去
+            Some(pos) => {
+                state.spawned.swap_remove(pos);
+            }
+            None => state.removed.push(uid),
+        }
+        self.unwatch(viewer, uid);
+        true
+    }
+
+    /// 观察者离开，例如会话断开
+    pub fn remove_viewer(&mut self, viewer: u64) {
+        if let Some(state) = self.viewers.remove(&viewer) {
+            for uid in state.visible {
+                self.unwatch(viewer, uid);
+            }
+        }
+    }
+
+    pub fn is_visible(&self, viewer: u64, uid: u64) -> bool {
+        self.viewers
+            .get(&viewer)
+            .is_some_and(|state| state.visible.contains(&uid))
+    }
+
+    pub fn viewers(&self) -> impl Iterator<Item = u64> + '_ {
+        self.viewers.keys().copied()
+    }
+
+    fn unwatch(&mut self, viewer: u64, uid: u64) {
+        if let Some(watched) = self.objects.get_mut(&uid) {
+            watched.viewers.remove(&viewer);
+            if watched.viewers.is_empty() {
+                self.objects.remove(&uid);
+            }
+        }
+    }
+
+    /// 每帧调用一次，返回每个观察者要发送的包，并清除对象的修改记录
+    ///
+    /// 同一个观察者可能有多个包，需要按返回的顺序发送。
+    pub fn collect(&mut self) -> Vec<(u64, Vec<u8>)> {
+        let mut packets: HashMap<u64, Vec<Vec<u8>>> = HashMap::new();
+        let mut deltas: Vec<(Vec<u8>, Vec<u64>)> = Vec::new();
+        let mut gone = Vec::new();
+
+        for (&uid, watched) in self.objects.iter() {
+            let object = match watched.object.upgrade() {
+                Some(object) if !object.borrow().is_deleted() => object,
+                _ => {
+                    gone.push(uid);
+                    continue;
+                }
+            };
+            Object::flush_changes(&object);
+            let (modify, moved) = {
+                let object = object.borrow();
+                (object.modify(), object.moved)
+            };
+            if !modify && !moved {
+                continue;
+            }
+            let mut record = Vec::new();
+            {
+                let object = object.borrow();
+                if moved {
+                    write_move(&object, &mut record);
+                }
+                if modify {
+                    write_delta(&object, &mut record);
+                }
+            }
+            {
+                let mut object = object.borrow_mut();
+                object.clear_modify();
+                object.moved = false;
+            }
+            let viewers = watched
+                .viewers
+                .iter()
+                .copied()
+                .filter(|viewer| {
+                    // 新可见的对象发快照，不需要再发增量
+                    !self.viewers[viewer].spawned.contains(&uid)
+                })
+                .collect();
+            deltas.push((record, viewers));
+        }
+
+        for uid in gone {
+            if let Some(watched) = self.objects.remove(&uid) {
+                for viewer in watched.viewers {
+                    if let Some(state) = self.viewers.get_mut(&viewer) {
+                        state.visible.remove(&uid);
+                        match state.spawned.iter().position(|&id| id == uid) {
+                            Some(pos) => {
+                                state.spawned.swap_remove(pos);
+                            }
+                            None => state.removed.push(uid),
+                        }
+                    }
+                }
+            }
+        }
+
+        let max = self.max_packet_len;
+        let mut record = Vec::new();
+        for (&viewer, state) in self.viewers.iter_mut() {
+            let packets = packets.entry(viewer).or_default();
+            for uid in state.removed.drain(..) {
+                record.clear();
+                record.push(RECORD_REMOVE);
+                record.extend_from_slice(&uid.to_le_bytes());
+                push_record(packets, &record, max);
+            }
+            for uid in state.spawned.drain(..) {
+                let Some(object) = self.objects.get(&uid).and_then(|w| w.object.upgrade()) else {
+                    continue;
+                };
+                record.clear();
+                write_snapshot(&object.borrow(), &mut record);
+                push_record(packets, &record, max);
+            }
+        }
+
+        for (record, viewers) in deltas {
+            for viewer in viewers {
+                push_record(packets.entry(viewer).or_default(), &record, max);
+            }
+        }
+
+        packets
+            .into_iter()
+            .flat_map(|(viewer, packets)| packets.into_iter().map(move |p| (viewer, p)))
+            .collect()
+    }
+}
+
+fn push_record(packets: &mut Vec<Vec<u8>>, record: &[u8], max: usize) {
+    if record.len() > max {
+        let uid = u64::from_le_bytes(record[1..9].try_into().unwrap());
+        error!(
+            "record {} of {} is {} bytes, larger than max packet {}, dropped",
+            record[0],
+            uid,
+            record.len(),
+            max
+        );
+        return;
+    }
+    match packets.last_mut() {
+        Some(packet) if packet.len() + record.len() <= max => packet.extend_from_slice(record),
+        _ => packets.push(record.to_vec()),
+    }
+}
+
+fn write_snapshot(object: &Object, buf: &mut Vec<u8>) {
+    buf.push(RECORD_SNAPSHOT);
+    buf.extend_from_slice(&object.uid().to_le_bytes());
+    let class_name = object.model.class_name.as_bytes();
+    buf.extend_from_slice(&(class_name.len() as u16).to_le_bytes());
+    buf.extend_from_slice(class_name);
+    write_attrs(object, object.rep_attrs_index(), buf);
+}
+
+fn write_move(object: &Object, buf: &mut Vec<u8>) {
+    buf.push(RECORD_MOVE);
+    buf.extend_from_slice(&object.uid().to_le_bytes());
+    let parent = object
+        .get_parent()
+        .map_or(0, |parent| parent.borrow().uid());
+    buf.extend_from_slice(&parent.to_le_bytes());
+    buf.extend_from_slice(&(object.get_container_pos() as u16).to_le_bytes());
+}
+
+fn write_delta(object: &Object, buf: &mut Vec<u8>) {
+    buf.push(RECORD_DELTA);
+    buf.extend_from_slice(&object.uid().to_le_bytes());
+    write_attrs(object, object.get_modify(), buf);
+}
+
+fn write_attrs(object: &Object, indexes: &[u32], buf: &mut Vec<u8>) {
+    buf.extend_from_slice(&(indexes.len() as u16).to_le_bytes());
+    let model = object.game_model.borrow();
+    for &index in indexes {
+        buf.extend_from_slice(&(index as u16).to_le_bytes());
+        model.get_value(index).unwrap_or_default().encode(buf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        testing::{self, TestPlayer},
+        value::Value,
+    };
+
+    fn shown(replicator: &mut Replicator, object: &ObjectPtr) {
+        assert!(replicator.show(1, object));
+        assert_eq!(replicator.collect().len(), 1);
+    }
+
+    #[test]
+    fn test_snapshot_on_show() {
+        let scene = testing::scene();
+        let player = testing::player(&scene);
+        let uid = player.borrow().uid();
+        Object::model_map_mut(&player, |p: &mut TestPlayer| p.set_age(18));
+
+        let mut replicator = Replicator::default();
+        assert!(replicator.collect().is_empty());
+        assert!(replicator.show(1, &player));
+        assert!(!replicator.show(1, &player));
+        let packets = replicator.collect();
+        assert_eq!(packets.len(), 1);
+        let (viewer, packet) = &packets[0];
+        assert_eq!(*viewer, 1);
+        assert_eq!(packet[0], RECORD_SNAPSHOT);
+        assert_eq!(&packet[1..9], &uid.to_le_bytes());
+        let class = TestPlayer::ClassName().as_bytes();
+        assert_eq!(&packet[11..11 + class.len()], class);
+        // name 和 age 两个同步属性
+        assert_eq!(
+            &packet[11 + class.len()..13 + class.len()],
+            &2u16.to_le_bytes()
+        );
+        // 快照已经包含修改，不再发送增量
+        assert!(!player.borrow().modify());
+        assert!(replicator.collect().is_empty());
+    }
+
+    #[test]
+    fn test_delta_keeps_last_value() {
+        let scene = testing::scene();
+        let player = testing::player(&scene);
+        let uid = player.borrow().uid();
+        let mut replicator = Replicator::default();
+        shown(&mut replicator, &player);
+
+        Object::model_map_mut(&player, |p: &mut TestPlayer| {
+            p.set_age(19);
+            p.set_age(20);
+        });
+        let age = player.borrow().get_attr_index("age").unwrap() as u16;
+        let mut expect = vec![RECORD_DELTA];
+        expect.extend_from_slice(&uid.to_le_bytes());
+        expect.extend_from_slice(&1u16.to_le_bytes());
+        expect.extend_from_slice(&age.to_le_bytes());
+        Value::Int(20).encode(&mut expect);
+        assert_eq!(replicator.collect(), vec![(1, expect)]);
+        assert!(replicator.collect().is_empty());
+    }
+
+    #[test]
+    fn test_remove_on_destroy() {
+        let scene = testing::scene();
+        let player = testing::player(&scene);
+        let uid = player.borrow().uid();
+        let mut replicator = Replicator::default();
+        shown(&mut replicator, &player);
+
+        Object::destroy_self(&player);
+        let packets = replicator.collect();
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].1[0], RECORD_REMOVE);
+        assert_eq!(&packets[0].1[1..], &uid.to_le_bytes());
+        assert!(!replicator.is_visible(1, uid));
+        assert!(replicator.collect().is_empty());
+    }
+
+    #[test]
+    fn test_hide_and_show_in_one_frame() {
+        let scene = testing::scene();
+        let player = testing::player(&scene);
+        let uid = player.borrow().uid();
+        let mut replicator = Replicator::default();
+
+        // 快照还没发出去就隐藏，什么都不发
+        replicator.show(1, &player);
+        assert!(replicator.hide(1, uid));
+        assert!(!replicator.hide(1, uid));
+        assert!(replicator.collect().is_empty());
+
+        shown(&mut replicator, &player);
+        replicator.hide(1, uid);
+        replicator.show(1, &player);
+        let packets = replicator.collect();
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].1[0], RECORD_SNAPSHOT);
+    }
+
+    #[test]
+    fn test_remove_viewer() {
+        let scene = testing::scene();
+        let player = testing::player(&scene);
+        let uid = player.borrow().uid();
+        let mut replicator = Replicator::default();
+        shown(&mut replicator, &player);
+        replicator.show(2, &player);
+
+        replicator.remove_viewer(1);
+        assert!(!replicator.is_visible(1, uid));
+        assert_eq!(replicator.viewers().collect::<Vec<_>>(), [2]);
+        Object::model_map_mut(&player, |p: &mut TestPlayer| p.set_age(1));
+        let packets = replicator.collect();
+        assert!(packets.iter().all(|(viewer, _)| *viewer == 2));
+    }
+
+    #[test]
+    fn test_split_packets() {
+        let scene = testing::scene();
+        let a = testing::player(&scene);
+        let b = testing::player(&scene);
+        let mut replicator = Replicator::default();
+        replicator.show(1, &a);
+        let len = replicator.collect()[0].1.len();
+
+        // 包长只放得下一条快照，各占一个包
+        let mut replicator = Replicator::new(len * 3 / 2);
+        replicator.show(1, &a);
+        replicator.show(1, &b);
+        let packets = replicator.collect();
+        assert_eq!(packets.len(), 2);
+        assert!(packets
+            .iter()
+            .all(|(_, p)| p[0] == RECORD_SNAPSHOT && p.len() == len));
+    }
+
+    #[test]
+    fn test_oversized_record() {
+        let scene = testing::scene();
+        let player = testing::player(&scene);
+        let mut replicator = Replicator::new(16);
+        replicator.show(1, &player);
+        // 快照超过包长，丢弃而不是发出超长的包
+        assert!(replicator.collect().is_empty());
+        // 放得下的记录照常发送
+        assert!(replicator.hide(1, player.borrow().uid()));
+        let packets = replicator.collect();
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].1[0], RECORD_REMOVE);
+    }
+}
@@ -0,0 +1,262 @@
+use std::fmt;
+
+use serde_json::Number;
+
+use crate::{
+    game_object::GameObject,
+    record::{ObjectRecord, RecordError},
+    value::{AttrValue, Value, ValueError},
+    ObjectPtr,
+};
+
+/// 二进制快照的文件头
+const MAGIC: &[u8; 4] = b"RESN";
+const VERSION: u8 = 1;
+
+/// 对象子树的完整快照，包含所有有 `Value` 读写的属性、容器容量和位置
+///
+/// JSON 用于调试和测试数据，二进制用于线上，例如崩溃转储和跨服迁移。
+/// 两种格式都自带属性名和类型，不需要对照类定义就能查看。
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub root: ObjectRecord,
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Json(serde_json::Error),
+    /// 文件头或者版本不对
+    BadHeader,
+    /// 载入到场景时类名不一致
+    Class {
+        expected: String,
+        found: String,
+    },
+    Value(ValueError),
+    Record(RecordError),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Json(err) => write!(f, "json: {}", err),
+            SnapshotError::BadHeader => write!(f, "bad snapshot header"),
+            SnapshotError::Class { expected, found } => {
+                write!(f, "expected class {}, found {}", expected, found)
+            }
+            SnapshotError::Value(err) => write!(f, "{}", err),
+            SnapshotError::Record(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<ValueError> for SnapshotError {
+    fn from(err: ValueError) -> Self {
+        SnapshotError::Value(err)
+    }
+}
+
+impl From<RecordError> for SnapshotError {
+    fn from(err: RecordError) -> Self {
+        SnapshotError::Record(err)
+    }
+}
+
+impl Snapshot {
+    pub fn capture(object: &ObjectPtr) -> Self {
+        Self {
+            root: ObjectRecord::capture_all(object),
+        }
+    }
+
+    /// 通过 `parent` 的 `Factory` 重建子树，返回新的根对象
+    pub fn restore(&self, parent: &ObjectPtr) -> Result<ObjectPtr, SnapshotError> {
+        Ok(self.root.restore(parent)?)
+    }
+
+    /// 载入到已有的对象，原有的子对象会被销毁
+    pub fn apply(&self, object: &ObjectPtr) -> Result<(), SnapshotError> {
+        object.borrow_mut().destroy_children();
+        Ok(self.root.apply(object)?)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(64);
+        buf.extend_from_slice(MAGIC);
+        buf.push(VERSION);
+        buf.extend_from_slice(&self.root.encode());
+        buf
+    }
+
+    pub fn from_bytes(buf: &[u8]) -> Result<Self, SnapshotError> {
+        match buf.strip_prefix(MAGIC.as_slice()) {
+            Some([VERSION, data @ ..]) => Ok(Self {
+                root: ObjectRecord::decode(data)?,
+            }),
+            _ => Err(SnapshotError::BadHeader),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(&value_to_json(&self.root.to_value())).unwrap()
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, SnapshotError> {
+        let json: serde_json::Value = serde_json::from_str(json).map_err(SnapshotError::Json)?;
+        Ok(Self {
+            root: ObjectRecord::from_value(&json_to_value(&json))?,
+        })
+    }
+}
+
+/// 整数按数值写出，读回时非负数为 `UInt`，属性的 setter 会按类型转换
+fn value_to_json(value: &Value) -> serde_json::Value {
+    match value {
+        Value::None => serde_json::Value::Null,
+        Value::Bool(v) => (*v).into(),
+        Value::Int(v) => (*v).into(),
+        Value::UInt(v) => (*v).into(),
+        Value::Float(v) => Number::from_f64(*v)
+            .map(serde_json::Value::Number)
+            .unwrap_or(serde_json::Value::Null),
+        Value::Str(v) => v.clone().into(),
+        Value::List(items) => items.iter().map(value_to_json).collect(),
+        Value::Map(fields) => serde_json::Value::Object(
+            fields
+                .iter()
+                .map(|(name, value)| (name.clone(), value_to_json(value)))
+                .collect(),
+        ),
+    }
+}
+
+fn json_to_value(json: &serde_json::Value) -> Value {
+    match json {
+        serde_json::Value::Null => Value::None,
+        serde_json::Value::Bool(v) => Value::Bool(*v),
+        serde_json::Value::Number(n) => {
+            if let Some(v) = n.as_u64() {
+                Value::UInt(v)
+            } else if let Some(v) = n.as_i64() {
+                Value::Int(v)
+            } else {
+                Value::Float(n.as_f64().unwrap_or_default())
+            }
+        }
+        serde_json::Value::String(v) => Value::Str(v.clone()),
+        serde_json::Value::Array(items) => Value::List(items.iter().map(json_to_value).collect()),
+        serde_json::Value::Object(fields) => Value::Map(
+            fields
+                .iter()
+                .map(|(name, value)| (name.clone(), json_to_value(value)))
+                .collect(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        container::Container,
+        game_scene::GameScene,
+        object::Object,
+        testing::{self, TestBox, TestPlayer, TestScene},
+    };
+
+    fn town() -> GameScene {
+        let scene = testing::scene();
+        Object::model_map_mut(&scene.scene_object, |s: &mut TestScene| s.set_name("town"));
+        let player = testing::player(&scene);
+        Object::model_map_mut(&player, |p: &mut TestPlayer| {
+            p.set_name("hero".to_string());
+            p.set_age(-30);
+        });
+        let bag = Object::create(&player, TestBox::ClassName(), 4, 0).unwrap();
+        testing::item(&bag, 3, "sword");
+        scene
+    }
+
+    #[test]
+    fn test_capture_all_attrs() {
+        let scene = town();
+        let snapshot = scene.snapshot();
+        // 普通属性没有 `Value` 读写，不在快照中
+        assert!(snapshot.root.attrs.is_empty());
+        // 快照包含没有 save 标记的属性
+        let player = &snapshot.root.children[0];
+        assert!(player.attrs.contains(&("age".to_string(), Value::Int(-30))));
+        assert_eq!(player.children[0].children[0].pos, 3);
+    }
+
+    #[test]
+    fn test_json() {
+        let snapshot = town().snapshot();
+        let json = snapshot.to_json();
+        assert!(json.contains("\"sword\""));
+        // 非负整数读回时是 UInt，载入时按属性类型转换
+        let other = testing::scene();
+        other.restore(&Snapshot::from_json(&json).unwrap()).unwrap();
+        assert_eq!(other.snapshot(), snapshot);
+
+        assert!(matches!(
+            Snapshot::from_json("{"),
+            Err(SnapshotError::Json(_))
+        ));
+        assert!(matches!(
+            Snapshot::from_json("{}"),
+            Err(SnapshotError::Value(ValueError::MissingField(_)))
+        ));
+    }
+
+    #[test]
+    fn test_bytes() {
+        let snapshot = town().snapshot();
+        let bytes = snapshot.to_bytes();
+        assert_eq!(Snapshot::from_bytes(&bytes).unwrap(), snapshot);
+        assert!(matches!(
+            Snapshot::from_bytes(b"RESN"),
+            Err(SnapshotError::BadHeader)
+        ));
+        let mut newer = bytes.clone();
+        newer[4] = VERSION + 1;
+        assert!(matches!(
+            Snapshot::from_bytes(&newer),
+            Err(SnapshotError::BadHeader)
+        ));
+        assert!(matches!(
+            Snapshot::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(SnapshotError::Value(ValueError::Eof))
+        ));
+    }
+
+    #[test]
+    fn test_restore_scene() {
+        let snapshot = town().snapshot();
+        let other = testing::scene();
+        testing::player(&other);
+        testing::player(&other);
+        other.restore(&snapshot).unwrap();
+        // 原有的子对象被替换
+        assert_eq!(other.scene_object.borrow().child_count(), 1);
+        assert_eq!(other.snapshot(), snapshot);
+
+        let mut bad = snapshot.clone();
+        bad.root.class_name = TestPlayer::ClassName().to_string();
+        assert!(matches!(
+            other.restore(&bad),
+            Err(SnapshotError::Class { .. })
+        ));
+        assert_eq!(other.scene_object.borrow().child_count(), 1);
+    }
+
+    #[test]
+    fn test_restore_subtree() {
+        let snapshot = Snapshot::capture(&town().scene_object.borrow().child_at(1).unwrap());
+        let other = testing::scene();
+        let player = snapshot.restore(&other.scene_object).unwrap();
+        assert_eq!(Snapshot::capture(&player), snapshot);
+    }
+}
@@ -0,0 +1,473 @@
+use std::rc::Rc;
+
+use crate::{
+    container::Container,
+    game_object::GameObject,
+    lifecycle,
+    object::Object,
+    value::{AttrValue, Value, ValueError},
+    GameModelPtr, ObjectPtr,
+};
+
+/// 可堆叠实体的描述，由 `#[def_entity(stack = N)]` 生成
+///
+/// `keys` 中的属性都相同的同类对象才能堆叠，数量保存在 `count` 属性中。
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StackModel {
+    pub max: u32,
+    pub keys: Vec<u32>,
+    pub count: u32,
+}
+
+struct Stack {
+    object: ObjectPtr,
+    model: StackModel,
+    game_model: GameModelPtr,
+    class_name: &'static str,
+}
+
+impl Stack {
+    fn of(this: &ObjectPtr) -> Option<Self> {
+        let object = this.borrow();
+        Some(Self {
+            object: this.clone(),
+            model: object.model.stack.clone()?,
+            game_model: object.game_model.clone(),
+            class_name: object.model.class_name,
+        })
+    }
+
+    fn count(&self) -> u32 {
+        self.game_model
+            .borrow()
+            .get_value(self.model.count)
+            .and_then(|value| u32::from_value(&value).ok())
+            .unwrap_or(0)
+    }
+
+    /// 数量超出属性类型的范围时返回错误
+    fn set_count(&self, count: u32) -> Result<(), ValueError> {
+        Object::set_value(&self.object, self.model.count, &Value::UInt(count as u64))
+    }
+
+    /// 不修改属性，检查数量能否写入 `count` 属性的类型
+    fn check_count(&self, count: u32) -> Result<(), ValueError> {
+        let model = self.game_model.borrow();
+        let Some(attr) = model.get_attr_by_index(self.model.count) else {
+            return Ok(());
+        };
+        let value = Value::UInt(count as u64);
+        macro_rules! check {
+            ($($ty:ty),*) => {
+                $(if attr.is::<$ty>() {
+                    return <$ty>::from_value(&value).map(drop);
+                })*
+            };
+        }
+        check!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+        Ok(())
+    }
+
+    fn room(&self) -> u32 {
+        self.model.max.saturating_sub(self.count())
+    }
+
+    fn matches(&self, other: &Stack) -> bool {
+        if self.class_name != other.class_name {
+            return false;
+        }
+        let (a, b) = (self.game_model.borrow(), other.game_model.borrow());
+        self.model
+            .keys
+            .iter()
+            .all(|&key| a.get_value(key) == b.get_value(key))
+    }
+}
+
+impl Object {
+    pub fn is_stackable(&self) -> bool {
+        self.model.stack.is_some()
+    }
+
+    /// 堆叠数量，不可堆叠的对象为 1
+    pub fn stack_count(this: &ObjectPtr) -> u32 {
+        Stack::of(this).map_or(1, |stack| stack.count())
+    }
+
+    /// 放入容器，先合并到容器中相同的堆，剩下的再占一个位置
+    ///
+    /// 全部合并时 `child` 被销毁。放不下时不做任何修改，返回 false，
+    /// 合并后剩下的数量超过最大数量也算放不下。不可堆叠的对象和 `add_child` 相同。
+    /// 数量超出属性类型的范围时返回错误，同样不做任何修改。
+    pub fn add_child_stacked(
+        container: &ObjectPtr,
+        child: ObjectPtr,
+        pos: usize,
+    ) -> Result<bool, ValueError> {
+        let Some(stack) = Stack::of(&child) else {
+            let added = container.borrow_mut().add_child(child, pos);
+            Self::flush_deferred(container);
+            return Ok(added);
+        };
+        if child.borrow().is_in_container() {
+            return Ok(false);
+        }
+        let targets: Vec<Stack> = container
+            .borrow()
+            .children
+            .iter()
+            .flatten()
+            .filter(|other| !Rc::ptr_eq(other, &child))
+            .filter_map(Stack::of)
+            .filter(|other| other.matches(&stack) && other.room() > 0)
+            .collect();
+
+        // 先算出并检查所有新的数量，再统一修改
+        let mut remaining = stack.count();
+        let mut counts = Vec::new();
+        for target in targets {
+            if remaining == 0 {
+                break;
+            }
+            let n = target.room().min(remaining);
+            let count = target.count() + n;
+            target.check_count(count)?;
+            counts.push((target, count));
+            remaining -= n;
+        }
+        if remaining > 0 {
+            if remaining > stack.model.max || container.borrow().target_index(pos).is_none() {
+                return Ok(false);
+            }
+            stack.check_count(remaining)?;
+        }
+
+        for (target, count) in counts {
+            target.set_count(count)?;
+        }
+        container.borrow_mut().set_dirty();
+        if remaining == 0 {
+            discard(&child);
+            return Ok(true);
+        }
+        stack.set_count(remaining)?;
+        let added = container.borrow_mut().add_child(child, pos);
+        Self::flush_deferred(container);
+        Ok(added)
+    }
+
+    /// 从堆中分出 `n` 个，放到同一个容器的空位，返回新的堆
+    ///
+    /// 新的堆复制原来的属性，放入容器之前设置好数量，`on_create` 时属性已经就绪。
+    /// `n` 必须小于堆的数量，容器没有空位时返回 None。
+    pub fn split_stack(child: &ObjectPtr, n: u32) -> Result<Option<ObjectPtr>, ValueError> {
+        let Some(stack) = Stack::of(child) else {
+            return Ok(None);
+        };
+        let count = stack.count();
+        if n == 0 || n >= count || !child.borrow().is_in_container() {
+            return Ok(None);
+        }
+        let Some(container) = child.borrow().get_parent() else {
+            return Ok(None);
+        };
+        if container.borrow().target_index(0).is_none() {
+            return Ok(None);
+        }
+
+        let cap = child.borrow().capacity();
+        let Some(split) = container.borrow().create_detached(stack.class_name, cap) else {
+            return Ok(None);
+        };
+        if let Err(err) = copy_attrs(&stack, &split, n) {
+            let factory = split.borrow().get_factory();
+            if let Some(factory) = factory {
+                factory.borrow_mut().destroy(&split);
+            }
+            return Err(err);
+        }
+        let placed = container.borrow_mut().place_created(&split, 0);
+        Self::flush_deferred(&container);
+        if !placed {
+            return Ok(None);
+        }
+        stack.set_count(count - n)?;
+        Ok(Some(split))
+    }
+
+    /// 把 `from` 合并到 `into`，返回合并的数量
+    ///
+    /// 超过最大数量的部分留在 `from` 中，全部合并时 `from` 被销毁。
+    /// 数量超出属性类型的范围时返回错误。
+    pub fn merge_stacks(from: &ObjectPtr, into: &ObjectPtr) -> Result<u32, ValueError> {
+        if Rc::ptr_eq(from, into) {
+            return Ok(0);
+        }
+        let (Some(source), Some(target)) = (Stack::of(from), Stack::of(into)) else {
+            return Ok(0);
+        };
+        if !source.matches(&target) {
+            return Ok(0);
+        }
+        let count = source.count();
+        let n = target.room().min(count);
+        if n == 0 {
+            return Ok(0);
+        }
+        target.set_count(target.count() + n)?;
+        if n == count {
+            let parent = from.borrow().get_parent();
+            match parent {
+                Some(parent) if from.borrow().is_in_container() => {
+                    parent.borrow_mut().destroy_child(from);
+                    Self::flush_deferred(&parent);
+                }
+                _ => discard(from),
+            }
+        } else {
+            source.set_count(count - n)?;
+        }
+        for object in [from, into] {
+            let parent = object.borrow().get_parent();
+            if let Some(parent) = parent {
+                parent.borrow_mut().set_dirty();
+            }
+        }
+        Ok(n)
+    }
+}
+
+/// 把堆的属性复制到还没有放入容器的 `split`，数量为 `n`
+fn copy_attrs(stack: &Stack, split: &ObjectPtr, n: u32) -> Result<(), ValueError> {
+    let split = split.borrow();
+    {
+        let source = stack.game_model.borrow();
+        let mut target = split.game_model.borrow_mut();
+        for index in 0..split.get_attr_count() {
+            if index == stack.model.count {
+                continue;
+            }
+            if let Some(value) = source.get_attr_by_index(index) {
+                target.set_attr_by_index(index, value);
+            }
+        }
+        target.set_value(stack.model.count, &Value::UInt(n as u64))?;
+    }
+    split.loaded();
+    Ok(())
+}
+
+/// 销毁不在容器中的对象
+fn discard(object: &ObjectPtr) {
+    object.borrow_mut().destroying = true;
+    lifecycle::fire(object, |hooks, object| hooks.on_destroy(object));
+    object.borrow_mut().destroy_children();
+    let factory = object.borrow().get_factory();
+    if let Some(factory) = factory {
+        factory.borrow_mut().delete(object);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use re_ops::def_entity;
+
+    use super::*;
+    use crate::{
+        game_scene::GameScene,
+        testing::{self, TestBox},
+    };
+
+    /// 数量类型放不下最大数量
+    #[def_entity(class = Item, stack = 300)]
+    struct TestPebble {
+        #[attr(save, stack_count)]
+        count: u8,
+    }
+
+    #[def_entity(class = Item, stack = 10)]
+    struct TestPotion {
+        #[attr(save, replicated, stack_key)]
+        kind: u32,
+        #[attr(save, replicated, stack_count)]
+        count: u16,
+    }
+
+    fn potion(parent: &ObjectPtr, kind: u32, count: u16) -> ObjectPtr {
+        let potion = Object::create(parent, TestPotion::ClassName(), 0, 0).unwrap();
+        Object::model_map_mut(&potion, |p: &mut TestPotion| {
+            p.set_kind(kind);
+            p.set_count(count);
+        });
+        potion
+    }
+
+    /// 不在容器中的堆
+    fn loose(scene: &GameScene, kind: u32, count: u16) -> ObjectPtr {
+        let potion = potion(&scene.scene_object, kind, count);
+        assert!(scene.scene_object.borrow_mut().remove_child(&potion));
+        potion
+    }
+
+    fn bag(scene: &GameScene, cap: usize) -> ObjectPtr {
+        Object::create(&testing::player(scene), TestBox::ClassName(), cap, 0).unwrap()
+    }
+
+    fn count(object: &ObjectPtr) -> u32 {
+        Object::stack_count(object)
+    }
+
+    #[test]
+    fn test_not_stackable() {
+        let scene = testing::scene();
+        let bag = bag(&scene, 2);
+        let item = testing::item(&bag, 1, "sword");
+        assert!(!item.borrow().is_stackable());
+        assert_eq!(count(&item), 1);
+        assert!(Object::split_stack(&item, 1).unwrap().is_none());
+        assert_eq!(Object::merge_stacks(&item, &item).unwrap(), 0);
+
+        let other = testing::item(&scene.scene_object, 0, "shield");
+        scene.scene_object.borrow_mut().remove_child(&other);
+        assert!(Object::add_child_stacked(&bag, other.clone(), 0).unwrap());
+        assert_eq!(other.borrow().get_container_pos(), 2);
+    }
+
+    #[test]
+    fn test_add_merges_first() {
+        let scene = testing::scene();
+        let bag = bag(&scene, 3);
+        let first = potion(&bag, 1, 8);
+        potion(&bag, 2, 5);
+
+        let rest = loose(&scene, 1, 5);
+        assert!(Object::add_child_stacked(&bag, rest.clone(), 0).unwrap());
+        assert_eq!((count(&first), count(&rest)), (10, 3));
+        assert_eq!(bag.borrow().child_count(), 3);
+
+        // 全部合并，不占位置
+        let merged = loose(&scene, 1, 4);
+        assert!(Object::add_child_stacked(&bag, merged.clone(), 0).unwrap());
+        assert!(merged.borrow().is_deleted());
+        assert_eq!(count(&rest), 7);
+        assert_eq!(bag.borrow().child_count(), 3);
+    }
+
+    #[test]
+    fn test_add_without_room() {
+        let scene = testing::scene();
+        let bag = bag(&scene, 1);
+        let first = potion(&bag, 1, 8);
+
+        // 放不下时不做修改
+        let extra = loose(&scene, 1, 3);
+        assert!(!Object::add_child_stacked(&bag, extra.clone(), 0).unwrap());
+        assert!(!extra.borrow().is_deleted());
+        assert_eq!((count(&first), count(&extra)), (8, 3));
+        assert!(!Object::add_child_stacked(&bag, loose(&scene, 2, 1), 0).unwrap());
+        // 已经在容器中的对象
+        assert!(!Object::add_child_stacked(&bag, first.clone(), 0).unwrap());
+    }
+
+    #[test]
+    fn test_split() {
+        let scene = testing::scene();
+        let bag = bag(&scene, 2);
+        let first = potion(&bag, 1, 7);
+        bag.borrow_mut().clear_dirty();
+
+        assert!(Object::split_stack(&first, 0).unwrap().is_none());
+        assert!(Object::split_stack(&first, 7).unwrap().is_none());
+        let split = Object::split_stack(&first, 2).unwrap().unwrap();
+        assert_eq!((count(&first), count(&split)), (5, 2));
+        Object::model_map(&split, |p: &TestPotion| assert_eq!(p.kind, 1));
+        assert_eq!(split.borrow().get_container_pos(), 2);
+        assert!(bag.borrow().dirty());
+
+        // 容器已满
+        assert!(Object::split_stack(&first, 1).unwrap().is_none());
+        assert_eq!(count(&first), 5);
+        // 不在容器中
+        assert!(Object::split_stack(&loose(&scene, 1, 5), 1)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_merge() {
+        let scene = testing::scene();
+        let bag = bag(&scene, 3);
+        let first = potion(&bag, 1, 7);
+        let rest = potion(&bag, 1, 6);
+        let other = potion(&bag, 2, 1);
+
+        assert_eq!(Object::merge_stacks(&first, &first).unwrap(), 0);
+        assert_eq!(Object::merge_stacks(&other, &first).unwrap(), 0);
+        // 超过的部分留在原来的堆
+        assert_eq!(Object::merge_stacks(&first, &rest).unwrap(), 4);
+        assert_eq!((count(&first), count(&rest)), (3, 10));
+        assert_eq!(Object::merge_stacks(&first, &rest).unwrap(), 0);
+
+        assert_eq!(Object::merge_stacks(&rest, &first).unwrap(), 7);
+        assert_eq!((count(&first), count(&rest)), (10, 3));
+
+        // 全部合并时销毁原来的堆
+        Object::model_map_mut(&first, |p: &mut TestPotion| p.set_count(5));
+        assert_eq!(Object::merge_stacks(&rest, &first).unwrap(), 3);
+        assert!(rest.borrow().is_deleted());
+        assert_eq!(count(&first), 8);
+        assert_eq!(bag.borrow().child_count(), 2);
+
+        // 不在容器中的堆合并后直接销毁
+        let extra = loose(&scene, 2, 3);
+        assert_eq!(Object::merge_stacks(&extra, &other).unwrap(), 3);
+        assert!(extra.borrow().is_deleted());
+        assert_eq!(count(&other), 4);
+    }
+
+    #[test]
+    fn test_oversized() {
+        let scene = testing::scene();
+        let bag = bag(&scene, 2);
+        let first = potion(&bag, 1, 7);
+
+        // 合并后剩下的仍然超过最大数量，不放入
+        let oversized = loose(&scene, 1, 25);
+        assert!(!Object::add_child_stacked(&bag, oversized.clone(), 0).unwrap());
+        assert_eq!((count(&first), count(&oversized)), (7, 25));
+        assert!(!oversized.borrow().is_in_container());
+
+        let fits = loose(&scene, 1, 12);
+        assert!(Object::add_child_stacked(&bag, fits.clone(), 0).unwrap());
+        assert_eq!((count(&first), count(&fits)), (10, 9));
+    }
+
+    #[test]
+    fn test_count_out_of_range() {
+        let scene = testing::scene();
+        let bag = bag(&scene, 3);
+        let pebbles = Object::create(&bag, TestPebble::ClassName(), 0, 0).unwrap();
+        Object::model_map_mut(&pebbles, |p: &mut TestPebble| p.set_count(200));
+        let others = Object::create(&bag, TestPebble::ClassName(), 0, 0).unwrap();
+        Object::model_map_mut(&others, |p: &mut TestPebble| p.set_count(250));
+        let more = Object::create(&scene.scene_object, TestPebble::ClassName(), 0, 0).unwrap();
+        Object::model_map_mut(&more, |p: &mut TestPebble| p.set_count(100));
+
+        assert_eq!(
+            Object::merge_stacks(&more, &pebbles),
+            Err(ValueError::OutOfRange)
+        );
+        assert_eq!((count(&pebbles), count(&more)), (200, 100));
+        assert!(scene.scene_object.borrow_mut().remove_child(&more));
+        // 两个堆都可以合并，出错时都不修改
+        assert_eq!(
+            Object::add_child_stacked(&bag, more.clone(), 0),
+            Err(ValueError::OutOfRange)
+        );
+        assert_eq!((count(&pebbles), count(&others)), (200, 250));
+        assert_eq!(count(&more), 100);
+        assert!(!more.borrow().is_deleted());
+        assert!(!more.borrow().is_in_container());
+        assert_eq!(bag.borrow().child_count(), 2);
+    }
+}
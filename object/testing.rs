@@ -0,0 +1,48 @@
+//! 单元测试共用的实体和场景
+
+use std::rc::Rc;
+
+use re_ops::def_entity;
+
+use crate::{game_scene::GameScene, object::Object, registry::Registry, ObjectPtr};
+
+#[def_entity(class = Scene)]
+pub struct TestScene {
+    #[attr()]
+    pub name: &'static str,
+}
+
+#[def_entity(class = Role)]
+pub struct TestPlayer {
+    pub hp: i32,
+    #[attr(save, replicated)]
+    pub name: String,
+    #[attr(replicated)]
+    pub age: i32,
+}
+
+#[def_entity(class = Container)]
+pub struct TestBox {
+    #[attr(save, replicated)]
+    pub name: String,
+}
+
+#[def_entity(class = Item)]
+pub struct TestItem {
+    #[attr(save, replicated)]
+    pub name: String,
+}
+
+pub fn scene() -> GameScene {
+    GameScene::new(TestScene::ClassName(), Rc::new(Registry::init())).unwrap()
+}
+
+pub fn player(scene: &GameScene) -> ObjectPtr {
+    scene.create_in_scene(TestPlayer::ClassName(), 0).unwrap()
+}
+
+pub fn item(parent: &ObjectPtr, pos: usize, name: &str) -> ObjectPtr {
+    let item = Object::create(parent, TestItem::ClassName(), 0, pos).unwrap();
+    Object::model_map_mut(&item, |i: &mut TestItem| i.set_name(name.to_string()));
+    item
+}
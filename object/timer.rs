@@ -0,0 +1,225 @@
+use std::{
+    cell::RefCell,
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+    rc::Rc,
+    time::Duration,
+};
+
+use crate::{handle::ObjectHandle, FactoryPtr, ObjectPtr};
+
+pub type TimersPtr = Rc<RefCell<Timers>>;
+
+/// 定时器句柄，用来取消定时器
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TimerId(u64);
+
+enum Callback {
+    Free(Box<dyn FnMut()>),
+    Object(ObjectHandle, Box<dyn FnMut(&ObjectPtr)>),
+}
+
+struct Entry {
+    deadline: Duration,
+    interval: Option<Duration>,
+    owner: Option<u64>,
+    // 回调执行期间为 None
+    callback: Option<Callback>,
+}
+
+/// 由帧循环驱动的定时器
+///
+/// 绑定到对象的定时器在对象被 `Factory::delete` 或 `Factory::destroy`
+/// 移除时自动取消，不会在已删除的对象上触发。
+#[derive(Default)]
+pub struct Timers {
+    now: Duration,
+    next_id: u64,
+    heap: BinaryHeap<Reverse<(Duration, u64)>>,
+    entries: HashMap<u64, Entry>,
+    owners: HashMap<u64, Vec<u64>>,
+}
+
+impl std::fmt::Debug for Timers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Timers")
+            .field("now", &self.now)
+            .field("count", &self.entries.len())
+            .finish()
+    }
+}
+
+impl Timers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 最近一次 `update` 的时间
+    pub fn now(&self) -> Duration {
+        self.now
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn is_active(&self, id: TimerId) -> bool {
+        self.entries.contains_key(&id.0)
+    }
+
+    /// `delay` 之后执行一次
+    pub fn after(&mut self, delay: Duration, f: impl FnMut() + 'static) -> TimerId {
+        self.add(delay, None, None, Callback::Free(Box::new(f)))
+    }
+
+    /// 每隔 `interval` 执行一次
+    pub fn every(&mut self, interval: Duration, f: impl FnMut() + 'static) -> TimerId {
+        self.add(interval, Some(interval), None, Callback::Free(Box::new(f)))
+    }
+
+    /// 绑定到对象，`delay` 之后执行一次
+    pub fn after_object(
+        &mut self,
+        object: ObjectHandle,
+        delay: Duration,
+        f: impl FnMut(&ObjectPtr) + 'static,
+    ) -> TimerId {
+        let callback = Callback::Object(object, Box::new(f));
+        self.add(delay, None, Some(object.uid()), callback)
+    }
+
+    /// 绑定到对象，每隔 `interval` 执行一次
+    pub fn every_object(
+        &mut self,
+        object: ObjectHandle,
+        interval: Duration,
+        f: impl FnMut(&ObjectPtr) + 'static,
+    ) -> TimerId {
+        let callback = Callback::Object(object, Box::new(f));
+        self.add(interval, Some(interval), Some(object.uid()), callback)
+    }
+
+    fn add(
+        &mut self,
+        delay: Duration,
+        interval: Option<Duration>,
+        owner: Option<u64>,
+        callback: Callback,
+    ) -> TimerId {
+        self.next_id += 1;
+        let id = self.next_id;
+        let deadline = self.now + delay;
+        self.entries.insert(
+            id,
+            Entry {
+                deadline,
+                interval,
+                owner,
+                callback: Some(callback),
+            },
+        );
+        self.heap.push(Reverse((deadline, id)));
+        if let Some(uid) = owner {
+            self.owners.entry(uid).or_default().push(id);
+        }
+        TimerId(id)
+    }
+
+    pub fn cancel(&mut self, id: TimerId) -> bool {
+        match self.entries.remove(&id.0) {
+            Some(entry) => {
+                if let Some(uid) = entry.owner {
+                    if let Some(ids) = self.owners.get_mut(&uid) {
+                        ids.retain(|&i| i != id.0);
+                        if ids.is_empty() {
+                            self.owners.remove(&uid);
+                        }
+                    }
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 取消绑定到对象的所有定时器
+    pub fn cancel_object(&mut self, uid: u64) {
+        if let Some(ids) = self.owners.remove(&uid) {
+            for id in ids {
+                self.entries.remove(&id);
+            }
+        }
+    }
+
+    /// 推进时间并执行到期的定时器
+    ///
+    /// 执行回调时不持有 `Timers` 和 `factory` 的借用，回调中可以添加或取消定时器。
+    /// 绑定到对象的定时器通过 `factory` 解析句柄。
+    pub fn update(this: &TimersPtr, factory: &FactoryPtr, now: Duration) {
+        let mut due = Vec::new();
+        {
+            let mut timers = this.borrow_mut();
+            timers.now = now;
+            while let Some(&Reverse((deadline, id))) = timers.heap.peek() {
+                if deadline > now {
+                    break;
+                }
+                timers.heap.pop();
+                if let Some(entry) = timers.entries.get_mut(&id) {
+                    if entry.deadline == deadline {
+                        if let Some(callback) = entry.callback.take() {
+                            due.push((id, callback));
+                        }
+                    }
+                }
+            }
+        }
+
+        for (id, mut callback) in due {
+            let alive = match &mut callback {
+                Callback::Free(f) => {
+                    f();
+                    true
+                }
+                Callback::Object(handle, f) => {
+                    let object = handle.resolve(&factory.borrow());
+                    match object {
+                        Some(object) => {
+                            f(&object);
+                            true
+                        }
+                        None => false,
+                    }
+                }
+            };
+
+            let mut timers = this.borrow_mut();
+            let repeat = match timers.entries.get_mut(&id) {
+                // 回调中被取消
+                None => continue,
+                Some(entry) => match entry.interval {
+                    Some(interval) if alive => {
+                        let mut deadline = entry.deadline + interval;
+                        if deadline <= now {
+                            deadline = now + interval;
+                        }
+                        entry.deadline = deadline;
+                        entry.callback = Some(callback);
+                        Some(deadline)
+                    }
+                    _ => None,
+                },
+            };
+            match repeat {
+                Some(deadline) => timers.heap.push(Reverse((deadline, id))),
+                None => {
+                    timers.cancel(TimerId(id));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,571 @@
+use std::{collections::HashMap, fmt};
+
+/// 属性值的通用表示，用于存盘、同步和调试工具
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Value {
+    #[default]
+    None,
+    Bool(bool),
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+    Str(String),
+    List(Vec<Value>),
+    /// 结构体按字段声明顺序保存
+    Map(Vec<(String, Value)>),
+}
+
+const TAG_NONE: u8 = 0;
+const TAG_BOOL: u8 = 1;
+const TAG_INT: u8 = 2;
+const TAG_UINT: u8 = 3;
+const TAG_FLOAT: u8 = 4;
+const TAG_STR: u8 = 5;
+const TAG_LIST: u8 = 6;
+const TAG_MAP: u8 = 7;
+
+/// 列表和字典嵌套的最大层数，防止恶意数据耗尽栈
+pub const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValueError {
+    /// 数据不完整
+    Eof,
+    BadTag(u8),
+    Utf8,
+    /// 值的类型和属性类型不一致
+    Mismatch {
+        expected: &'static str,
+        found: &'static str,
+    },
+    /// 整数超出属性类型的范围
+    OutOfRange,
+    MissingField(&'static str),
+    UnknownAttr(u32),
+    /// 唯一属性的值已被其它对象使用
+    Duplicate(&'static str),
+    /// 嵌套超过 `MAX_DEPTH` 层
+    TooDeep,
+}
+
+impl fmt::Display for ValueError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValueError::Eof => write!(f, "unexpected end of value"),
+            ValueError::BadTag(tag) => write!(f, "bad value tag {}", tag),
+            ValueError::Utf8 => write!(f, "invalid utf8 string"),
+            ValueError::Mismatch { expected, found } => {
+                write!(f, "expect {}, found {}", expected, found)
+            }
+            ValueError::OutOfRange => write!(f, "integer out of range"),
+            ValueError::MissingField(name) => write!(f, "missing field {}", name),
+            ValueError::UnknownAttr(index) => write!(f, "unknown attribute {}", index),
+            ValueError::Duplicate(attr) => write!(f, "duplicate value of unique {}", attr),
+            ValueError::TooDeep => write!(f, "value nested deeper than {}", MAX_DEPTH),
+        }
+    }
+}
+
+impl std::error::Error for ValueError {}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::None => "none",
+            Value::Bool(_) => "bool",
+            Value::Int(_) => "int",
+            Value::UInt(_) => "uint",
+            Value::Float(_) => "float",
+            Value::Str(_) => "str",
+            Value::List(_) => "list",
+            Value::Map(_) => "map",
+        }
+    }
+
+    pub fn mismatch(&self, expected: &'static str) -> ValueError {
+        ValueError::Mismatch {
+            expected,
+            found: self.type_name(),
+        }
+    }
+
+    /// 按名字查找结构体字段
+    pub fn field(&self, name: &'static str) -> Result<&Value, ValueError> {
+        match self {
+            Value::Map(fields) => fields
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value)
+                .ok_or(ValueError::MissingField(name)),
+            _ => Err(self.mismatch("map")),
+        }
+    }
+
+    /// 编码格式：`[tag:u8][payload]`，整数和长度使用 varint，有符号整数先做 zigzag
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Value::None => buf.push(TAG_NONE),
+            Value::Bool(v) => {
+                buf.push(TAG_BOOL);
+                buf.push(*v as u8);
+            }
+            Value::Int(v) => {
+                buf.push(TAG_INT);
+                put_varint(buf, ((v << 1) ^ (v >> 63)) as u64);
+            }
+            Value::UInt(v) => {
+                buf.push(TAG_UINT);
+                put_varint(buf, *v);
+            }
+            Value::Float(v) => {
+                buf.push(TAG_FLOAT);
+                buf.extend_from_slice(&v.to_le_bytes());
+            }
+            Value::Str(v) => {
+                buf.push(TAG_STR);
+                put_str(buf, v);
+            }
+            Value::List(items) => {
+                buf.push(TAG_LIST);
+                put_varint(buf, items.len() as u64);
+                for item in items {
+                    item.encode(buf);
+                }
+            }
+            Value::Map(fields) => {
+                buf.push(TAG_MAP);
+                put_varint(buf, fields.len() as u64);
+                for (key, value) in fields {
+                    put_str(buf, key);
+                    value.encode(buf);
+                }
+            }
+        }
+    }
+
+    /// 从 `buf` 的开头解码一个值，并跳过已读取的部分
+    pub fn decode(buf: &mut &[u8]) -> Result<Value, ValueError> {
+        Self::decode_at(buf, 0)
+    }
+
+    fn decode_at(buf: &mut &[u8], depth: usize) -> Result<Value, ValueError> {
+        let value = match get_u8(buf)? {
+            TAG_NONE => Value::None,
+            TAG_BOOL => Value::Bool(get_u8(buf)? != 0),
+            TAG_INT => {
+                let v = get_varint(buf)?;
+                Value::Int((v >> 1) as i64 ^ -((v & 1) as i64))
+            }
+            TAG_UINT => Value::UInt(get_varint(buf)?),
+            TAG_FLOAT => {
+                let bytes = take(buf, 8)?;
+                Value::Float(f64::from_le_bytes(bytes.try_into().unwrap()))
+            }
+            TAG_STR => Value::Str(get_str(buf)?),
+            TAG_LIST | TAG_MAP if depth >= MAX_DEPTH => return Err(ValueError::TooDeep),
+            TAG_LIST => {
+                let len = get_len(buf)?;
+                let mut items = Vec::with_capacity(len);
+                for _ in 0..len {
+                    items.push(Value::decode_at(buf, depth + 1)?);
+                }
+                Value::List(items)
+            }
+            TAG_MAP => {
+                let len = get_len(buf)?;
+                let mut fields = Vec::with_capacity(len);
+                for _ in 0..len {
+                    let key = get_str(buf)?;
+                    fields.push((key, Value::decode_at(buf, depth + 1)?));
+                }
+                Value::Map(fields)
+            }
+            tag => return Err(ValueError::BadTag(tag)),
+        };
+        Ok(value)
+    }
+}
+
+fn put_varint(buf: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        buf.push(v as u8 | 0x80);
+        v >>= 7;
+    }
+    buf.push(v as u8);
+}
+
+fn put_str(buf: &mut Vec<u8>, s: &str) {
+    put_varint(buf, s.len() as u64);
+    buf.extend_from_slice(s.as_bytes());
+}
+
+fn take<'a>(buf: &mut &'a [u8], len: usize) -> Result<&'a [u8], ValueError> {
+    if buf.len() < len {
+        return Err(ValueError::Eof);
+    }
+    let (head, tail) = buf.split_at(len);
+    *buf = tail;
+    Ok(head)
+}
+
+fn get_u8(buf: &mut &[u8]) -> Result<u8, ValueError> {
+    Ok(take(buf, 1)?[0])
+}
+
+fn get_varint(buf: &mut &[u8]) -> Result<u64, ValueError> {
+    let mut v = 0u64;
+    for shift in (0..64).step_by(7) {
+        let b = get_u8(buf)?;
+        v |= ((b & 0x7F) as u64) << shift;
+        if b & 0x80 == 0 {
+            return Ok(v);
+        }
+    }
+    Err(ValueError::OutOfRange)
+}
+
+// 长度不会超过剩余数据，避免恶意数据导致预分配过大
+fn get_len(buf: &mut &[u8]) -> Result<usize, ValueError> {
+    let len = get_varint(buf)?;
+    if len > buf.len() as u64 {
+        return Err(ValueError::Eof);
+    }
+    Ok(len as usize)
+}
+
+fn get_str(buf: &mut &[u8]) -> Result<String, ValueError> {
+    let len = get_len(buf)?;
+    let bytes = take(buf, len)?;
+    String::from_utf8(bytes.to_vec()).map_err(|_| ValueError::Utf8)
+}
+
+/// 可以按 `Value` 读写的属性类型
+///
+/// 带 save、replicated、索引或堆叠标记的属性需要实现，普通属性不需要。结构体可以用 `#[derive(AttrValue)]` 实现，按字段名保存为 `Value::Map`。
+pub trait AttrValue: Sized {
+    fn to_value(&self) -> Value;
+    fn from_value(value: &Value) -> Result<Self, ValueError>;
+}
+
+macro_rules! impl_int {
+    ($variant:ident, $name:expr, $($ty:ty),*) => {
+        $(impl AttrValue for $ty {
+            fn to_value(&self) -> Value {
+                Value::$variant(*self as _)
+            }
+
+            fn from_value(value: &Value) -> Result<Self, ValueError> {
+                match *value {
+                    Value::Int(v) => v.try_into().map_err(|_| ValueError::OutOfRange),
+                    Value::UInt(v) => v.try_into().map_err(|_| ValueError::OutOfRange),
+                    _ => Err(value.mismatch($name)),
+                }
+            }
+        })*
+    };
+}
+
+impl_int!(Int, "int", i8, i16, i32, i64, isize);
+impl_int!(UInt, "uint", u8, u16, u32, u64, usize);
+
+macro_rules! impl_float {
+    ($($ty:ty),*) => {
+        $(impl AttrValue for $ty {
+            fn to_value(&self) -> Value {
+                Value::Float(*self as f64)
+            }
+
+            fn from_value(value: &Value) -> Result<Self, ValueError> {
+                match *value {
+                    Value::Float(v) => Ok(v as $ty),
+                    Value::Int(v) => Ok(v as $ty),
+                    Value::UInt(v) => Ok(v as $ty),
+                    _ => Err(value.mismatch("float")),
+                }
+            }
+        })*
+    };
+}
+
+impl_float!(f32, f64);
+
+impl AttrValue for () {
+    fn to_value(&self) -> Value {
+        Value::None
+    }
+
+    fn from_value(_value: &Value) -> Result<Self, ValueError> {
+        Ok(())
+    }
+}
+
+impl AttrValue for bool {
+    fn to_value(&self) -> Value {
+        Value::Bool(*self)
+    }
+
+    fn from_value(value: &Value) -> Result<Self, ValueError> {
+        match *value {
+            Value::Bool(v) => Ok(v),
+            _ => Err(value.mismatch("bool")),
+        }
+    }
+}
+
+impl AttrValue for String {
+    fn to_value(&self) -> Value {
+        Value::Str(self.clone())
+    }
+
+    fn from_value(value: &Value) -> Result<Self, ValueError> {
+        match value {
+            Value::Str(v) => Ok(v.clone()),
+            _ => Err(value.mismatch("str")),
+        }
+    }
+}
+
+impl<T: AttrValue> AttrValue for Option<T> {
+    fn to_value(&self) -> Value {
+        match self {
+            Some(v) => v.to_value(),
+            None => Value::None,
+        }
+    }
+
+    fn from_value(value: &Value) -> Result<Self, ValueError> {
+        match value {
+            Value::None => Ok(None),
+            _ => T::from_value(value).map(Some),
+        }
+    }
+}
+
+impl<T: AttrValue> AttrValue for Vec<T> {
+    fn to_value(&self) -> Value {
+        Value::List(self.iter().map(T::to_value).collect())
+    }
+
+    fn from_value(value: &Value) -> Result<Self, ValueError> {
+        match value {
+            Value::List(items) => items.iter().map(T::from_value).collect(),
+            _ => Err(value.mismatch("list")),
+        }
+    }
+}
+
+impl<T: AttrValue> AttrValue for HashMap<String, T> {
+    fn to_value(&self) -> Value {
+        let mut fields: Vec<_> = self
+            .iter()
+            .map(|(key, value)| (key.clone(), value.to_value()))
+            .collect();
+        // 保证相同的内容编码结果相同
+        fields.sort_by(|a, b| a.0.cmp(&b.0));
+        Value::Map(fields)
+    }
+
+    fn from_value(value: &Value) -> Result<Self, ValueError> {
+        match value {
+            Value::Map(fields) => fields
+                .iter()
+                .map(|(key, value)| Ok((key.clone(), T::from_value(value)?)))
+                .collect(),
+            _ => Err(value.mismatch("map")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use re_ops::{def_entity, AttrValue};
+
+    use super::*;
+    use crate::{game_object::GameObject, object::Object, testing};
+
+    #[derive(Debug, Default, Clone, PartialEq, AttrValue)]
+    struct TestStats {
+        level: u16,
+        tags: Vec<String>,
+        weight: Option<f32>,
+    }
+
+    #[def_entity]
+    struct TestMonster {
+        #[attr(save)]
+        hp: i32,
+        #[attr(save, replicated)]
+        stats: TestStats,
+    }
+
+    fn encoded(value: &Value) -> Vec<u8> {
+        let mut buf = Vec::new();
+        value.encode(&mut buf);
+        buf
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let value = Value::List(vec![
+            Value::Int(-3),
+            Value::Int(i64::MIN),
+            Value::UInt(u64::MAX),
+            Value::Float(1.5),
+            Value::Bool(true),
+            Value::Str("abc".into()),
+            Value::Map(vec![("k".into(), Value::None)]),
+        ]);
+        let buf = encoded(&value);
+        let mut src = &buf[..];
+        assert_eq!(Value::decode(&mut src), Ok(value));
+        assert!(src.is_empty());
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let buf = encoded(&Value::Str("abc".into()));
+        assert_eq!(
+            Value::decode(&mut &buf[..buf.len() - 1]),
+            Err(ValueError::Eof)
+        );
+        assert_eq!(Value::decode(&mut &[][..]), Err(ValueError::Eof));
+        assert_eq!(Value::decode(&mut &[9][..]), Err(ValueError::BadTag(9)));
+        assert_eq!(
+            Value::decode(&mut &[TAG_STR, 1, 0xFF][..]),
+            Err(ValueError::Utf8)
+        );
+        // 长度超过剩余数据时不预分配
+        assert_eq!(
+            Value::decode(&mut &[TAG_LIST, 0xFF, 0xFF, 0xFF, 0x7F][..]),
+            Err(ValueError::Eof)
+        );
+        assert_eq!(
+            Value::decode(
+                &mut &[TAG_UINT, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF][..]
+            ),
+            Err(ValueError::OutOfRange)
+        );
+    }
+
+    #[test]
+    fn test_decode_depth() {
+        let nested = |depth: usize| {
+            let mut value = Value::None;
+            for _ in 0..depth {
+                value = Value::List(vec![value]);
+            }
+            encoded(&value)
+        };
+        let buf = nested(MAX_DEPTH);
+        assert!(Value::decode(&mut &buf[..]).is_ok());
+        let buf = nested(MAX_DEPTH + 1);
+        assert_eq!(Value::decode(&mut &buf[..]), Err(ValueError::TooDeep));
+        // 不需要完整的数据就能拒绝
+        let buf = [TAG_LIST, 1].repeat(100_000);
+        assert_eq!(Value::decode(&mut &buf[..]), Err(ValueError::TooDeep));
+    }
+
+    #[test]
+    fn test_conversions() {
+        assert_eq!(
+            u8::from_value(&Value::Int(256)),
+            Err(ValueError::OutOfRange)
+        );
+        assert_eq!(u8::from_value(&Value::Int(-1)), Err(ValueError::OutOfRange));
+        assert_eq!(i64::from_value(&Value::UInt(7)), Ok(7));
+        assert_eq!(f32::from_value(&Value::Int(2)), Ok(2.0));
+        assert_eq!(
+            bool::from_value(&Value::Int(1)),
+            Err(ValueError::Mismatch {
+                expected: "bool",
+                found: "int"
+            })
+        );
+        assert_eq!(Option::<u8>::from_value(&Value::None), Ok(None));
+        assert_eq!(Some(3u8).to_value(), Value::UInt(3));
+
+        // 相同内容的 HashMap 编码结果相同
+        let map: HashMap<String, i32> = [("b".into(), 2), ("a".into(), 1)].into();
+        assert_eq!(
+            map.to_value(),
+            Value::Map(vec![
+                ("a".into(), Value::Int(1)),
+                ("b".into(), Value::Int(2))
+            ])
+        );
+        assert_eq!(HashMap::from_value(&map.to_value()), Ok(map));
+    }
+
+    #[test]
+    fn test_derive() {
+        let stats = TestStats {
+            level: 7,
+            tags: vec!["elite".into()],
+            weight: Some(2.5),
+        };
+        assert_eq!(TestStats::from_value(&stats.to_value()), Ok(stats.clone()));
+
+        let mut value = stats.to_value();
+        if let Value::Map(fields) = &mut value {
+            fields.retain(|(name, _)| name != "tags");
+        }
+        assert_eq!(
+            TestStats::from_value(&value),
+            Err(ValueError::MissingField("tags"))
+        );
+        assert_eq!(
+            TestStats::from_value(&Value::Int(1)),
+            Err(ValueError::Mismatch {
+                expected: "map",
+                found: "int"
+            })
+        );
+    }
+
+    #[test]
+    fn test_entity_values() {
+        let scene = testing::scene();
+        let monster = scene.create_in_scene(TestMonster::ClassName(), 0).unwrap();
+        let (hp, stats) = {
+            let object = monster.borrow();
+            (
+                object.get_attr_index("hp").unwrap(),
+                object.get_attr_index("stats").unwrap(),
+            )
+        };
+        let expect = TestStats {
+            level: 7,
+            tags: vec!["elite".into()],
+            weight: Some(2.5),
+        };
+        let mut buf = encoded(&expect.to_value());
+        Value::Int(42).encode(&mut buf);
+
+        let object = monster.borrow();
+        let mut model = object.game_model.borrow_mut();
+        let mut src = &buf[..];
+        model.decode_attr(stats, &mut src).unwrap();
+        model.decode_attr(hp, &mut src).unwrap();
+        assert!(src.is_empty());
+        assert_eq!(
+            model.decode_attr(hp, &mut &buf[..]),
+            Err(ValueError::Mismatch {
+                expected: "int",
+                found: "map"
+            })
+        );
+        assert_eq!(model.get_value(hp), Some(Value::Int(42)));
+        assert!(model.get_value(99).is_none());
+        assert_eq!(
+            model.set_value(99, &Value::None),
+            Err(ValueError::UnknownAttr(99))
+        );
+        let mut out = Vec::new();
+        assert!(model.encode_attr(stats, &mut out));
+        assert_eq!(out, buf[..out.len()]);
+        assert!(!model.encode_attr(99, &mut out));
+        drop(model);
+        drop(object);
+        Object::model_map(&monster, |m: &TestMonster| assert_eq!(m.stats, expect));
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::VecDeque;
+
+use crate::{game_object::GameObject, object::Object, ObjectPtr};
+
+/// 遍历顺序
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Order {
+    /// 深度优先，先序
+    DepthFirst,
+    /// 广度优先，按层
+    BreadthFirst,
+}
+
+type Prune = Box<dyn Fn(&Object) -> bool>;
+
+/// 直接子对象的迭代器，由 `Object::children` 创建
+///
+/// 每次只短暂借用父对象，遍历期间可以修改或销毁子对象，
+/// 已经被移除的位置会被跳过。
+pub struct Children {
+    parent: ObjectPtr,
+    index: usize,
+}
+
+impl Iterator for Children {
+    type Item = ObjectPtr;
+
+    fn next(&mut self) -> Option<ObjectPtr> {
+        let parent = self.parent.borrow();
+        while self.index < parent.children.len() {
+            let slot = &parent.children[self.index];
+            self.index += 1;
+            match slot {
+                Some(child) if !child.borrow().is_deleted() => return Some(child.clone()),
+                _ => {}
+            }
+        }
+        None
+    }
+}
+
+/// 后代对象的迭代器，由 `Object::descendants` 创建，不包含起点
+///
+/// 子对象在访问到父对象之后才读取，遍历期间销毁的对象不会再被访问。
+pub struct Walk {
+    order: Order,
+    pending: VecDeque<Children>,
+    prune: Option<Prune>,
+}
+
+impl Walk {
+    /// `f` 返回 true 时不再访问这个对象的子对象，对象本身仍会返回
+    pub fn prune(mut self, f: impl Fn(&Object) -> bool + 'static) -> Self {
+        self.prune = Some(Box::new(f));
+        self
+    }
+}
+
+impl Iterator for Walk {
+    type Item = ObjectPtr;
+
+    fn next(&mut self) -> Option<ObjectPtr> {
+        loop {
+            let children = match self.order {
+                Order::DepthFirst => self.pending.back_mut()?,
+                Order::BreadthFirst => self.pending.front_mut()?,
+            };
+            let Some(child) = children.next() else {
+                match self.order {
+                    Order::DepthFirst => self.pending.pop_back(),
+                    Order::BreadthFirst => self.pending.pop_front(),
+                };
+                continue;
+            };
+            let pruned = self.prune.as_ref().is_some_and(|f| f(&child.borrow()));
+            if !pruned {
+                self.pending.push_back(Object::children(&child));
+            }
+            return Some(child);
+        }
+    }
+}
+
+impl Object {
+    /// 按位置顺序遍历直接子对象
+    pub fn children(this: &ObjectPtr) -> Children {
+        Children {
+            parent: this.clone(),
+            index: 0,
+        }
+    }
+
+    /// 遍历所有后代对象
+    pub fn descendants(this: &ObjectPtr, order: Order) -> Walk {
+        Walk {
+            order,
+            pending: VecDeque::from([Object::children(this)]),
+            prune: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::{
+        container::Container,
+        game_object::GameObject,
+        game_scene::GameScene,
+        object::ClassType,
+        testing::{self, TestBox},
+        value::AttrValue,
+    };
+
+    /// player 下有 3 格的 bag（第 1、2 格为 b、a）和 1 格的 equip（c）
+    struct Tree {
+        _scene: GameScene,
+        player: ObjectPtr,
+        bag: ObjectPtr,
+        equip: ObjectPtr,
+    }
+
+    fn tree() -> Tree {
+        let scene = testing::scene();
+        let player = testing::player(&scene);
+        let bag = Object::create(&player, TestBox::ClassName(), 3, 0).unwrap();
+        Object::model_map_mut(&bag, |b: &mut TestBox| b.set_name("bag".to_string()));
+        testing::item(&bag, 2, "a");
+        testing::item(&bag, 1, "b");
+        let equip = Object::create(&player, TestBox::ClassName(), 1, 0).unwrap();
+        Object::model_map_mut(&equip, |e: &mut TestBox| e.set_name("equip".to_string()));
+        testing::item(&equip, 1, "c");
+        Tree {
+            _scene: scene,
+            player,
+            bag,
+            equip,
+        }
+    }
+
+    fn names(walk: impl Iterator<Item = ObjectPtr>) -> Vec<String> {
+        walk.map(|o| {
+            let object = o.borrow();
+            let index = object.get_attr_index("name").unwrap();
+            let name = object.game_model.borrow().get_value(index);
+            String::from_value(&name.unwrap()).unwrap()
+        })
+        .collect()
+    }
+
+    #[test]
+    fn test_children() {
+        let tree = tree();
+        assert_eq!(names(Object::children(&tree.bag)), ["b", "a"]);
+        assert_eq!(Object::children(&tree.player).count(), 2);
+        let leaf = tree.equip.borrow().child_at(1).unwrap();
+        assert_eq!(Object::children(&leaf).count(), 0);
+    }
+
+    #[test]
+    fn test_orders() {
+        let tree = tree();
+        assert_eq!(
+            names(Object::descendants(&tree.player, Order::DepthFirst)),
+            ["bag", "b", "a", "equip", "c"]
+        );
+        assert_eq!(
+            names(Object::descendants(&tree.player, Order::BreadthFirst)),
+            ["bag", "equip", "b", "a", "c"]
+        );
+        assert_eq!(
+            names(Object::descendants(&tree.bag, Order::DepthFirst)),
+            ["b", "a"]
+        );
+    }
+
+    #[test]
+    fn test_prune() {
+        let tree = tree();
+        let walk = Object::descendants(&tree.player, Order::DepthFirst)
+            .prune(|o| o.get_class_type() == ClassType::Container);
+        assert_eq!(names(walk), ["bag", "equip"]);
+    }
+
+    #[test]
+    fn test_destroy_while_walking() {
+        let tree = tree();
+        let mut visited = vec![];
+        for object in Object::descendants(&tree.player, Order::DepthFirst) {
+            visited.push(object.clone());
+            if Rc::ptr_eq(&object, &tree.bag) {
+                Object::destroy_self(&object);
+            }
+        }
+        // bag 的子对象不再访问
+        assert_eq!(visited.len(), 3);
+        assert!(Rc::ptr_eq(&visited[1], &tree.equip));
+
+        for child in Object::children(&tree.equip) {
+            tree.equip.borrow_mut().destroy_child(&child);
+        }
+        assert_eq!(Object::children(&tree.player).count(), 1);
+        assert_eq!(tree.equip.borrow().child_count(), 0);
+    }
+}
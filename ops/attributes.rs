@@ -1,10 +1,19 @@
 use bae::FromAttributes;
-use syn;
+use proc_macro2::TokenStream;
+use quote::quote;
 
 #[derive(Default, FromAttributes, Debug)]
 pub struct Attr {
     pub save: Option<()>,
     pub replicated: Option<()>,
+    /// 相同才能堆叠的属性
+    pub stack_key: Option<()>,
+    /// 堆叠数量
+    pub stack_count: Option<()>,
+    /// 建立索引，用于 `GameScene::query`
+    pub index: Option<()>,
+    /// 建立唯一索引，setter 返回 `Result`，拒绝其它对象已经使用的值
+    pub unique: Option<()>,
 }
 
 impl Attr {
@@ -15,4 +24,55 @@ impl Attr {
     pub fn should_replicate(&self) -> bool {
         self.replicated.is_some()
     }
+
+    /// 需要按 `Value` 读写，属性类型要实现 `AttrValue`
+    pub fn has_value(&self) -> bool {
+        self.save.is_some()
+            || self.replicated.is_some()
+            || self.stack_key.is_some()
+            || self.stack_count.is_some()
+            || self.index.is_some()
+            || self.unique.is_some()
+    }
+}
+
+/// `#[def_entity(...)]` 的参数
+#[derive(Default, FromAttributes, Debug)]
+pub struct Entity {
+    pub version: Option<syn::LitInt>,
+    pub class: Option<syn::Path>,
+    /// 实体实现了 `Lifecycle`
+    pub hooks: Option<()>,
+    /// 最大堆叠数量
+    pub stack: Option<syn::LitInt>,
+}
+
+impl Entity {
+    pub fn version(&self) -> syn::Result<u32> {
+        match &self.version {
+            Some(version) => version.base10_parse(),
+            None => Ok(0),
+        }
+    }
+
+    pub fn stack(&self) -> syn::Result<Option<u32>> {
+        self.stack
+            .as_ref()
+            .map(|max| max.base10_parse())
+            .transpose()
+    }
+
+    /// 内置的类别写成 `class = Item`，其它的按 `ClassType` 常量处理
+    pub fn class_type(&self) -> TokenStream {
+        const BUILTIN: &[&str] = &["None", "Scene", "Role", "Npc", "Item", "Aide", "Container"];
+        match &self.class {
+            Some(path) => match path.get_ident() {
+                Some(ident) if BUILTIN.contains(&ident.to_string().as_str()) => {
+                    quote! { re_object::object::ClassType::#ident }
+                }
+                _ => quote! { #path },
+            },
+            None => quote! { re_object::object::ClassType::None },
+        }
+    }
 }
@@ -2,17 +2,16 @@ mod attributes;
 mod object;
 
 use proc_macro::TokenStream;
-use proc_macro2::Ident;
 use quote::quote;
-use syn::{
-    parse::{self, Parser},
-    parse_macro_input, DeriveInput, ItemStruct,
-};
+use syn::{parse::Parser, parse_macro_input, DeriveInput, ItemStruct};
 
 #[proc_macro_attribute]
 pub fn def_entity(args: TokenStream, input: TokenStream) -> TokenStream {
     let mut item_struct = parse_macro_input!(input as ItemStruct);
-    let _ = parse_macro_input!(args as parse::Nothing);
+    let args = proc_macro2::TokenStream::from(args);
+    if !args.is_empty() {
+        item_struct.attrs.push(syn::parse_quote!(#[entity(#args)]));
+    }
     if let syn::Fields::Named(ref mut fields) = item_struct.fields {
         // 插入一个占位属性
         fields.named.insert(
@@ -23,7 +22,7 @@ pub fn def_entity(args: TokenStream, input: TokenStream) -> TokenStream {
         );
         let add_attr = vec![
             quote! {__model: re_object::game_model::Model},
-            quote! {__go: re_object::MutObjectPtr},
+            quote! {__changes: re_object::game_model::Changes},
         ];
         //let add_attr: Vec<proc_macro2::TokenStream> = Vec::new();
         for att in add_attr {
@@ -32,50 +31,27 @@ pub fn def_entity(args: TokenStream, input: TokenStream) -> TokenStream {
                 .push(syn::Field::parse_named.parse2(att).unwrap());
         }
     }
-    return quote! {
+    quote! {
         #[derive(Default, Debug, re_ops::Entity)]
         #[allow(dead_code)]
         #item_struct
     }
-    .into();
+    .into()
 }
 
-#[proc_macro_derive(Entity, attributes(attr))]
+#[proc_macro_derive(Entity, attributes(attr, entity))]
 pub fn entity_builder(input: TokenStream) -> TokenStream {
     let ast: DeriveInput = parse_macro_input!(input);
-    let mut attrs: Vec<Ident> = Vec::new();
-    let mut fn_attrs: Vec<proc_macro2::TokenStream> = Vec::new();
-    let mut save_attrs: Vec<Ident> = Vec::new();
-    let mut rep_attrs: Vec<Ident> = Vec::new();
-    let mut match_any_set: Vec<proc_macro2::TokenStream> = Vec::new();
-    let mut match_any_get: Vec<proc_macro2::TokenStream> = Vec::new();
-    let mut match_attr_set: Vec<proc_macro2::TokenStream> = Vec::new();
-    let mut match_attr_get: Vec<proc_macro2::TokenStream> = Vec::new();
-
-    let ident = object::parse_token(
-        ast,
-        &mut attrs,
-        &mut fn_attrs,
-        &mut save_attrs,
-        &mut rep_attrs,
-        &mut match_any_set,
-        &mut match_any_get,
-        &mut match_attr_set,
-        &mut match_attr_get,
-    );
-
-    let entity_token = object::make_entity(
-        &ident,
-        &attrs,
-        &fn_attrs,
-        &save_attrs,
-        &rep_attrs,
-        &match_any_set,
-        &match_any_get,
-        &mut match_attr_set,
-        &mut match_attr_get,
-    );
-    let object_token = object::make_object(&ident);
+    let mut tokens = object::EntityTokens::default();
+    if let Err(err) = object::parse_entity(&ast, &mut tokens) {
+        return err.to_compile_error().into();
+    }
+    let ident = object::parse_token(ast, &mut tokens);
+    if let Err(err) = object::check_stack(&ident, &tokens) {
+        return err.to_compile_error().into();
+    }
+    let entity_token = object::make_entity(&ident, &tokens);
+    let object_token = object::make_object(&ident, &tokens);
 
     let output = quote! {
         #entity_token
@@ -86,3 +62,9 @@ pub fn entity_builder(input: TokenStream) -> TokenStream {
     };
     output.into()
 }
+
+#[proc_macro_derive(AttrValue)]
+pub fn attr_value_builder(input: TokenStream) -> TokenStream {
+    let ast: DeriveInput = parse_macro_input!(input);
+    object::make_attr_value(ast).into()
+}
@@ -2,19 +2,58 @@ use proc_macro2::{Ident, TokenStream};
 use quote::{format_ident, quote};
 use syn::DeriveInput;
 
-use crate::attributes::Attr;
+use crate::attributes::{Attr, Entity};
 
-pub fn parse_token(
-    ast: DeriveInput,
-    attrs: &mut Vec<Ident>,
-    fn_attrs: &mut Vec<TokenStream>,
-    save_attrs: &mut Vec<Ident>,
-    rep_attrs: &mut Vec<Ident>,
-    match_any_set: &mut Vec<TokenStream>,
-    match_any_get: &mut Vec<TokenStream>,
-    match_attr_set: &mut Vec<TokenStream>,
-    match_attr_get: &mut Vec<TokenStream>,
-) -> Ident {
+#[derive(Default)]
+pub struct EntityTokens {
+    pub version: u32,
+    pub class_type: TokenStream,
+    pub hooks: bool,
+    pub stack: Option<u32>,
+    pub stack_keys: Vec<u32>,
+    pub stack_count: Option<u32>,
+    pub indexes: Vec<TokenStream>,
+    pub attrs: Vec<Ident>,
+    pub fn_attrs: Vec<TokenStream>,
+    pub save_attrs: Vec<Ident>,
+    pub rep_attrs: Vec<Ident>,
+    pub match_any_set: Vec<TokenStream>,
+    pub match_any_get: Vec<TokenStream>,
+    pub match_attr_set: Vec<TokenStream>,
+    pub match_attr_get: Vec<TokenStream>,
+    pub match_value_get: Vec<TokenStream>,
+    pub match_value_set: Vec<TokenStream>,
+}
+
+/// 堆叠参数和 `stack_count` 属性需要同时出现
+pub fn check_stack(ident: &Ident, tokens: &EntityTokens) -> syn::Result<()> {
+    match (tokens.stack, tokens.stack_count) {
+        (Some(0), _) => Err(syn::Error::new(
+            ident.span(),
+            "stack must be greater than 0",
+        )),
+        (Some(_), None) => Err(syn::Error::new(
+            ident.span(),
+            "stackable entity needs a #[attr(stack_count)] field",
+        )),
+        (None, Some(_)) => Err(syn::Error::new(
+            ident.span(),
+            "#[attr(stack_count)] needs #[def_entity(stack = N)]",
+        )),
+        _ => Ok(()),
+    }
+}
+
+pub fn parse_entity(ast: &DeriveInput, tokens: &mut EntityTokens) -> syn::Result<()> {
+    let entity = Entity::try_from_attributes(&ast.attrs)?.unwrap_or_default();
+    tokens.version = entity.version()?;
+    tokens.class_type = entity.class_type();
+    tokens.hooks = entity.hooks.is_some();
+    tokens.stack = entity.stack()?;
+    Ok(())
+}
+
+pub fn parse_token(ast: DeriveInput, tokens: &mut EntityTokens) -> Ident {
     let DeriveInput { ident, .. } = ast;
     if let syn::Data::Struct(syn::DataStruct { fields, .. }) = ast.data {
         let mut index: u32 = 0;
@@ -25,58 +64,128 @@ pub fn parse_token(
                 let get = format_ident!("get_{}", ident_field);
                 let set = format_ident!("set_{}", ident_field);
                 let set_any = format_ident!("set_{}_any", ident_field);
-                attrs.push(ident_field.clone());
-                let fp = quote! {
-                    pub fn #get<'a>(&'a self) -> &'a #ty{
-                        &self.#ident_field
+                tokens.attrs.push(ident_field.clone());
+                let store = quote! {
+                    if self.__changes.watched(stringify!(#ident_field)) {
+                        let old = std::mem::replace(&mut self.#ident_field, val);
+                        self.__changes.push_values(#index, old, self.#ident_field.clone());
+                    } else {
+                        self.#ident_field = val;
+                        self.__changes.push(#index);
                     }
-                    pub fn #set(&mut self, val:#ty) {
-                        if self.#ident_field == val {
-                            return;
+                };
+                // 唯一属性的 setter 拒绝其它对象已经使用的值
+                let unique = attr.unique.is_some();
+                let setter = if unique {
+                    quote! {
+                        pub fn #set(&mut self, val:#ty) -> Result<(), re_object::value::ValueError> {
+                            if self.#ident_field == val {
+                                return Ok(());
+                            }
+                            let value = re_object::value::AttrValue::to_value(&val);
+                            if self.__changes.is_taken(stringify!(#ident_field), &value) {
+                                return Err(re_object::value::ValueError::Duplicate(stringify!(#ident_field)));
+                            }
+                            #store
+                            Ok(())
+                        }
+                        pub fn #set_any(&mut self, val:&dyn std::any::Any) -> bool {
+                            match val.downcast_ref::<#ty>() {
+                                Some(v) => self.#set(v.clone()).is_ok(),
+                                None => false,
+                            }
                         }
-                        let old = std::mem::replace(&mut self.#ident_field, val);
-                        self.change_attr(#index, &old);
                     }
-                    pub fn #set_any(&mut self, val:&dyn std::any::Any) -> bool {
-                        match val.downcast_ref::<#ty>() {
-                            Some(v) => {
-                                self.#set(v.clone());
-                                true
+                } else {
+                    quote! {
+                        pub fn #set(&mut self, val:#ty) {
+                            if self.#ident_field == val {
+                                return;
+                            }
+                            #store
+                        }
+                        pub fn #set_any(&mut self, val:&dyn std::any::Any) -> bool {
+                            match val.downcast_ref::<#ty>() {
+                                Some(v) => {
+                                    self.#set(v.clone());
+                                    true
+                                }
+                                None => false,
                             }
-                            None => false,
                         }
                     }
                 };
+                let fp = quote! {
+                    pub fn #get<'a>(&'a self) -> &'a #ty{
+                        &self.#ident_field
+                    }
+                    #setter
+                };
 
-                fn_attrs.push(fp);
+                tokens.fn_attrs.push(fp);
 
                 if attr.should_save() {
-                    save_attrs.push(ident_field.clone());
+                    tokens.save_attrs.push(ident_field.clone());
                 }
                 if attr.should_replicate() {
-                    rep_attrs.push(ident_field.clone());
+                    tokens.rep_attrs.push(ident_field.clone());
+                }
+                if attr.stack_key.is_some() {
+                    tokens.stack_keys.push(index);
+                }
+                if attr.stack_count.is_some() {
+                    tokens.stack_count = Some(index);
+                }
+                if attr.index.is_some() || unique {
+                    tokens.indexes.push(quote! {
+                        re_object::query::IndexModel {
+                            attr: #index,
+                            unique: #unique,
+                        }
+                    });
                 }
 
-                match_any_set.push(quote! {
+                tokens.match_any_set.push(quote! {
                     #index => {
                         self.#set_any(v)
                     }
                 });
-                match_any_get.push(quote! {
+                tokens.match_any_get.push(quote! {
                     #index => {
                         Some(self.#get())
                     }
                 });
-                match_attr_set.push(quote! {
+                tokens.match_attr_set.push(quote! {
                     stringify!(#ident_field) => {
                         self.#set_any(v)
                     }
                 });
-                match_attr_get.push(quote! {
+                tokens.match_attr_get.push(quote! {
                     stringify!(#ident_field) => {
                         Some(self.#get())
                     }
                 });
+                // 其它属性没有 `Value` 读写，类型不需要实现 `AttrValue`
+                if attr.has_value() {
+                    tokens.match_value_get.push(quote! {
+                        #index => {
+                            Some(re_object::value::AttrValue::to_value(&self.#ident_field))
+                        }
+                    });
+                    let set_value = if unique {
+                        quote! { self.#set(re_object::value::AttrValue::from_value(v)?) }
+                    } else {
+                        quote! {
+                            self.#set(re_object::value::AttrValue::from_value(v)?);
+                            Ok(())
+                        }
+                    };
+                    tokens.match_value_set.push(quote! {
+                        #index => {
+                            #set_value
+                        }
+                    });
+                }
                 index += 1;
             }
         }
@@ -84,17 +193,36 @@ pub fn parse_token(
     ident
 }
 
-pub fn make_entity(
-    ident: &Ident,
-    attrs: &Vec<Ident>,
-    fn_attrs: &Vec<TokenStream>,
-    save_attrs: &Vec<Ident>,
-    rep_attrs: &Vec<Ident>,
-    match_any_set: &Vec<TokenStream>,
-    match_any_get: &Vec<TokenStream>,
-    match_attr_set: &mut Vec<TokenStream>,
-    match_attr_get: &mut Vec<TokenStream>,
-) -> TokenStream {
+pub fn make_entity(ident: &Ident, tokens: &EntityTokens) -> TokenStream {
+    let EntityTokens {
+        version,
+        class_type,
+        hooks: _,
+        stack,
+        stack_keys,
+        stack_count,
+        indexes,
+        attrs,
+        fn_attrs,
+        save_attrs,
+        rep_attrs,
+        match_any_set,
+        match_any_get,
+        match_attr_set,
+        match_attr_get,
+        match_value_get,
+        match_value_set,
+    } = tokens;
+    let stack = match (stack, stack_count) {
+        (Some(max), Some(count)) => quote! {
+            d.__model.stack = Some(re_object::stack::StackModel {
+                max: #max,
+                keys: vec![#(#stack_keys),*],
+                count: #count,
+            });
+        },
+        _ => quote! {},
+    };
     quote! {
         impl #ident {
             pub fn new() -> Self {
@@ -103,16 +231,15 @@ pub fn make_entity(
                 let saves:Vec<&'static str> = vec![ #(stringify!(#save_attrs)),* ];
                 let reps:Vec<&'static str> = vec![ #(stringify!(#rep_attrs)),* ];
                 d.__model = re_object::game_model::Model::new(stringify!(#ident), attrs, saves, reps);
+                d.__model.version = #version;
+                d.__model.class_type = #class_type;
+                d.__model.indexes = vec![#(#indexes),*];
+                #stack
                 d
             }
             pub fn ClassName() -> &'static str {
                 stringify!(#ident)
             }
-            pub fn change_attr(&mut self, index:u32, old:&dyn std::any::Any) {
-                unsafe{
-                    (*self.__go.0).change_attr(index, old);
-                }
-            }
             pub fn set_attr_by_index(&mut self, att: u32, v :&dyn std::any::Any) -> bool {
                 match att {
                     #(#match_any_set) *
@@ -137,6 +264,18 @@ pub fn make_entity(
                     _ => None
                 }
             }
+            pub fn get_value_by_index(&self, att: u32) -> Option<re_object::value::Value> {
+                match att {
+                    #(#match_value_get) *
+                    _ => None
+                }
+            }
+            pub fn set_value_by_index(&mut self, att: u32, v: &re_object::value::Value) -> Result<(), re_object::value::ValueError> {
+                match att {
+                    #(#match_value_set) *
+                    _ => Err(re_object::value::ValueError::UnknownAttr(att))
+                }
+            }
             #(#fn_attrs) *
         }
         /*
@@ -156,14 +295,23 @@ pub fn make_entity(
     }
 }
 
-pub fn make_object(ident: &Ident) -> TokenStream {
+pub fn make_object(ident: &Ident, tokens: &EntityTokens) -> TokenStream {
+    let lifecycle = if tokens.hooks {
+        quote! {
+            fn lifecycle(&mut self) -> Option<&mut dyn re_object::lifecycle::Lifecycle> {
+                Some(self)
+            }
+        }
+    } else {
+        quote! {}
+    };
     quote! {
         impl re_object::game_model::GameModel for #ident {
             fn get_model(&self) -> re_object::game_model::Model {
                 self.__model.clone()
             }
-            fn set_gameobj(&mut self, go: *mut re_object::object::Object){
-                self.__go=re_object::MutObjectPtr(go);
+            fn changes(&mut self) -> &mut re_object::game_model::Changes {
+                &mut self.__changes
             }
             fn get_attr_by_name<'a>(&'a self, attr: &str) -> Option<&'a dyn std::any::Any> {
                 self.get_attr(attr)
@@ -177,12 +325,55 @@ pub fn make_object(ident: &Ident) -> TokenStream {
             fn set_attr_by_index(&mut self, index: u32, val: &dyn std::any::Any) -> bool {
                 self.set_attr_by_index(index, val)
             }
+            fn get_value(&self, index: u32) -> Option<re_object::value::Value> {
+                self.get_value_by_index(index)
+            }
+            fn set_value(&mut self, index: u32, value: &re_object::value::Value) -> Result<(), re_object::value::ValueError> {
+                self.set_value_by_index(index, value)
+            }
             fn get_any<'a>(&'a self) -> &'a dyn std::any::Any {
                 self
             }
             fn get_mut_any<'a>(&'a mut self) -> &'a mut dyn std::any::Any {
                 self
             }
+            #lifecycle
+        }
+    }
+}
+
+/// 结构体按字段名转成 `Value::Map`
+pub fn make_attr_value(ast: DeriveInput) -> TokenStream {
+    let DeriveInput { ident, data, .. } = ast;
+    let fields: Vec<Ident> = match data {
+        syn::Data::Struct(syn::DataStruct {
+            fields: syn::Fields::Named(fields),
+            ..
+        }) => fields
+            .named
+            .into_iter()
+            .map(|field| field.ident.unwrap())
+            .collect(),
+        _ => {
+            return syn::Error::new(
+                ident.span(),
+                "AttrValue only supports structs with named fields",
+            )
+            .to_compile_error()
+        }
+    };
+    quote! {
+        impl re_object::value::AttrValue for #ident {
+            fn to_value(&self) -> re_object::value::Value {
+                re_object::value::Value::Map(vec![
+                    #((stringify!(#fields).to_string(), re_object::value::AttrValue::to_value(&self.#fields))),*
+                ])
+            }
+            fn from_value(value: &re_object::value::Value) -> Result<Self, re_object::value::ValueError> {
+                Ok(Self {
+                    #(#fields: re_object::value::AttrValue::from_value(value.field(stringify!(#fields))?)?),*
+                })
+            }
         }
     }
 }
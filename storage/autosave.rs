@@ -0,0 +1,220 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    rc::{Rc, Weak},
+    time::Duration,
+};
+
+use re_object::{game_object::GameObject, object::Object, walk::Order, ObjectPtr, WeakObjectPtr};
+use tokio::sync::Notify;
+use tracing::{debug, warn};
+
+use crate::AsyncStorage;
+
+/// 按根对象定时存盘，例如每个角色一个根
+///
+/// 根对象或任意子对象为脏时，整棵子树作为一次写入提交。写入成功后才清除脏标记，
+/// 存盘期间又被修改的对象保持为脏，下次继续保存；写入失败按退避时间重试。
+///
+/// 提交的写入通过 `spawn_local` 执行，需要在游戏线程的 `LocalSet` 中使用。
+#[derive(Clone)]
+pub struct Autosave {
+    inner: Rc<RefCell<Inner>>,
+    done: Rc<Notify>,
+}
+
+struct Inner {
+    storage: AsyncStorage,
+    interval: Duration,
+    retry_delay: Duration,
+    now: Duration,
+    roots: HashMap<String, Root>,
+}
+
+struct Root {
+    object: WeakObjectPtr,
+    next: Duration,
+    failures: u32,
+    in_flight: bool,
+}
+
+impl Autosave {
+    pub fn new(storage: AsyncStorage, interval: Duration) -> Self {
+        Self {
+            inner: Rc::new(RefCell::new(Inner {
+                storage,
+                interval,
+                retry_delay: Duration::from_secs(1),
+                now: Duration::ZERO,
+                roots: HashMap::new(),
+            })),
+            done: Rc::new(Notify::new()),
+        }
+    }
+
+    /// 第一次失败后的重试间隔，之后每次翻倍，最长为存盘间隔
+    pub fn set_retry_delay(&self, delay: Duration) {
+        self.inner.borrow_mut().retry_delay = delay;
+    }
+
+    pub fn add_root(&self, key: impl Into<String>, object: &ObjectPtr) {
+        let mut inner = self.inner.borrow_mut();
+        let next = inner.now + inner.interval;
+        inner.roots.insert(
+            key.into(),
+            Root {
+                object: Rc::downgrade(object),
+                next,
+                failures: 0,
+                in_flight: false,
+            },
+        );
+    }
+
+    /// 不再自动保存，移除前需要保存的话先调用 `flush_all`
+    pub fn remove_root(&self, key: &str) -> bool {
+        self.inner.borrow_mut().roots.remove(key).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.borrow().roots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.borrow().roots.is_empty()
+    }
+
+    /// 正在写入的根对象数
+    pub fn in_flight(&self) -> usize {
+        self.inner
+            .borrow()
+            .roots
+            .values()
+            .filter(|root| root.in_flight)
+            .count()
+    }
+
+    /// 每帧调用，`now` 为逻辑时间，提交到期的脏对象
+    pub fn update(&self, now: Duration) {
+        let due: Vec<String> = {
+            let mut inner = self.inner.borrow_mut();
+            inner.now = now;
+            inner
+                .roots
+                .iter()
+                .filter(|(_, root)| !root.in_flight && root.next <= now)
+                .map(|(key, _)| key.clone())
+                .collect()
+        };
+        for key in due {
+            self.submit(&key);
+        }
+    }
+
+    /// 立即保存所有脏的根对象，等待包括已提交的所有写入完成，返回失败的数量
+    pub async fn flush_all(&self) -> usize {
+        self.wait_in_flight().await;
+        let keys: Vec<String> = self.inner.borrow().roots.keys().cloned().collect();
+        for key in &keys {
+            self.submit(key);
+        }
+        self.wait_in_flight().await;
+        self.inner
+            .borrow()
+            .roots
+            .values()
+            .filter(|root| root.failures > 0)
+            .count()
+    }
+
+    async fn wait_in_flight(&self) {
+        loop {
+            let notified = self.done.notified();
+            if self.in_flight() == 0 {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    fn submit(&self, key: &str) {
+        let mut inner = self.inner.borrow_mut();
+        let now = inner.now;
+        let interval = inner.interval;
+        let storage = inner.storage.clone();
+        let Some(root) = inner.roots.get_mut(key) else {
+            return;
+        };
+        let Some(object) = root.object.upgrade() else {
+            warn!("autosave root {} dropped", key);
+            inner.roots.remove(key);
+            return;
+        };
+        root.next = now + interval;
+        let mut versions = Vec::new();
+        collect_dirty(&object, &mut versions);
+        if versions.is_empty() {
+            return;
+        }
+        root.in_flight = true;
+        drop(inner);
+
+        debug!("autosave {}, {} dirty objects", key, versions.len());
+        let key = key.to_string();
+        let save = storage.save_object(key.clone(), &object);
+        let this = self.clone();
+        tokio::task::spawn_local(async move {
+            let res = save.await;
+            this.complete(&key, versions, res);
+        });
+    }
+
+    fn complete(&self, key: &str, versions: Vec<(WeakObjectPtr, u64)>, res: crate::Result<()>) {
+        let mut inner = self.inner.borrow_mut();
+        let now = inner.now;
+        let interval = inner.interval;
+        let retry_delay = inner.retry_delay;
+        if let Some(root) = inner.roots.get_mut(key) {
+            root.in_flight = false;
+            match &res {
+                Ok(()) => root.failures = 0,
+                Err(err) => {
+                    let delay = retry_delay
+                        .saturating_mul(1 << root.failures.min(16))
+                        .min(interval);
+                    root.failures += 1;
+                    root.next = now + delay;
+                    warn!(
+                        "autosave {} failed {} times: {}, retry in {:?}",
+                        key, root.failures, err, delay
+                    );
+                }
+            }
+        }
+        drop(inner);
+        if res.is_ok() {
+            for (object, version) in versions {
+                let Some(object) = Weak::upgrade(&object) else {
+                    continue;
+                };
+                let mut object = object.borrow_mut();
+                if object.save_version() == version {
+                    object.clear_dirty();
+                }
+            }
+        }
+        self.done.notify_waiters();
+    }
+}
+
+fn collect_dirty(object: &ObjectPtr, out: &mut Vec<(WeakObjectPtr, u64)>) {
+    let walk =
+        std::iter::once(object.clone()).chain(Object::descendants(object, Order::DepthFirst));
+    for object in walk {
+        Object::flush_changes(&object);
+        let object_ref = object.borrow();
+        if object_ref.dirty() {
+            out.push((Rc::downgrade(&object), object_ref.save_version()));
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use re_object::record::ObjectRecord;
+
+use crate::{Result, Storage};
+
+const EXT: &str = "rec";
+
+// 临时文件名的序号，同时保存同一个 key 时不会写到同一个临时文件
+static TMP_SERIAL: AtomicU64 = AtomicU64::new(0);
+
+/// 每个 key 一个文件的目录存档
+///
+/// 文件名是转义后的 key，字母、数字和 `-_.` 以外的字节写成 `%XX`。
+/// 写入时先写临时文件再重命名，中途崩溃不会留下半个存档。临时文件名带进程号和序号，
+/// 多个线程或进程同时保存同一个 key 时互不干扰。
+pub struct FileStorage {
+    dir: PathBuf,
+}
+
+impl FileStorage {
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.{}", escape(key), EXT))
+    }
+}
+
+impl Storage for FileStorage {
+    fn save(&self, key: &str, record: &ObjectRecord) -> Result<()> {
+        let path = self.path(key);
+        let serial = TMP_SERIAL.fetch_add(1, Ordering::Relaxed);
+        let tmp = path.with_extension(format!("{}.{}.tmp", std::process::id(), serial));
+        let res = fs::File::create(&tmp).and_then(|mut file| {
+            file.write_all(&record.encode())?;
+            file.sync_all()
+        });
+        if let Err(err) = res.and_then(|()| fs::rename(&tmp, &path)) {
+            _ = fs::remove_file(&tmp);
+            return Err(err.into());
+        }
+        Ok(())
+    }
+
+    fn load(&self, key: &str) -> Result<Option<ObjectRecord>> {
+        match fs::read(self.path(key)) {
+            Ok(data) => Ok(Some(ObjectRecord::decode(&data)?)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn remove(&self, key: &str) -> Result<bool> {
+        match fs::remove_file(self.path(key)) {
+            Ok(()) => Ok(true),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn keys(&self) -> Result<Vec<String>> {
+        let mut keys = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some(EXT) {
+                continue;
+            }
+            if let Some(key) = path.file_stem().and_then(|s| s.to_str()).and_then(unescape) {
+                keys.push(key);
+            }
+        }
+        Ok(keys)
+    }
+}
+
+fn escape(key: &str) -> String {
+    let mut name = String::with_capacity(key.len());
+    for b in key.bytes() {
+        match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' | b'.' => name.push(b as char),
+            _ => name.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    name
+}
+
+fn unescape(name: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(name.len());
+    let mut iter = name.bytes();
+    while let Some(b) = iter.next() {
+        if b == b'%' {
+            let hex = [iter.next()?, iter.next()?];
+            bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            bytes.push(b);
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
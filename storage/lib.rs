@@ -0,0 +1,267 @@
+use std::{future::Future, sync::Arc};
+
+use re_object::{record::ObjectRecord, ObjectPtr};
+
+pub mod autosave;
+pub mod file;
+pub mod sqlite;
+
+pub use autosave::Autosave;
+pub use file::FileStorage;
+pub use sqlite::SqliteStorage;
+
+pub type Error = Box<dyn std::error::Error + Send + Sync>;
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// 存档后端，按 key 保存对象子树，例如角色 id
+///
+/// 方法都是阻塞的，游戏线程通过 `AsyncStorage` 调用。
+pub trait Storage: Send + Sync + 'static {
+    fn save(&self, key: &str, record: &ObjectRecord) -> Result<()>;
+    fn load(&self, key: &str) -> Result<Option<ObjectRecord>>;
+    /// 返回 key 是否存在
+    fn remove(&self, key: &str) -> Result<bool>;
+    fn keys(&self) -> Result<Vec<String>>;
+}
+
+/// 在 tokio 的阻塞线程池中执行存档操作
+#[derive(Clone)]
+pub struct AsyncStorage {
+    inner: Arc<dyn Storage>,
+}
+
+impl AsyncStorage {
+    pub fn new(storage: impl Storage) -> Self {
+        Self {
+            inner: Arc::new(storage),
+        }
+    }
+
+    pub async fn save(&self, key: impl Into<String>, record: ObjectRecord) -> Result<()> {
+        let inner = self.inner.clone();
+        let key = key.into();
+        tokio::task::spawn_blocking(move || inner.save(&key, &record)).await?
+    }
+
+    /// 在当前线程上保存对象数据，返回的 future 只做 I/O
+    pub fn save_object(
+        &self,
+        key: impl Into<String>,
+        object: &ObjectPtr,
+    ) -> impl Future<Output = Result<()>> {
+        let record = ObjectRecord::capture(object);
+        let this = self.clone();
+        let key = key.into();
+        async move { this.save(key, record).await }
+    }
+
+    pub async fn load(&self, key: impl Into<String>) -> Result<Option<ObjectRecord>> {
+        let inner = self.inner.clone();
+        let key = key.into();
+        tokio::task::spawn_blocking(move || inner.load(&key)).await?
+    }
+
+    pub async fn remove(&self, key: impl Into<String>) -> Result<bool> {
+        let inner = self.inner.clone();
+        let key = key.into();
+        tokio::task::spawn_blocking(move || inner.remove(&key)).await?
+    }
+
+    pub async fn keys(&self) -> Result<Vec<String>> {
+        let inner = self.inner.clone();
+        tokio::task::spawn_blocking(move || inner.keys()).await?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        rc::Rc,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use re_object::{
+        game_object::GameObject, game_scene::GameScene, object::Object, record::ObjectRecord,
+        registry::Registry, value::Value,
+    };
+    use re_ops::def_entity;
+
+    use crate::{AsyncStorage, Autosave, FileStorage, SqliteStorage, Storage};
+
+    #[def_entity]
+    struct SaveScene {}
+
+    #[def_entity]
+    struct SaveRole {
+        #[attr(save)]
+        level: i32,
+        #[attr(save)]
+        name: String,
+    }
+
+    /// 可以模拟写入失败的存档
+    struct FlakyStorage {
+        inner: SqliteStorage,
+        fail: Arc<AtomicBool>,
+    }
+
+    impl Storage for FlakyStorage {
+        fn save(&self, key: &str, record: &ObjectRecord) -> crate::Result<()> {
+            if self.fail.load(Ordering::SeqCst) {
+                return Err("disk full".into());
+            }
+            self.inner.save(key, record)
+        }
+
+        fn load(&self, key: &str) -> crate::Result<Option<ObjectRecord>> {
+            self.inner.load(key)
+        }
+
+        fn remove(&self, key: &str) -> crate::Result<bool> {
+            self.inner.remove(key)
+        }
+
+        fn keys(&self) -> crate::Result<Vec<String>> {
+            self.inner.keys()
+        }
+    }
+
+    fn record() -> ObjectRecord {
+        ObjectRecord {
+            class_name: "Player".to_string(),
+            version: 0,
+            cap: 0,
+            pos: 1,
+            attrs: vec![("name".to_string(), Value::Str("hero".to_string()))],
+            children: vec![ObjectRecord {
+                class_name: "Bag".to_string(),
+                version: 0,
+                cap: 8,
+                pos: 1,
+                attrs: vec![("size".to_string(), Value::UInt(8))],
+                children: Vec::new(),
+            }],
+        }
+    }
+
+    fn check(storage: &dyn Storage) {
+        let record = record();
+        assert_eq!(storage.load("role/1").unwrap(), None);
+        storage.save("role/1", &record).unwrap();
+        storage.save("role 2", &record).unwrap();
+        assert_eq!(storage.load("role/1").unwrap(), Some(record.clone()));
+
+        let mut changed = record;
+        changed.attrs.clear();
+        storage.save("role/1", &changed).unwrap();
+        assert_eq!(storage.load("role/1").unwrap(), Some(changed));
+
+        let mut keys = storage.keys().unwrap();
+        keys.sort();
+        assert_eq!(keys, vec!["role 2", "role/1"]);
+        assert!(storage.remove("role 2").unwrap());
+        assert!(!storage.remove("role 2").unwrap());
+    }
+
+    #[test]
+    fn test_backends() {
+        check(&SqliteStorage::open_in_memory().unwrap());
+
+        let dir = std::env::temp_dir().join(format!("re_storage_{}", std::process::id()));
+        check(&FileStorage::open(&dir).unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let storage = AsyncStorage::new(SqliteStorage::open_in_memory().unwrap());
+        rt.block_on(async {
+            storage.save("1", record()).await.unwrap();
+            assert_eq!(storage.load("1").await.unwrap(), Some(record()));
+        });
+    }
+
+    #[test]
+    fn test_file_concurrent_save() {
+        let dir = std::env::temp_dir().join(format!("re_storage_tmp_{}", std::process::id()));
+        let storage = Arc::new(FileStorage::open(&dir).unwrap());
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let storage = storage.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..20 {
+                        storage.save("role/1", &record()).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(storage.load("role/1").unwrap(), Some(record()));
+        // 没有留下临时文件
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_autosave() {
+        let registry = Rc::new(Registry::init());
+        let scene = GameScene::new(SaveScene::ClassName(), registry).unwrap();
+        let role = scene.create_in_scene(SaveRole::ClassName(), 0).unwrap();
+        let fail = Arc::new(AtomicBool::new(true));
+        let storage = AsyncStorage::new(FlakyStorage {
+            inner: SqliteStorage::open_in_memory().unwrap(),
+            fail: fail.clone(),
+        });
+        let autosave = Autosave::new(storage.clone(), Duration::from_secs(10));
+        autosave.set_retry_delay(Duration::from_secs(1));
+        autosave.add_root("role", &role);
+        let set_level = |level| Object::model_map_mut(&role, |r: &mut SaveRole| r.set_level(level));
+        let secs = Duration::from_secs;
+
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let local = tokio::task::LocalSet::new();
+        local.block_on(&rt, async {
+            // 没到时间不保存
+            set_level(1);
+            autosave.update(secs(5));
+            assert_eq!(autosave.in_flight(), 0);
+
+            // 写入失败，期间的修改也要保留
+            autosave.update(secs(10));
+            assert_eq!(autosave.in_flight(), 1);
+            set_level(2);
+            while autosave.in_flight() > 0 {
+                tokio::task::yield_now().await;
+            }
+            assert!(role.borrow().dirty());
+            autosave.update(secs(10) + Duration::from_millis(500));
+            assert_eq!(autosave.in_flight(), 0);
+
+            // 重试成功，提交后又修改的对象保持为脏
+            fail.store(false, Ordering::SeqCst);
+            autosave.update(secs(11));
+            assert_eq!(autosave.in_flight(), 1);
+            set_level(3);
+            autosave.flush_all().await;
+            assert!(!role.borrow().dirty());
+            let record = storage.load("role").await.unwrap().unwrap();
+            assert_eq!(record.attrs[0], ("level".to_string(), Value::Int(3)));
+
+            // 没有修改时不提交
+            autosave.update(secs(100));
+            assert_eq!(autosave.in_flight(), 0);
+            fail.store(true, Ordering::SeqCst);
+            set_level(4);
+            assert_eq!(autosave.flush_all().await, 1);
+            assert!(role.borrow().dirty());
+        });
+    }
+}
@@ -0,0 +1,91 @@
+use std::{
+    path::Path,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use re_object::record::ObjectRecord;
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::{Result, Storage};
+
+/// 单文件的嵌入式 SQLite 存档
+pub struct SqliteStorage {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStorage {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        Self::init(conn)
+    }
+
+    pub fn open_in_memory() -> Result<Self> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> Result<Self> {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS objects (
+                key TEXT PRIMARY KEY,
+                class_name TEXT NOT NULL,
+                data BLOB NOT NULL,
+                updated_at INTEGER NOT NULL
+            )",
+        )?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    fn conn(&self) -> std::sync::MutexGuard<'_, Connection> {
+        // 持有锁的线程 panic 不影响连接本身
+        self.conn.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn save(&self, key: &str, record: &ObjectRecord) -> Result<()> {
+        let data = record.encode();
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+        self.conn().execute(
+            "INSERT INTO objects (key, class_name, data, updated_at) VALUES (?1, ?2, ?3, ?4)
+            ON CONFLICT(key) DO UPDATE SET
+                class_name = excluded.class_name,
+                data = excluded.data,
+                updated_at = excluded.updated_at",
+            params![key, record.class_name, data, now],
+        )?;
+        Ok(())
+    }
+
+    fn load(&self, key: &str) -> Result<Option<ObjectRecord>> {
+        let data: Option<Vec<u8>> = self
+            .conn()
+            .query_row("SELECT data FROM objects WHERE key = ?1", [key], |row| {
+                row.get(0)
+            })
+            .optional()?;
+        match data {
+            Some(data) => Ok(Some(ObjectRecord::decode(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn remove(&self, key: &str) -> Result<bool> {
+        let n = self
+            .conn()
+            .execute("DELETE FROM objects WHERE key = ?1", [key])?;
+        Ok(n > 0)
+    }
+
+    fn keys(&self) -> Result<Vec<String>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare("SELECT key FROM objects")?;
+        let keys = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        Ok(keys)
+    }
+}